@@ -0,0 +1,150 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use candle_core::{Device, DType, IndexOp, Tensor};
+use candle_transformers::models::{
+    bert::{self, BertModel},
+    modernbert::{self, ModernBert},
+    xlm_roberta::{self, XLMRobertaModel},
+};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+use crate::{
+    error::EmbeddingError,
+    EmbeddingMethod,
+    average_pool,
+    encode_batch,
+    mmap_var_builder,
+    normalize,
+    remap_prefix,
+    model2vec::StaticModel,
+    manifest::{
+        Architecture,
+        MANIFEST_FILE,
+        ModelManifest,
+        Pooling,
+    },
+};
+
+/// transformer of the custom model
+enum Backbone {
+    Bert(BertModel),
+    XlmRoberta(XLMRobertaModel),
+    ModernBert(ModernBert),
+    Static(StaticModel),
+}
+
+/// embedding model described by snippets_model.json
+pub struct CustomModel {
+    pub(crate) manifest: ModelManifest,
+    model:               Backbone,
+    tokenizer:           Option<Tokenizer>, // None for static model, it has its own tokenizer
+    device:              Device,
+}
+
+impl CustomModel {
+    /// Loads the model and tokenizer from local, architecture and pooling are given by the manifest
+    pub fn load(manifest: &ModelManifest, model_file: &Path, config_file: &Path, tokenizer_file: &Path, device: Device, dtype: DType) -> Result<Self, EmbeddingError> {
+        // static model has its own loading, no transformer
+        if let Architecture::Static = manifest.architecture {
+            let model = Backbone::Static(StaticModel::new(model_file, config_file, tokenizer_file, Device::Cpu, dtype)?); // lookup is faster on cpu
+            return Ok(Self { manifest: manifest.clone(), model, tokenizer: None, device: Device::Cpu })
+        }
+
+        let config = read_to_string(config_file)?;
+
+        // Load model weights
+        let weights = mmap_var_builder(model_file, dtype, &device)?;
+        let vb = match &manifest.weight_prefix {
+            Some(p) => weights.pp(p),
+            None => weights.clone(),
+        };
+
+        // init model
+        let (model, max_position_embeddings) = match manifest.architecture {
+            Architecture::Bert => {
+                let config: bert::Config = serde_json::from_str(&config).map_err(|e| EmbeddingError::JsonFromStrError{error: e})?;
+                (Backbone::Bert(BertModel::load(vb, &config)?), config.max_position_embeddings)
+            },
+            Architecture::XlmRoberta => {
+                let config: xlm_roberta::Config = serde_json::from_str(&config).map_err(|e| EmbeddingError::JsonFromStrError{error: e})?;
+                (Backbone::XlmRoberta(XLMRobertaModel::new(&config, vb)?), config.max_position_embeddings)
+            },
+            Architecture::ModernBert => {
+                let config: modernbert::Config = serde_json::from_str(&config).map_err(|e| EmbeddingError::JsonFromStrError{error: e})?;
+                // ModernBert expects "model." prefix, which is missing in most sentence-transformer exports
+                let vb = if vb.contains_tensor("model.embeddings.tok_embeddings.weight") {
+                    vb
+                } else {
+                    let prefix = manifest.weight_prefix.as_ref().map(|p| format!("{}.", p)).unwrap_or_default();
+                    remap_prefix(weights, "model.", &prefix)
+                };
+                (Backbone::ModernBert(ModernBert::load(vb, &config)?), config.max_position_embeddings)
+            },
+            Architecture::Static => unreachable!(),
+        };
+
+        // Load model tokenizer
+        let mut tokenizer = Tokenizer::from_file(tokenizer_file).map_err(|e| EmbeddingError::TokenizerFromFileError{error: e})?;
+
+        // tokenizer truncation
+        let truncation_params = TruncationParams {
+            max_length: manifest.max_length.unwrap_or(max_position_embeddings),
+            ..Default::default()
+        };
+        let _ = tokenizer.with_truncation(Some(truncation_params));
+
+        // tokenizer padding
+        let padding_params = PaddingParams {
+            strategy: tokenizers::PaddingStrategy::BatchLongest,
+            ..Default::default()
+        };
+        tokenizer.with_padding(Some(padding_params));
+
+        Ok(Self { manifest: manifest.clone(), model, tokenizer: Some(tokenizer), device })
+    }
+}
+
+impl EmbeddingMethod for CustomModel {
+    /// Loads the model and tokenizer from local, read snippets_model.json in the model folder
+    fn new(model_file: &Path, config_file: &Path, tokenizer_file: &Path, device: Device, dtype: DType) -> Result<Self, EmbeddingError> {
+        let model_dir = model_file.parent().unwrap_or(Path::new("."));
+        match ModelManifest::read(model_dir)? {
+            Some(manifest) => Self::load(&manifest, model_file, config_file, tokenizer_file, device, dtype),
+            None => Err(EmbeddingError::FileNotExistError{file: model_dir.join(MANIFEST_FILE).display().to_string()}),
+        }
+    }
+
+    /// Generates embeddings for a batch of sentences
+    fn get_embeddings(&self, sentences: &[&str]) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        let tokenizer = match (&self.model, &self.tokenizer) {
+            (Backbone::Static(model), _) => return model.get_embeddings(sentences),
+            (_, Some(tokenizer))         => tokenizer,
+            (_, None)                    => return Err(EmbeddingError::ParaError{para: "tokenizer not loaded".to_string()}),
+        };
+
+        // encode the given inputs, padding to the longest sentence
+        let (token_ids, token_type_ids, attention_mask) = encode_batch(tokenizer, sentences, &self.device)?;
+
+        // forward pass, padding tokens are masked
+        let last_hidden_state = match &self.model {
+            Backbone::Bert(model)       => model.forward(&token_ids, &token_type_ids, Some(&attention_mask))?,
+            Backbone::XlmRoberta(model) => model.forward(&token_ids, &attention_mask, &token_type_ids, None, None, None)?,
+            Backbone::ModernBert(model) => model.forward(&token_ids, &attention_mask)?,
+            Backbone::Static(_)         => unreachable!(),
+        }.to_dtype(DType::F32)?;
+
+        // pooling, padding is on the right, so the first token is never a padding token
+        let embeddings: Tensor = match self.manifest.pooling {
+            Pooling::Cls  => last_hidden_state.i((.., 0))?,
+            Pooling::Mean => average_pool(&last_hidden_state, &attention_mask)?,
+        };
+
+        // L2 normalization, convert Tensor [batch, dim] to Vec<Vec<f32>>
+        if self.manifest.normalize {
+            normalize(&embeddings)
+        } else {
+            Ok(embeddings.to_vec2::<f32>()?)
+        }
+    }
+}
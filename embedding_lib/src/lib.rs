@@ -1,17 +1,26 @@
+use std::env;
+use std::fs::read_to_string;
 use std::path::{Path, PathBuf};
 
 #[cfg(feature = "embedding")]
 use candle_core::{
     Device,
+    DType,
     Tensor,
     utils::{
         cuda_is_available,
         metal_is_available,
     },
 };
+#[cfg(feature = "embedding")]
+use candle_nn::VarBuilder;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "embedding")]
+use tokenizers::Tokenizer;
 
 pub mod error;
+pub mod manifest;
+pub mod reranker;
 #[cfg(feature = "embedding")]
 pub mod granite_english_r2;
 #[cfg(feature = "embedding")]
@@ -26,8 +35,13 @@ pub mod mxbai_embed_v1;
 pub mod e5_base_v2;
 #[cfg(feature = "embedding")]
 pub mod multilingual_e5_small;
+#[cfg(feature = "embedding")]
+pub mod model2vec;
+#[cfg(feature = "embedding")]
+pub mod custom;
 
 use error::EmbeddingError;
+use manifest::{Architecture, ModelManifest};
 #[cfg(feature = "embedding")]
 use granite_english_r2::GraniteEnglishR2Model;
 #[cfg(feature = "embedding")]
@@ -42,6 +56,13 @@ use mxbai_embed_v1::MxbaiEmbedV1Model;
 use e5_base_v2::E5BaseV2Model;
 #[cfg(feature = "embedding")]
 use multilingual_e5_small::MultilingualE5SmallModel;
+#[cfg(feature = "embedding")]
+use model2vec::StaticModel;
+#[cfg(feature = "embedding")]
+use custom::CustomModel;
+
+/// (model.safetensors, config.json, tokenizer.json)
+pub type ModelFiles = (PathBuf, PathBuf, PathBuf);
 
 /// model path name
 #[derive(Clone, Deserialize, Serialize, Hash, PartialEq, Eq)]
@@ -57,10 +78,30 @@ pub enum Model {
     MxbaiEmbedXsmallV1,
     E5BaseV2,
     MultilingualE5Small,
+    PotionBase8m,
+    PotionMultilingual128m,
+    Custom(String), // model folder with snippets_model.json, value: model name
 }
 
+/// organization of each supported model on Hugging Face, same order as MODEL_NAME
+const MODEL_ORG: &[&str;13] = &[
+    "ibm-granite",
+    "ibm-granite",
+    "ibm-granite",
+    "ibm-granite",
+    "sentence-transformers",
+    "sentence-transformers",
+    "sentence-transformers",
+    "mixedbread-ai",
+    "mixedbread-ai",
+    "intfloat",
+    "intfloat",
+    "minishlab",
+    "minishlab",
+];
+
 /// all supported model name
-const MODEL_NAME: &[&str;11] = &[
+const MODEL_NAME: &[&str;13] = &[
     "granite-embedding-small-english-r2", // https://huggingface.co/ibm-granite/granite-embedding-small-english-r2
     "granite-embedding-english-r2", // https://huggingface.co/ibm-granite/granite-embedding-english-r2
     "granite-embedding-107m-multilingual", // https://huggingface.co/ibm-granite/granite-embedding-107m-multilingual
@@ -72,6 +113,8 @@ const MODEL_NAME: &[&str;11] = &[
     "mxbai-embed-xsmall-v1", // https://hf-mirror.com/mixedbread-ai/mxbai-embed-xsmall-v1
     "e5-base-v2", // https://huggingface.co/intfloat/e5-base-v2
     "multilingual-e5-small", // https://huggingface.co/intfloat/multilingual-e5-small
+    "potion-base-8M", // https://huggingface.co/minishlab/potion-base-8M
+    "potion-multilingual-128M", // https://huggingface.co/minishlab/potion-multilingual-128M
 ];
 
 impl Model {
@@ -89,13 +132,83 @@ impl Model {
             9  => Ok(Self::MxbaiEmbedXsmallV1),
             10 => Ok(Self::E5BaseV2),
             11 => Ok(Self::MultilingualE5Small),
-            _  => return Err(EmbeddingError::ParaError{para: format!("-m not support this model: {}", id)}),
+            12 => Ok(Self::PotionBase8m),
+            13 => Ok(Self::PotionMultilingual128m),
+            _  => Err(EmbeddingError::ParaError{para: format!("-m not support this model: {}", id)}),
         }
     }
 
-    /// get model type and model path
-    pub fn model_type_and_path(&self, base_path: &Path) -> (ModelType, PathBuf) {
+    /// find model by -m value: id (1~13), built-in model name, or name of a model folder with snippets_model.json under base_path
+    pub fn find(name: &str, base_path: &Path) -> Result<(ModelType, Model, ModelFiles), EmbeddingError> {
+        // numeric id is the alias of built-in model
+        if let Ok(id) = name.parse::<usize>() {
+            let model = Model::new(id)?;
+            let (model_type, model_dir) = model.model_type_and_path(base_path)?;
+            return Ok((model_type, model, Self::check_model_files(&model_dir)?))
+        }
+        // folder name
+        let model_dir = base_path.join(name);
+        if model_dir.is_dir() {
+            if let Some(m) = Self::check_model(&model_dir)? {
+                return Ok(m)
+            }
+        }
+        // model name in snippets_model.json
+        if let Some((manifest, model_dir)) = find_manifest(name, base_path) {
+            return Ok((ModelType::Custom(manifest), Model::Custom(name.to_string()), Self::check_model_files(&model_dir)?))
+        }
+        Err(EmbeddingError::ParaError{para: format!("couldn't find model {} in {}", name, base_path.display())})
+    }
+
+    /// find built-in model in the Hugging Face cache, offline
+    /// name: id (1~13), model name, or Hugging Face repo id (e.g. intfloat/e5-base-v2)
+    pub fn find_in_hf_cache(name: &str) -> Result<(ModelType, Model, ModelFiles), EmbeddingError> {
+        let model = match name.parse::<usize>() {
+            Ok(id) => Model::new(id)?,
+            Err(_) => {
+                let short_name = name.rsplit('/').next().unwrap_or(name);
+                match MODEL_NAME.iter().position(|n| *n == short_name) {
+                    Some(index) => Model::new(index+1)?,
+                    None => return Err(EmbeddingError::ParaError{para: format!("{} is not a built-in model, only built-in models are searched in the Hugging Face cache", name)}),
+                }
+            },
+        };
+        let repo_id = model.repo_id().unwrap_or_default();
+        let cache_dir = hf_cache_dir().ok_or(EmbeddingError::ParaError{para: "couldn't find the Hugging Face cache, set HF_HOME or HF_HUB_CACHE".to_string()})?;
+        let snapshot = hf_snapshot(&cache_dir, &repo_id).ok_or(EmbeddingError::ParaError{para: format!("couldn't find {} in the Hugging Face cache {}", repo_id, cache_dir.display())})?;
+        let (model_type, _) = model.model_type_and_path(&snapshot)?;
+        Ok((model_type, model, Self::check_model_files(&snapshot)?))
+    }
+
+    /// Hugging Face repo id of built-in model, e.g. intfloat/e5-base-v2
+    pub fn repo_id(&self) -> Option<String> {
+        let index = MODEL_NAME.iter().position(|n| *n == self.name())?;
+        Some(format!("{}/{}", MODEL_ORG[index], MODEL_NAME[index]))
+    }
+
+    /// get model name, same as the model folder name
+    pub fn name(&self) -> &str {
         match self {
+            Self::GraniteEmbeddingSmallEnglishR247m  => MODEL_NAME[0],
+            Self::GraniteEmbeddingEnglishR2149m      => MODEL_NAME[1],
+            Self::GraniteEmbeddingMultilingual107m   => MODEL_NAME[2],
+            Self::GraniteEmbeddingMultilingual278m   => MODEL_NAME[3],
+            Self::AllMiniLmL6V2                      => MODEL_NAME[4],
+            Self::AllMiniLmL12V2                     => MODEL_NAME[5],
+            Self::ParaphraseMultilingualMiniLmL12V2  => MODEL_NAME[6],
+            Self::MxbaiEmbedLargeV1                  => MODEL_NAME[7],
+            Self::MxbaiEmbedXsmallV1                 => MODEL_NAME[8],
+            Self::E5BaseV2                           => MODEL_NAME[9],
+            Self::MultilingualE5Small                => MODEL_NAME[10],
+            Self::PotionBase8m                       => MODEL_NAME[11],
+            Self::PotionMultilingual128m             => MODEL_NAME[12],
+            Self::Custom(name)                       => name,
+        }
+    }
+
+    /// get model type and model path
+    pub fn model_type_and_path(&self, base_path: &Path) -> Result<(ModelType, PathBuf), EmbeddingError> {
+        Ok(match self {
             Self::GraniteEmbeddingSmallEnglishR247m  => (ModelType::GraniteEnglishR2,                  base_path.join(MODEL_NAME[0])),
             Self::GraniteEmbeddingEnglishR2149m      => (ModelType::GraniteEnglishR2,                  base_path.join(MODEL_NAME[1])),
             Self::GraniteEmbeddingMultilingual107m   => (ModelType::GraniteMultilingual,               base_path.join(MODEL_NAME[2])),
@@ -107,33 +220,27 @@ impl Model {
             Self::MxbaiEmbedXsmallV1                 => (ModelType::MxbaiEmbedV1,                      base_path.join(MODEL_NAME[8])),
             Self::E5BaseV2                           => (ModelType::E5BaseV2,                          base_path.join(MODEL_NAME[9])),
             Self::MultilingualE5Small                => (ModelType::MultilingualE5Small,               base_path.join(MODEL_NAME[10])),
-        }
-    }
-
-    /// get model type
-    pub fn model_type(&self) -> ModelType {
-        match self {
-            Self::GraniteEmbeddingSmallEnglishR247m  => ModelType::GraniteEnglishR2,
-            Self::GraniteEmbeddingEnglishR2149m      => ModelType::GraniteEnglishR2,
-            Self::GraniteEmbeddingMultilingual107m   => ModelType::GraniteMultilingual,
-            Self::GraniteEmbeddingMultilingual278m   => ModelType::GraniteMultilingual,
-            Self::AllMiniLmL6V2                      => ModelType::AllMiniLmV2,
-            Self::AllMiniLmL12V2                     => ModelType::AllMiniLmV2,
-            Self::ParaphraseMultilingualMiniLmL12V2  => ModelType::ParaphraseMultilingualMiniLmL12V2,
-            Self::MxbaiEmbedLargeV1                  => ModelType::MxbaiEmbedV1,
-            Self::MxbaiEmbedXsmallV1                 => ModelType::MxbaiEmbedV1,
-            Self::E5BaseV2                           => ModelType::E5BaseV2,
-            Self::MultilingualE5Small                => ModelType::MultilingualE5Small
-        }
+            Self::PotionBase8m                       => (ModelType::Static,                            base_path.join(MODEL_NAME[11])),
+            Self::PotionMultilingual128m             => (ModelType::Static,                            base_path.join(MODEL_NAME[12])),
+            Self::Custom(name)                       => match find_manifest(name, base_path) {
+                Some((manifest, model_dir)) => (ModelType::Custom(manifest), model_dir),
+                None => return Err(EmbeddingError::ParaError{para: format!("couldn't find model {} in {}", name, base_path.display())}),
+            },
+        })
     }
 
     /// check valid model path, model.safetensors, config.json, tokenizer.json exist
-    pub fn check_model(base_path: &Path) -> Result<Option<(ModelType, Model, (PathBuf, PathBuf, PathBuf))>, EmbeddingError> {
+    /// built-in model folder, or folder with snippets_model.json
+    pub fn check_model(base_path: &Path) -> Result<Option<(ModelType, Model, ModelFiles)>, EmbeddingError> {
         if base_path.exists() && base_path.is_dir() {
             if let Some(name) = base_path.file_name() {
                 if let Some(index) = MODEL_NAME.iter().position(|n| *n == name) {
                     let model = Model::new(index+1)?;
-                    Ok(Some((model.model_type(), model, Self::check_model_files(base_path)?)))
+                    let (model_type, _) = model.model_type_and_path(base_path)?;
+                    Ok(Some((model_type, model, Self::check_model_files(base_path)?)))
+                } else if let Some(manifest) = ModelManifest::read(base_path)? {
+                    let model = Model::Custom(manifest.model_name(base_path));
+                    Ok(Some((ModelType::Custom(manifest), model, Self::check_model_files(base_path)?)))
                 } else {
                     Ok(None)
                 }
@@ -146,7 +253,7 @@ impl Model {
     }
 
     /// check valid model model.safetensors, config.json, tokenizer.json exist
-    pub fn check_model_files(base_path: &Path) -> Result<(PathBuf, PathBuf, PathBuf), EmbeddingError> {
+    pub fn check_model_files(base_path: &Path) -> Result<ModelFiles, EmbeddingError> {
         if base_path.exists() && base_path.is_dir() {
             let model_path = base_path.join("model.safetensors");
             if model_path.exists() && model_path.is_file() {
@@ -180,16 +287,147 @@ pub enum ModelType {
     MxbaiEmbedV1,                      // 8, 9
     E5BaseV2,                          // 10
     MultilingualE5Small,               // 11
+    Static,                            // 12, 13, Model2Vec lookup table
+    Custom(ModelManifest),             // model folder with snippets_model.json
+}
+
+impl ModelType {
+    /// prompt prepended to the text before embedding, some models are trained with different prompts for queries and documents
+    pub fn prompt(&self, mode: EmbedMode) -> &str {
+        match (self, mode) {
+            (Self::Custom(manifest), EmbedMode::Query)                        => &manifest.query_prompt,
+            (Self::Custom(manifest), EmbedMode::Document)                     => &manifest.document_prompt,
+            (Self::E5BaseV2 | Self::MultilingualE5Small, EmbedMode::Query)    => "query: ", // https://huggingface.co/intfloat/e5-base-v2#faq
+            (Self::E5BaseV2 | Self::MultilingualE5Small, EmbedMode::Document) => "passage: ",
+            (Self::MxbaiEmbedV1, EmbedMode::Query)                            => "Represent this sentence for searching relevant passages: ", // https://huggingface.co/mixedbread-ai/mxbai-embed-large-v1
+            _                                                                 => "",
+        }
+    }
+
+    /// transformer architecture, decides which dtypes can be used
+    pub fn architecture(&self) -> Architecture {
+        match self {
+            Self::GraniteEnglishR2    => Architecture::ModernBert,
+            Self::GraniteMultilingual => Architecture::XlmRoberta,
+            Self::Static              => Architecture::Static,
+            Self::Custom(manifest)    => manifest.architecture.clone(),
+            _                         => Architecture::Bert, // 5 ~ 11
+        }
+    }
+
+    /// embeddings can be truncated to the first dimensions with little quality loss (Matryoshka representation learning)
+    pub fn matryoshka(&self) -> bool {
+        match self {
+            Self::Custom(manifest) => manifest.matryoshka,
+            Self::MxbaiEmbedV1     => true, // https://huggingface.co/mixedbread-ai/mxbai-embed-large-v1#matryoshka-representation-learning
+            _                      => false,
+        }
+    }
+}
+
+/// dtype of the model weights
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ModelDtype {
+    F32,  // full precision
+    F16,  // half precision, half the memory, faster on gpu
+    BF16, // bfloat16, half the memory, same range as f32
+}
+
+impl ModelDtype {
+    /// parse --dtype or SNIPPETS_DTYPE value
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "f32"  => Some(Self::F32),
+            "f16"  => Some(Self::F16),
+            "bf16" => Some(Self::BF16),
+            _      => None,
+        }
+    }
+
+    /// dtype name
+    pub fn name(&self) -> &str {
+        match self {
+            Self::F32  => "f32",
+            Self::F16  => "f16",
+            Self::BF16 => "bf16",
+        }
+    }
+
+    /// why the architecture can't run in this dtype, f32 is used instead
+    /// candle builds the attention masks of XLM-RoBERTa and ModernBERT only in f32, and the BERT attention mask overflows in f16
+    pub fn unsupported(&self, architecture: &Architecture) -> Option<&'static str> {
+        match (self, architecture) {
+            (Self::F32, _) | (_, Architecture::Static) => None, // static models only look up rows of the table
+            (Self::BF16, Architecture::Bert)           => None,
+            (Self::F16, Architecture::Bert)            => Some("the attention mask of BERT overflows in f16, use bf16"),
+            (_, Architecture::XlmRoberta)              => Some("XLM-RoBERTa only runs in f32"),
+            (_, Architecture::ModernBert)              => Some("ModernBERT only runs in f32"),
+        }
+    }
+
+    /// whether the dtype needs a gpu for the architecture, candle has no bf16 matmul on cpu, static models have no matmul
+    pub fn needs_gpu(&self, architecture: &Architecture) -> bool {
+        matches!(self, Self::BF16) && !matches!(architecture, Architecture::Static)
+    }
+
+    /// candle dtype of the weights, f32 if the architecture can't run in this dtype, or bf16 on cpu (no bf16 matmul on cpu)
+    #[cfg(feature = "embedding")]
+    pub(crate) fn resolve(&self, architecture: &Architecture, device: &Device) -> DType {
+        if self.unsupported(architecture).is_some() {
+            return DType::F32
+        }
+        match self {
+            Self::F32                     => DType::F32,
+            Self::F16                     => DType::F16,
+            Self::BF16 if device.is_cpu() && self.needs_gpu(architecture) => DType::F32,
+            Self::BF16                    => DType::BF16,
+        }
+    }
+}
+
+/// what the text is embedded for, decides the prompt
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EmbedMode {
+    Query,    // search keyword
+    Document, // snippet discription
+}
+
+/// embedding provider of semantic search, implement it to plug in your own model
+/// object safe, can be used as Box<dyn Embedder>
+pub trait Embedder {
+    /// model identity, key of the embeddings stored in snippets
+    fn model(&self) -> Model;
+
+    /// embedding dimension
+    fn dimension(&self) -> usize;
+
+    /// prompt prepended to texts of the mode, stored embeddings calculated with another document prompt are stale
+    fn prompt(&self, _mode: EmbedMode) -> &str {
+        ""
+    }
+
+    /// generates embeddings for a batch of sentences, the prompt of the mode is applied by the embedder
+    fn embed_batch(&self, sentences: &[&str], mode: EmbedMode) -> Result<Vec<Vec<f32>>, EmbeddingError>;
+
+    /// generates embedding for a sentence
+    fn embed(&self, sentence: &str, mode: EmbedMode) -> Result<Vec<f32>, EmbeddingError> {
+        self.embed_batch(&[sentence], mode)?.pop().ok_or(EmbeddingError::ParaError{para: "empty embedding".to_string()})
+    }
 }
 
 /// embedding trait
 #[cfg(feature = "embedding")]
 trait EmbeddingMethod {
     /// init model
-    fn new(model_file: &Path, config_file: &Path, tokenizer_file: &Path, device: Device) -> Result<Self, EmbeddingError> where Self: Sized;
+    fn new(model_file: &Path, config_file: &Path, tokenizer_file: &Path, device: Device, dtype: DType) -> Result<Self, EmbeddingError> where Self: Sized;
+
+    /// generates embeddings for a batch of sentences
+    fn get_embeddings(&self, sentences: &[&str]) -> Result<Vec<Vec<f32>>, EmbeddingError>;
 
     /// generates embedding for a sentence
-    fn get_embedding(&self, sentence: &str) -> Result<Vec<f32>, EmbeddingError>;
+    fn get_embedding(&self, sentence: &str) -> Result<Vec<f32>, EmbeddingError> {
+        self.get_embeddings(&[sentence])?.pop().ok_or(EmbeddingError::ParaError{para: "empty embedding".to_string()})
+    }
 }
 
 /// embedding model
@@ -202,51 +440,259 @@ pub enum EmbeddingModel {
     MxbaiEmbedV1(MxbaiEmbedV1Model),
     E5BaseV2(E5BaseV2Model),
     MultilingualE5Small(MultilingualE5SmallModel),
+    Static(StaticModel),
+    Custom(CustomModel),
 }
 
 #[cfg(feature = "embedding")]
 impl EmbeddingModel {
-    /// load model
-    pub fn load_model(model: &ModelType, model_file: &Path, config_file: &Path, tokenizer_file: &Path, use_cpu: bool) -> Result<Self, EmbeddingError> {
-        let device = if use_cpu {
-            Device::Cpu
-        } else {
-            if (cfg!(target_os = "windows") || cfg!(target_os = "linux")) && cuda_is_available() {
-                Device::new_cuda(0)?
-            } else if cfg!(target_os = "macos") && metal_is_available() {
-                Device::new_metal(0)?
-            } else {
-                Device::Cpu
-            }
-        };
+    /// load model, weights are memory-mapped and loaded as dtype, static models always run on cpu
+    pub fn load_model(model: &ModelType, model_file: &Path, config_file: &Path, tokenizer_file: &Path, use_cpu: bool, dtype: ModelDtype) -> Result<Self, EmbeddingError> {
+        let device = select_device(use_cpu)?;
+        let dtype = dtype.resolve(&model.architecture(), &device);
         Ok(
             match model {
-                ModelType::GraniteEnglishR2                  => Self::GraniteEnglishR2(GraniteEnglishR2Model::new(model_file, config_file, tokenizer_file, device)?),
-                ModelType::GraniteMultilingual               => Self::GraniteMultilingual(GraniteMultilingualModel::new(model_file, config_file, tokenizer_file, device)?),
-                ModelType::AllMiniLmV2                       => Self::AllMiniLmV2(AllMiniLmV2Model::new(model_file, config_file, tokenizer_file, device)?),
-                ModelType::ParaphraseMultilingualMiniLmL12V2 => Self::ParaphraseMultilingualMiniLmL12V2(ParaphraseMultilingualMiniLmL12V2Model::new(model_file, config_file, tokenizer_file, device)?),
-                ModelType::MxbaiEmbedV1                      => Self::MxbaiEmbedV1(MxbaiEmbedV1Model::new(model_file, config_file, tokenizer_file, device)?),
-                ModelType::E5BaseV2                          => Self::E5BaseV2(E5BaseV2Model::new(model_file, config_file, tokenizer_file, device)?),
-                ModelType::MultilingualE5Small               => Self::MultilingualE5Small(MultilingualE5SmallModel::new(model_file, config_file, tokenizer_file, device)?),
+                ModelType::GraniteEnglishR2                  => Self::GraniteEnglishR2(GraniteEnglishR2Model::new(model_file, config_file, tokenizer_file, device, dtype)?),
+                ModelType::GraniteMultilingual               => Self::GraniteMultilingual(GraniteMultilingualModel::new(model_file, config_file, tokenizer_file, device, dtype)?),
+                ModelType::AllMiniLmV2                       => Self::AllMiniLmV2(AllMiniLmV2Model::new(model_file, config_file, tokenizer_file, device, dtype)?),
+                ModelType::ParaphraseMultilingualMiniLmL12V2 => Self::ParaphraseMultilingualMiniLmL12V2(ParaphraseMultilingualMiniLmL12V2Model::new(model_file, config_file, tokenizer_file, device, dtype)?),
+                ModelType::MxbaiEmbedV1                      => Self::MxbaiEmbedV1(MxbaiEmbedV1Model::new(model_file, config_file, tokenizer_file, device, dtype)?),
+                ModelType::E5BaseV2                          => Self::E5BaseV2(E5BaseV2Model::new(model_file, config_file, tokenizer_file, device, dtype)?),
+                ModelType::MultilingualE5Small               => Self::MultilingualE5Small(MultilingualE5SmallModel::new(model_file, config_file, tokenizer_file, device, dtype)?),
+                ModelType::Static                            => Self::Static(StaticModel::new(model_file, config_file, tokenizer_file, Device::Cpu, dtype)?), // lookup is faster on cpu
+                ModelType::Custom(manifest)                  => Self::Custom(CustomModel::load(manifest, model_file, config_file, tokenizer_file, device, dtype)?),
             }
         )
     }
 
+    /// get model type
+    pub fn model_type(&self) -> ModelType {
+        match self {
+            Self::GraniteEnglishR2(_)                  => ModelType::GraniteEnglishR2,
+            Self::GraniteMultilingual(_)               => ModelType::GraniteMultilingual,
+            Self::AllMiniLmV2(_)                       => ModelType::AllMiniLmV2,
+            Self::ParaphraseMultilingualMiniLmL12V2(_) => ModelType::ParaphraseMultilingualMiniLmL12V2,
+            Self::MxbaiEmbedV1(_)                      => ModelType::MxbaiEmbedV1,
+            Self::E5BaseV2(_)                          => ModelType::E5BaseV2,
+            Self::MultilingualE5Small(_)               => ModelType::MultilingualE5Small,
+            Self::Static(_)                            => ModelType::Static,
+            Self::Custom(model)                        => ModelType::Custom(model.manifest.clone()),
+        }
+    }
+
+    /// generates embeddings for a batch of sentences, sentences are padded to the longest one
+    /// the model's query or document prompt is prepended to each sentence
+    pub fn get_embeddings(&self, sentences: &[&str], mode: EmbedMode) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        if sentences.is_empty() {
+            return Ok(Vec::new())
+        }
+        let model_type = self.model_type();
+        let prompt = model_type.prompt(mode);
+        let prompted: Vec<String> = sentences.iter().map(|s| format!("{}{}", prompt, s)).collect();
+        let sentences: Vec<&str> = prompted.iter().map(|s| s.as_str()).collect();
+        let sentences = sentences.as_slice();
+        match self {
+            Self::GraniteEnglishR2(model)                  => model.get_embeddings(sentences),
+            Self::GraniteMultilingual(model)               => model.get_embeddings(sentences),
+            Self::AllMiniLmV2(model)                       => model.get_embeddings(sentences),
+            Self::ParaphraseMultilingualMiniLmL12V2(model) => model.get_embeddings(sentences),
+            Self::MxbaiEmbedV1(model)                      => model.get_embeddings(sentences),
+            Self::E5BaseV2(model)                          => model.get_embeddings(sentences),
+            Self::MultilingualE5Small(model)               => model.get_embeddings(sentences),
+            Self::Static(model)                            => model.get_embeddings(sentences),
+            Self::Custom(model)                            => model.get_embeddings(sentences),
+        }
+    }
+
     /// generates embedding for a sentence
-    pub fn get_embedding(&self, sentence: &str) -> Result<Vec<f32>, EmbeddingError> {
+    /// the model's query or document prompt is prepended to the sentence
+    pub fn get_embedding(&self, sentence: &str, mode: EmbedMode) -> Result<Vec<f32>, EmbeddingError> {
+        let sentence = format!("{}{}", self.model_type().prompt(mode), sentence);
         match self {
-            Self::GraniteEnglishR2(model)                  => model.get_embedding(sentence),
-            Self::GraniteMultilingual(model)               => model.get_embedding(sentence),
-            Self::AllMiniLmV2(model)                       => model.get_embedding(sentence),
-            Self::ParaphraseMultilingualMiniLmL12V2(model) => model.get_embedding(sentence),
-            Self::MxbaiEmbedV1(model)                      => model.get_embedding(sentence),
-            Self::E5BaseV2(model)                          => model.get_embedding(sentence),
-            Self::MultilingualE5Small(model)               => model.get_embedding(sentence),
+            Self::GraniteEnglishR2(model)                  => model.get_embedding(&sentence),
+            Self::GraniteMultilingual(model)               => model.get_embedding(&sentence),
+            Self::AllMiniLmV2(model)                       => model.get_embedding(&sentence),
+            Self::ParaphraseMultilingualMiniLmL12V2(model) => model.get_embedding(&sentence),
+            Self::MxbaiEmbedV1(model)                      => model.get_embedding(&sentence),
+            Self::E5BaseV2(model)                          => model.get_embedding(&sentence),
+            Self::MultilingualE5Small(model)               => model.get_embedding(&sentence),
+            Self::Static(model)                            => model.get_embedding(&sentence),
+            Self::Custom(model)                            => model.get_embedding(&sentence),
+        }
+    }
+}
+
+/// cpu if use_cpu, otherwise prioritize using the gpu
+#[cfg(feature = "embedding")]
+fn select_device(use_cpu: bool) -> Result<Device, EmbeddingError> {
+    Ok(if use_cpu {
+        Device::Cpu
+    } else {
+        if (cfg!(target_os = "windows") || cfg!(target_os = "linux")) && cuda_is_available() {
+            Device::new_cuda(0)?
+        } else if cfg!(target_os = "macos") && metal_is_available() {
+            Device::new_metal(0)?
+        } else {
+            Device::Cpu
+        }
+    })
+}
+
+/// Hugging Face hub cache, priority order: HF_HUB_CACHE > HF_HOME/hub > ~/.cache/huggingface/hub
+pub fn hf_cache_dir() -> Option<PathBuf> {
+    let cache_dir = if let Some(hub_cache) = env::var_os("HF_HUB_CACHE") {
+        PathBuf::from(hub_cache)
+    } else if let Some(hf_home) = env::var_os("HF_HOME") {
+        PathBuf::from(hf_home).join("hub")
+    } else {
+        PathBuf::from(env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?).join(".cache").join("huggingface").join("hub")
+    };
+    if cache_dir.is_dir() {
+        Some(cache_dir)
+    } else {
+        None
+    }
+}
+
+/// snapshot folder of the repo in the Hugging Face cache: <cache>/models--org--name/snapshots/<rev>/
+/// use the revision in refs/main, otherwise the most recent snapshot containing model.safetensors, config.json, tokenizer.json
+/// files in the snapshot are symlinks to blobs, they are followed when checking and reading
+fn hf_snapshot(cache_dir: &Path, repo_id: &str) -> Option<PathBuf> {
+    let repo_dir = cache_dir.join(format!("models--{}", repo_id.replace("/", "--")));
+    if let Ok(rev) = read_to_string(repo_dir.join("refs").join("main")) {
+        let snapshot = repo_dir.join("snapshots").join(rev.trim());
+        if Model::check_model_files(&snapshot).is_ok() {
+            return Some(snapshot)
+        }
+    }
+    repo_dir.join("snapshots")
+        .read_dir()
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| Model::check_model_files(p).is_ok())
+        .max_by_key(|p| p.metadata().and_then(|m| m.modified()).ok())
+}
+
+/// Embedder of the built-in models and the models with snippets_model.json
+#[cfg(feature = "embedding")]
+pub struct ModelEmbedder {
+    model:           Model,
+    model_type:      ModelType,
+    dimension:       usize,
+    embedding_model: EmbeddingModel,
+}
+
+#[cfg(feature = "embedding")]
+impl ModelEmbedder {
+    /// load model, dimension is hidden_size in config.json, or hidden_dim for static models
+    pub fn load(model: Model, model_type: ModelType, model_file: &Path, config_file: &Path, tokenizer_file: &Path, use_cpu: bool, dtype: ModelDtype) -> Result<Self, EmbeddingError> {
+        let dimension = model_dimension(config_file)?;
+        let embedding_model = EmbeddingModel::load_model(&model_type, model_file, config_file, tokenizer_file, use_cpu, dtype)?;
+        Ok(Self { model, model_type, dimension, embedding_model })
+    }
+}
+
+#[cfg(feature = "embedding")]
+impl Embedder for ModelEmbedder {
+    fn model(&self) -> Model {
+        self.model.clone()
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn prompt(&self, mode: EmbedMode) -> &str {
+        self.model_type.prompt(mode)
+    }
+
+    fn embed_batch(&self, sentences: &[&str], mode: EmbedMode) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        self.embedding_model.get_embeddings(sentences, mode)
+    }
+
+    fn embed(&self, sentence: &str, mode: EmbedMode) -> Result<Vec<f32>, EmbeddingError> {
+        self.embedding_model.get_embedding(sentence, mode)
+    }
+}
+
+/// memory-mapped model.safetensors, only the requested tensors are read and converted to dtype
+#[cfg(feature = "embedding")]
+pub(crate) fn mmap_var_builder(model_file: &Path, dtype: DType, device: &Device) -> Result<VarBuilder<'static>, EmbeddingError> {
+    Ok(unsafe { VarBuilder::from_mmaped_safetensors(&[model_file], dtype, device)? })
+}
+
+/// requested tensor names starting with `from` are read from `to` + the rest of the name, no tensor is copied
+/// e.g. ModernBert requests "model.*", which is stored without the prefix in most sentence-transformer exports
+#[cfg(feature = "embedding")]
+pub(crate) fn remap_prefix(vb: VarBuilder<'static>, from: &str, to: &str) -> VarBuilder<'static> {
+    let (from, to) = (from.to_string(), to.to_string());
+    vb.rename_f(move |name| match name.strip_prefix(&from) {
+        Some(rest) => format!("{}{}", to, rest),
+        None => name.to_string(),
+    })
+}
+
+/// embedding dimension of the model without loading it, hidden_size in config.json, or hidden_dim for static models
+pub fn model_dimension(config_file: &Path) -> Result<usize, EmbeddingError> {
+    let config: serde_json::Value = serde_json::from_str(&read_to_string(config_file)?).map_err(|e| EmbeddingError::JsonFromStrError{error: e})?;
+    Ok(config.get("hidden_size").or_else(|| config.get("hidden_dim")).and_then(|d| d.as_u64()).ok_or(EmbeddingError::ParaError{para: format!("hidden_size not found in {}", config_file.display())})? as usize)
+}
+
+/// Matryoshka truncation, keep the first dim values and renormalize, zero vector stays zero
+pub fn truncate_embedding(embedding: &[f32], dim: usize) -> Vec<f32> {
+    let truncated = &embedding[..dim.min(embedding.len())];
+    let norm = truncated.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        truncated.iter().map(|x| x / norm).collect()
+    } else {
+        truncated.to_vec()
+    }
+}
+
+/// find the model folder whose snippets_model.json has the given model name
+fn find_manifest(name: &str, base_path: &Path) -> Option<(ModelManifest, PathBuf)> {
+    for entry in base_path.read_dir().ok()?.flatten() {
+        let model_dir = entry.path();
+        if let Ok(Some(manifest)) = ModelManifest::read(&model_dir) {
+            if manifest.model_name(&model_dir) == name {
+                return Some((manifest, model_dir))
+            }
         }
     }
+    None
+}
+
+/// tokenize a batch of sentences, padding to the longest one
+/// return token_ids, token_type_ids, attention_mask, shape: (batch, seq_len)
+/// attention_mask is 1 for real tokens and 0 for padding tokens
+#[cfg(feature = "embedding")]
+pub fn encode_batch(tokenizer: &Tokenizer, sentences: &[&str], device: &Device) -> Result<(Tensor, Tensor, Tensor), EmbeddingError> {
+    // encode the given inputs, accepts both single sequences, as well as pair sequences.
+    let encodings = tokenizer.encode_batch(sentences.to_vec(), true).map_err(|e| EmbeddingError::TokenizerEncodeError{error: e})?;
+
+    // Convert the tokenized data (Vec<u32>) into Candle Tensors
+    let mut token_ids: Vec<Tensor> = Vec::with_capacity(encodings.len());
+    let mut token_type_ids: Vec<Tensor> = Vec::with_capacity(encodings.len());
+    let mut attention_mask: Vec<Tensor> = Vec::with_capacity(encodings.len());
+    for encoding in &encodings {
+        token_ids.push(Tensor::new(encoding.get_ids(), device)?);
+        token_type_ids.push(Tensor::new(encoding.get_type_ids(), device)?);
+        attention_mask.push(Tensor::new(encoding.get_attention_mask(), device)?);
+    }
+    Ok((Tensor::stack(&token_ids, 0)?, Tensor::stack(&token_type_ids, 0)?, Tensor::stack(&attention_mask, 0)?))
 }
 
-/// average pooling
+/// L2 normalization, convert Tensor (batch, dim) to Vec<Vec<f32>>
+#[cfg(feature = "embedding")]
+pub fn normalize(embeddings: &Tensor) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+    // Manually implement L2 normalization
+    // The formula is `x / sqrt(sum(x^2))`
+    let norms = embeddings.sqr()?.sum_keepdim(1)?.sqrt()?;
+    Ok(embeddings.broadcast_div(&norms)?.to_vec2::<f32>()?)
+}
+
+/// average pooling, padding tokens are excluded by attention_mask
 /// last_hidden_state: (1, 6, 768)
 /// attention_mask: (1, 6)
 /// last_hidden: (1, 6, 768)
@@ -267,6 +713,35 @@ pub fn embedding_enabled() -> bool {
     cfg!(feature = "embedding")
 }
 
+/// words in one content chunk, short enough for the max length of all supported models
+pub const CHUNK_WORDS: usize = 96;
+
+/// words shared by two neighbouring content chunks, so a match across the chunk border is not lost
+pub const CHUNK_OVERLAP: usize = 24;
+
+/// split snippet content to overlapping chunks of whitespace separated words, empty content has no chunk
+/// used for content embeddings, both at compile time and at runtime
+pub fn chunk_text(text: &str, chunk_words: usize, overlap: usize) -> Vec<String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let step = chunk_words.saturating_sub(overlap).max(1);
+    let mut chunks: Vec<String> = Vec::new();
+    let mut start = 0;
+    while start < words.len() {
+        let end = (start + chunk_words).min(words.len());
+        chunks.push(words[start..end].join(" "));
+        if end == words.len() {
+            break
+        }
+        start += step;
+    }
+    chunks
+}
+
+/// default id of a snippet in its source: the discription in lowercase, words joined by '-'
+pub fn snippet_id(disc: &str) -> String {
+    disc.to_lowercase().split_whitespace().collect::<Vec<_>>().join("-")
+}
+
 /// split long discription to multiple short lines
 pub fn split_discription(disc: &str, max_width: usize) -> String {
     let mut short_lines: Vec<String> = Vec::new();
@@ -286,3 +761,115 @@ pub fn split_discription(disc: &str, max_width: usize) -> String {
     }
     short_lines.join("\n")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_and_passage_prompts() {
+        for e5 in [ModelType::E5BaseV2, ModelType::MultilingualE5Small] {
+            assert_eq!(e5.prompt(EmbedMode::Query), "query: ");
+            assert_eq!(e5.prompt(EmbedMode::Document), "passage: ");
+        }
+        assert_eq!(ModelType::MxbaiEmbedV1.prompt(EmbedMode::Query), "Represent this sentence for searching relevant passages: ");
+        assert_eq!(ModelType::MxbaiEmbedV1.prompt(EmbedMode::Document), ""); // documents are embedded without prompt
+        assert_eq!(ModelType::AllMiniLmV2.prompt(EmbedMode::Query), "");
+    }
+
+    #[test]
+    fn chunks_overlap() {
+        let text = (1..=10).map(|i| i.to_string()).collect::<Vec<_>>().join(" \n ");
+        assert_eq!(chunk_text(&text, 4, 1), vec!["1 2 3 4", "4 5 6 7", "7 8 9 10"]); // the last chunk ends at the last word
+        assert_eq!(chunk_text(&text, 4, 2), vec!["1 2 3 4", "3 4 5 6", "5 6 7 8", "7 8 9 10"]);
+        assert_eq!(chunk_text(&text, 6, 2), vec!["1 2 3 4 5 6", "5 6 7 8 9 10"]);
+        assert_eq!(chunk_text(&text, 10, 3), vec![text.split_whitespace().collect::<Vec<_>>().join(" ")]); // short content is one chunk
+        assert_eq!(chunk_text("a b c", 2, 5), vec!["a b", "b c"]); // overlap >= chunk_words still advances one word
+        assert!(chunk_text(" \n\t", 4, 1).is_empty());
+    }
+
+    #[test]
+    fn truncate_and_renormalize() {
+        let truncated = truncate_embedding(&[0.6, 0.0, 0.8, 0.0], 2);
+        assert_eq!(truncated, vec![1.0, 0.0]);
+        let truncated = truncate_embedding(&[0.5, 0.5, 0.5, 0.5], 3);
+        assert!((truncated.iter().map(|x| x * x).sum::<f32>() - 1.0).abs() < 1e-6);
+        assert!(truncated.iter().all(|x| (x - 1.0 / 3f32.sqrt()).abs() < 1e-6));
+        assert_eq!(truncate_embedding(&[0.0, 0.0, 1.0], 2), vec![0.0, 0.0]); // zero vector stays zero
+        assert_eq!(truncate_embedding(&[3.0, 4.0], 8), vec![0.6, 0.8]); // dim longer than the embedding
+    }
+
+    /// empty temporary folder of the test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("snippets-embedding-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn hf_cache_priority() {
+        let dir = temp_dir("hf-cache");
+        let (hub_cache, hf_home, home) = (dir.join("hub-cache"), dir.join("hf-home"), dir.join("home"));
+        for d in [hub_cache.clone(), hf_home.join("hub"), home.join(".cache").join("huggingface").join("hub")] {
+            std::fs::create_dir_all(d).unwrap();
+        }
+        let saved: Vec<_> = ["HF_HUB_CACHE", "HF_HOME", "HOME"].iter().map(|k| (k, env::var_os(k))).collect();
+        env::set_var("HF_HUB_CACHE", &hub_cache);
+        env::set_var("HF_HOME", &hf_home);
+        env::set_var("HOME", &home);
+        assert_eq!(hf_cache_dir(), Some(hub_cache.clone()));
+        env::remove_var("HF_HUB_CACHE");
+        assert_eq!(hf_cache_dir(), Some(hf_home.join("hub")));
+        env::remove_var("HF_HOME");
+        assert_eq!(hf_cache_dir(), Some(home.join(".cache").join("huggingface").join("hub")));
+        env::set_var("HF_HUB_CACHE", dir.join("missing")); // no fallback to lower priorities
+        assert_eq!(hf_cache_dir(), None);
+        for (k, v) in saved {
+            match v {
+                Some(v) => env::set_var(k, v),
+                None => env::remove_var(k),
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hf_snapshot_revision() {
+        let cache = temp_dir("hf-snapshot");
+        let repo = cache.join("models--org--model");
+        let snapshot = |rev: &str, complete: bool| {
+            let dir = repo.join("snapshots").join(rev);
+            std::fs::create_dir_all(&dir).unwrap();
+            let files: &[&str] = if complete { &["model.safetensors", "config.json", "tokenizer.json"] } else { &["config.json"] };
+            for f in files {
+                std::fs::write(dir.join(f), "").unwrap();
+            }
+            dir
+        };
+        assert_eq!(hf_snapshot(&cache, "org/model"), None);
+        snapshot("partial", false);
+        let complete = snapshot("complete", true);
+        assert_eq!(hf_snapshot(&cache, "org/model"), Some(complete.clone())); // the only complete snapshot
+        let main = snapshot("main-rev", true);
+        std::fs::create_dir_all(repo.join("refs")).unwrap();
+        std::fs::write(repo.join("refs").join("main"), "main-rev\n").unwrap();
+        assert_eq!(hf_snapshot(&cache, "org/model"), Some(main.clone()));
+        std::fs::write(repo.join("refs").join("main"), "partial").unwrap(); // incomplete revision in refs/main, the newest complete one is used
+        let found = hf_snapshot(&cache, "org/model").unwrap();
+        assert!(found == main || found == complete);
+        std::fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[cfg(feature = "embedding")]
+    #[test]
+    fn average_pool_skips_padding() {
+        // batch of 2, seq_len 3, dim 2, the second sentence has one padding token
+        let hidden = Tensor::new(&[[[1f32, 2.0], [3.0, 4.0], [5.0, 6.0]], [[2.0, 0.0], [4.0, 2.0], [100.0, 100.0]]], &Device::Cpu).unwrap();
+        let mask = Tensor::new(&[[1u32, 1, 1], [1, 1, 0]], &Device::Cpu).unwrap();
+        let pooled = average_pool(&hidden, &mask).unwrap().to_vec2::<f32>().unwrap();
+        assert_eq!(pooled, vec![vec![3.0, 4.0], vec![3.0, 1.0]]);
+        let normalized = normalize(&Tensor::new(&[[3f32, 4.0]], &Device::Cpu).unwrap()).unwrap();
+        assert_eq!(normalized, vec![vec![0.6, 0.8]]);
+    }
+}
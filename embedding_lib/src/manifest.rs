@@ -0,0 +1,140 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::EmbeddingError;
+
+/// manifest file in the model folder, next to model.safetensors, config.json, tokenizer.json
+pub const MANIFEST_FILE: &str = "snippets_model.json";
+
+/// model architecture
+#[derive(Clone, Deserialize, Serialize)]
+pub enum Architecture {
+    #[serde(rename = "bert")]
+    Bert,       // BERT, MiniLM, e5, bge, ...
+    #[serde(rename = "xlm-roberta")]
+    XlmRoberta, // XLM-RoBERTa, multilingual models
+    #[serde(rename = "modernbert")]
+    ModernBert, // ModernBERT
+    #[serde(rename = "static")]
+    Static,     // Model2Vec static lookup table, pooling is always mean, normalize is read from config.json
+}
+
+/// pooling of the last hidden state
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub enum Pooling {
+    #[serde(rename = "cls")]
+    Cls,  // embedding of the first token
+    #[serde(rename = "mean")]
+    Mean, // average of all tokens
+}
+
+/// model manifest, describes how to load and use a sentence-transformer folder
+/// {"architecture": "bert", "pooling": "cls", "normalize": true, "max_length": 512, "query_prompt": "query: ", "document_prompt": "passage: "}
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ModelManifest {
+    #[serde(default)]
+    pub name:            Option<String>, // model name used by -m and stored embeddings, default: folder name
+    pub architecture:    Architecture,
+    pub pooling:         Pooling,
+    #[serde(default = "default_normalize")]
+    pub normalize:       bool,           // L2 normalization, default: true
+    #[serde(default)]
+    pub max_length:      Option<usize>,  // tokenizer truncation, default: max_position_embeddings in config.json
+    #[serde(default)]
+    pub weight_prefix:   Option<String>, // prefix of tensor names in model.safetensors, e.g. "bert", "roberta"
+    #[serde(default)]
+    pub query_prompt:    String,         // prepended to search keywords
+    #[serde(default)]
+    pub document_prompt: String,         // prepended to snippet discriptions
+    #[serde(default)]
+    pub matryoshka:      bool,           // trained with Matryoshka representation learning, embeddings can be truncated by --dim, default: false
+}
+
+/// normalize embeddings by default
+fn default_normalize() -> bool {
+    true
+}
+
+impl ModelManifest {
+    /// read manifest in the model folder, return None if the folder does not contain a manifest
+    pub fn read(model_dir: &Path) -> Result<Option<Self>, EmbeddingError> {
+        let manifest_file = model_dir.join(MANIFEST_FILE);
+        if !manifest_file.is_file() {
+            return Ok(None)
+        }
+        serde_json::from_str(&read_to_string(&manifest_file)?)
+            .map(Some)
+            .map_err(|e| EmbeddingError::ParaError{para: format!("invalid model manifest {}: {}", manifest_file.display(), e)})
+    }
+
+    /// model name, use folder name if the manifest does not specify it
+    pub fn model_name(&self, model_dir: &Path) -> String {
+        match &self.name {
+            Some(n) => n.clone(),
+            None => model_dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::path::PathBuf;
+
+    /// model folder named by the test, with this manifest if any
+    fn model_dir(name: &str, manifest: Option<&str>) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("snippets-manifest-{}-{}", std::process::id(), name));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        if let Some(m) = manifest {
+            write(dir.join(MANIFEST_FILE), m).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn defaults_and_name() {
+        let dir = model_dir("bge", Some(r#"{"architecture": "bert", "pooling": "cls"}"#));
+        let manifest = ModelManifest::read(&dir).unwrap().unwrap();
+        assert!(matches!(manifest.architecture, Architecture::Bert));
+        assert!(matches!(manifest.pooling, Pooling::Cls));
+        assert!(manifest.normalize);
+        assert!(!manifest.matryoshka);
+        assert_eq!((manifest.max_length, manifest.weight_prefix.as_deref()), (None, None));
+        assert_eq!((manifest.query_prompt.as_str(), manifest.document_prompt.as_str()), ("", ""));
+        assert_eq!(manifest.model_name(&dir), dir.file_name().unwrap().to_string_lossy());
+        remove_dir_all(&dir).unwrap();
+
+        let dir = model_dir("e5", Some(r#"{"name": "my-e5", "architecture": "xlm-roberta", "pooling": "mean", "normalize": false, "max_length": 256, "weight_prefix": "roberta", "query_prompt": "query: ", "document_prompt": "passage: ", "matryoshka": true}"#));
+        let manifest = ModelManifest::read(&dir).unwrap().unwrap();
+        assert!(matches!(manifest.architecture, Architecture::XlmRoberta));
+        assert!(matches!(manifest.pooling, Pooling::Mean));
+        assert!(!manifest.normalize && manifest.matryoshka);
+        assert_eq!((manifest.max_length, manifest.weight_prefix.as_deref()), (Some(256), Some("roberta")));
+        assert_eq!((manifest.query_prompt.as_str(), manifest.document_prompt.as_str()), ("query: ", "passage: "));
+        assert_eq!(manifest.model_name(&dir), "my-e5");
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_or_invalid() {
+        let dir = model_dir("none", None);
+        assert!(ModelManifest::read(&dir).unwrap().is_none());
+        remove_dir_all(&dir).unwrap();
+        for (name, manifest) in [
+            ("architecture", r#"{"architecture": "gpt", "pooling": "cls"}"#),
+            ("pooling", r#"{"architecture": "bert", "pooling": "max"}"#),
+            ("required", r#"{"architecture": "bert"}"#),
+            ("type", r#"{"architecture": "bert", "pooling": "cls", "max_length": "long"}"#),
+            ("json", r#"{"architecture": "bert","#),
+        ] {
+            let dir = model_dir(name, Some(manifest));
+            let error = ModelManifest::read(&dir).err().unwrap().to_string();
+            assert!(error.contains(MANIFEST_FILE), "{}", error);
+            remove_dir_all(&dir).unwrap();
+        }
+    }
+}
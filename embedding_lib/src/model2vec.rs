@@ -0,0 +1,133 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use candle_core::{
+    Device,
+    DType,
+    Tensor,
+};
+use tokenizers::Tokenizer;
+
+use crate::{
+    error::EmbeddingError,
+    EmbeddingMethod,
+    mmap_var_builder,
+    normalize,
+};
+
+/// static embedding model (Model2Vec), no transformer forward pass
+/// sentence embedding is the mean of the token embeddings in a lookup table
+/// https://github.com/MinishLab/model2vec
+pub struct StaticModel {
+    embeddings: Tensor,           // lookup table [vocab, dim], in the dtype of --dtype
+    weights:    Option<Tensor>,   // per token weight [vocab], indexed by token id, only in some models, in the dtype of --dtype
+    mapping:    Option<Vec<u32>>, // token id -> row of the lookup table, only in quantized vocabulary models
+    tokenizer:  Box<Tokenizer>,
+    normalize:  bool,
+    unk_id:     Option<u32>,      // unknown tokens are dropped
+    device:     Device,
+}
+
+impl EmbeddingMethod for StaticModel {
+    /// Loads the lookup table and tokenizer from local, the lookup table is memory-mapped and loaded as dtype, the looked up rows are pooled in f32
+    fn new(model_file: &Path, config_file: &Path, tokenizer_file: &Path, device: Device, dtype: DType) -> Result<Self, EmbeddingError> {
+        // Load model configuration, {"model_type": "model2vec", "hidden_dim": 256, "normalize": true}
+        let config: serde_json::Value = serde_json::from_str(&read_to_string(config_file)?).map_err(|e| EmbeddingError::JsonFromStrError{error: e})?;
+        let normalize = config.get("normalize").and_then(|n| n.as_bool()).unwrap_or(true);
+
+        // Load lookup table
+        let vb = mmap_var_builder(model_file, dtype, &device)?;
+        if !vb.contains_tensor("embeddings") {
+            return Err(EmbeddingError::ParaError{para: format!("tensor embeddings not found in {}", model_file.display())})
+        }
+        let embeddings = vb.get_unchecked("embeddings")?;
+        let weights = match vb.contains_tensor("weights") {
+            true  => Some(vb.get_unchecked("weights")?),
+            false => None,
+        };
+        let mapping = match vb.contains_tensor("mapping") {
+            true  => Some(vb.get_unchecked_dtype("mapping", DType::U32)?.to_vec1::<u32>()?),
+            false => None,
+        };
+
+        // Load model tokenizer, static models are trained without padding and special tokens
+        let mut tokenizer = Tokenizer::from_file(tokenizer_file).map_err(|e| EmbeddingError::TokenizerFromFileError{error: e})?;
+        tokenizer.with_padding(None);
+        let unk_id = tokenizer.token_to_id("[UNK]").or_else(|| tokenizer.token_to_id("<unk>"));
+
+        Ok(Self { embeddings, weights, mapping, tokenizer: Box::new(tokenizer), normalize, unk_id, device })
+    }
+
+    /// Generates embeddings for a batch of sentences
+    fn get_embeddings(&self, sentences: &[&str]) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        // encode the given inputs without special tokens
+        let encodings = self.tokenizer.encode_batch(sentences.to_vec(), false).map_err(|e| EmbeddingError::TokenizerEncodeError{error: e})?;
+
+        let dim = self.embeddings.dim(1)?;
+        let mut pooled: Vec<Tensor> = Vec::with_capacity(encodings.len());
+        for encoding in &encodings {
+            let ids: Vec<u32> = encoding.get_ids().iter().filter(|i| Some(**i) != self.unk_id).copied().collect();
+            if ids.is_empty() { // empty text or only unknown tokens
+                pooled.push(Tensor::zeros(dim, DType::F32, &self.device)?);
+                continue
+            }
+            let rows: Vec<u32> = match &self.mapping {
+                Some(m) => ids.iter().map(|i| m.get(*i as usize).copied().unwrap_or(*i)).collect(),
+                None => ids.clone(),
+            };
+            let ids = Tensor::new(ids.as_slice(), &self.device)?;
+            let rows = Tensor::new(rows.as_slice(), &self.device)?;
+
+            // look up token embeddings [tokens, dim], then mean pooling
+            let mut token_embeddings = self.embeddings.index_select(&rows, 0)?.to_dtype(DType::F32)?;
+            if let Some(w) = &self.weights {
+                token_embeddings = token_embeddings.broadcast_mul(&w.index_select(&ids, 0)?.to_dtype(DType::F32)?.unsqueeze(1)?)?;
+            }
+            pooled.push(token_embeddings.mean(0)?);
+        }
+        let embeddings = Tensor::stack(&pooled, 0)?;
+
+        // L2 normalization, convert Tensor [batch, dim] to Vec<Vec<f32>>
+        // zero vectors stay zero
+        if self.normalize {
+            Ok(normalize(&embeddings)?.into_iter().map(|v| v.into_iter().map(|x| if x.is_nan() { 0.0 } else { x }).collect()).collect())
+        } else {
+            Ok(embeddings.to_vec2::<f32>()?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    /// tiny static model: 3 tokens of dim 2, [UNK] is row 0
+    fn model_files(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("snippets-model2vec-{}-{}", name, std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let tensors = HashMap::from([
+            ("embeddings".to_string(), Tensor::new(&[[0f32, 0.0], [3.0, 0.0], [0.0, 4.0]], &Device::Cpu).unwrap()),
+            ("mapping".to_string(), Tensor::new(&[0i64, 2, 1], &Device::Cpu).unwrap()), // a and b swap rows
+        ]);
+        candle_core::safetensors::save(&tensors, dir.join("model.safetensors")).unwrap();
+        write(dir.join("config.json"), r#"{"model_type": "model2vec", "hidden_dim": 2, "normalize": false}"#).unwrap();
+        write(dir.join("tokenizer.json"), r#"{"version": "1.0", "truncation": null, "padding": null, "added_tokens": [], "normalizer": null, "pre_tokenizer": {"type": "Whitespace"}, "post_processor": null, "decoder": null, "model": {"type": "WordLevel", "vocab": {"[UNK]": 0, "a": 1, "b": 2}, "unk_token": "[UNK]"}}"#).unwrap();
+        dir
+    }
+
+    #[test]
+    fn lookup_in_any_dtype() {
+        let dir = model_files("dtype");
+        for dtype in [DType::F32, DType::F16, DType::BF16] {
+            let model = StaticModel::new(&dir.join("model.safetensors"), &dir.join("config.json"), &dir.join("tokenizer.json"), Device::Cpu, dtype).unwrap();
+            assert_eq!(model.embeddings.dtype(), dtype);
+            // a -> row 2, b -> row 1, unknown tokens are dropped
+            let embeddings = model.get_embeddings(&["a", "a b", "zzz"]).unwrap();
+            assert_eq!(embeddings, vec![vec![0.0, 4.0], vec![1.5, 2.0], vec![0.0, 0.0]]);
+        }
+        remove_dir_all(&dir).unwrap();
+    }
+}
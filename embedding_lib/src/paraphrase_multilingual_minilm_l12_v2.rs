@@ -5,9 +5,7 @@ use candle_core::{
     Device,
     DType,
     //IndexOp,
-    Tensor,
 };
-use candle_nn::VarBuilder;
 use candle_transformers::models::bert::{BertModel, Config};
 use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
 
@@ -15,6 +13,9 @@ use crate::{
     error::EmbeddingError,
     EmbeddingMethod,
     average_pool,
+    encode_batch,
+    mmap_var_builder,
+    normalize,
 };
 
 /// embedding model
@@ -26,14 +27,12 @@ pub struct ParaphraseMultilingualMiniLmL12V2Model {
 
 impl EmbeddingMethod for ParaphraseMultilingualMiniLmL12V2Model {
     /// Loads the model and tokenizer from local
-    fn new(model_file: &Path, config_file: &Path, tokenizer_file: &Path, device: Device) -> Result<Self, EmbeddingError> {
+    fn new(model_file: &Path, config_file: &Path, tokenizer_file: &Path, device: Device, dtype: DType) -> Result<Self, EmbeddingError> {
         // Load model configuration
         let config: Config = serde_json::from_str(&read_to_string(config_file)?).map_err(|e| EmbeddingError::JsonFromStrError{error: e})?;
 
         // Load model weights
-        let vb = unsafe {
-            VarBuilder::from_mmaped_safetensors(&[model_file], DType::F32, &device)?
-        };
+        let vb = mmap_var_builder(model_file, dtype, &device)?;
 
         // init modern bert
         let model = BertModel::load(vb, &config)?;
@@ -59,39 +58,18 @@ impl EmbeddingMethod for ParaphraseMultilingualMiniLmL12V2Model {
         Ok(Self { model, tokenizer, device })
     }
 
-    /// Generates embedding for a sentence
-    fn get_embedding(&self, sentence: &str) -> Result<Vec<f32>, EmbeddingError> {
-        // encode the given input, accepts both single sequences, as well as pair sequences.
-        // A sequence can be a string, or already pre-tokenized input directly
-        let tokens = self.tokenizer.encode(sentence, true).map_err(|e| EmbeddingError::TokenizerEncodeError{error: e})?;
-
-        // Convert the tokenized data (Vec<u32>) into Candle Tensors
-        let token_ids: Vec<u32> = tokens.get_ids().to_vec();
-        let token_ids = Tensor::new(token_ids, &self.device)?.unsqueeze(0)?;
-
-        let token_type_ids: Vec<u32> = tokens.get_type_ids().to_vec();
-        let token_type_ids = Tensor::new(token_type_ids, &self.device)?.unsqueeze(0)?;
+    /// Generates embeddings for a batch of sentences
+    fn get_embeddings(&self, sentences: &[&str]) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        // encode the given inputs, padding to the longest sentence
+        let (token_ids, token_type_ids, attention_mask) = encode_batch(&self.tokenizer, sentences, &self.device)?;
 
-        // forward pass
-        let last_hidden_state = self.model.forward(&token_ids, &token_type_ids, None)?.to_dtype(DType::F32)?;
+        // forward pass, padding tokens are masked
+        let last_hidden_state = self.model.forward(&token_ids, &token_type_ids, Some(&attention_mask))?.to_dtype(DType::F32)?;
 
-        // average pooling
-        let attention_mask = tokens.get_attention_mask().to_vec();
-        let attention_mask = Tensor::new(attention_mask, &self.device)?.unsqueeze(0)?;
+        // average pooling over real tokens
         let query_embeddings = average_pool(&last_hidden_state, &attention_mask)?;
 
-        // Pooling and Normalization
-        // `model_output[0][:, 0]` becomes `last_hidden_state.i((.., 0))?`
-        // This performs CLS Pooling by taking the embedding of the first token (`[CLS]`).
-        // The `..` syntax means "select all elements on this axis" (the batch axis).
-        //let query_embeddings = last_hidden_state.i((.., 0))?;
-
-        // Manually implement L2 normalization
-        // The formula is `x / sqrt(sum(x^2))`
-        let norms = query_embeddings.sqr()?.sum_keepdim(1)?.sqrt()?;
-        let normalized_embeddings = query_embeddings.broadcast_div(&norms)?;
-
-        // convert Tensor [1, 384] to Vec<f32>
-        Ok(normalized_embeddings.squeeze(0)?.to_vec1::<f32>()?)
+        // L2 normalization, convert Tensor [batch, 384] to Vec<Vec<f32>>
+        normalize(&query_embeddings)
     }
 }
@@ -0,0 +1,259 @@
+use std::path::Path;
+#[cfg(feature = "embedding")]
+use std::fs::read_to_string;
+
+#[cfg(feature = "embedding")]
+use candle_core::{
+    Device,
+    DType,
+    IndexOp,
+    Module,
+    Tensor,
+};
+#[cfg(feature = "embedding")]
+use candle_nn::{Linear, linear};
+#[cfg(feature = "embedding")]
+use candle_transformers::models::{
+    bert::{self, BertModel},
+    xlm_roberta::{self, XLMRobertaForSequenceClassification},
+};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "embedding")]
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+use crate::{
+    Model,
+    ModelFiles,
+    error::EmbeddingError,
+    hf_cache_dir,
+    hf_snapshot,
+};
+#[cfg(feature = "embedding")]
+use crate::{
+    ModelDtype,
+    manifest::Architecture,
+    mmap_var_builder,
+    select_device,
+};
+
+/// cross-encoder model path name
+#[derive(Clone, Deserialize, Serialize, Hash, PartialEq, Eq)]
+pub enum Reranker {
+    MsMarcoMiniLmL6V2,
+    MsMarcoMiniLmL12V2,
+    BgeRerankerBase,
+    BgeRerankerV2M3,
+    Custom(String), // other cross-encoder folder, value: folder name
+}
+
+/// organization of each supported cross-encoder on Hugging Face, same order as RERANKER_NAME
+const RERANKER_ORG: &[&str;4] = &[
+    "cross-encoder",
+    "cross-encoder",
+    "BAAI",
+    "BAAI",
+];
+
+/// all supported cross-encoder name
+const RERANKER_NAME: &[&str;4] = &[
+    "ms-marco-MiniLM-L6-v2", // https://huggingface.co/cross-encoder/ms-marco-MiniLM-L6-v2
+    "ms-marco-MiniLM-L12-v2", // https://huggingface.co/cross-encoder/ms-marco-MiniLM-L12-v2
+    "bge-reranker-base", // https://huggingface.co/BAAI/bge-reranker-base
+    "bge-reranker-v2-m3", // https://huggingface.co/BAAI/bge-reranker-v2-m3
+];
+
+impl Reranker {
+    /// create from --rerank id
+    pub fn new(id: usize) -> Result<Self, EmbeddingError> {
+        match id {
+            1 => Ok(Self::MsMarcoMiniLmL6V2),
+            2 => Ok(Self::MsMarcoMiniLmL12V2),
+            3 => Ok(Self::BgeRerankerBase),
+            4 => Ok(Self::BgeRerankerV2M3),
+            _ => Err(EmbeddingError::ParaError{para: format!("--rerank not support this model: {}", id)}),
+        }
+    }
+
+    /// find cross-encoder by --rerank value: id (1~4), built-in model name, or name of any cross-encoder folder under base_path
+    pub fn find(name: &str, base_path: &Path) -> Result<(Reranker, ModelFiles), EmbeddingError> {
+        let reranker = match name.parse::<usize>() {
+            Ok(id) => Reranker::new(id)?,
+            Err(_) => match RERANKER_NAME.iter().position(|n| *n == name) {
+                Some(index) => Reranker::new(index+1)?,
+                None => Reranker::Custom(name.to_string()),
+            },
+        };
+        let files = Model::check_model_files(&base_path.join(reranker.name()))?;
+        Ok((reranker, files))
+    }
+
+    /// find built-in cross-encoder in the Hugging Face cache, offline
+    /// name: id (1~4), model name, or Hugging Face repo id (e.g. BAAI/bge-reranker-base)
+    pub fn find_in_hf_cache(name: &str) -> Result<(Reranker, ModelFiles), EmbeddingError> {
+        let reranker = match name.parse::<usize>() {
+            Ok(id) => Reranker::new(id)?,
+            Err(_) => {
+                let short_name = name.rsplit('/').next().unwrap_or(name);
+                match RERANKER_NAME.iter().position(|n| *n == short_name) {
+                    Some(index) => Reranker::new(index+1)?,
+                    None => return Err(EmbeddingError::ParaError{para: format!("{} is not a built-in cross-encoder, only built-in models are searched in the Hugging Face cache", name)}),
+                }
+            },
+        };
+        let repo_id = reranker.repo_id().unwrap_or_default();
+        let cache_dir = hf_cache_dir().ok_or(EmbeddingError::ParaError{para: "couldn't find the Hugging Face cache, set HF_HOME or HF_HUB_CACHE".to_string()})?;
+        let snapshot = hf_snapshot(&cache_dir, &repo_id).ok_or(EmbeddingError::ParaError{para: format!("couldn't find {} in the Hugging Face cache {}", repo_id, cache_dir.display())})?;
+        Ok((reranker, Model::check_model_files(&snapshot)?))
+    }
+
+    /// Hugging Face repo id of built-in cross-encoder, e.g. BAAI/bge-reranker-base
+    pub fn repo_id(&self) -> Option<String> {
+        let index = RERANKER_NAME.iter().position(|n| *n == self.name())?;
+        Some(format!("{}/{}", RERANKER_ORG[index], RERANKER_NAME[index]))
+    }
+
+    /// get model name, same as the model folder name
+    pub fn name(&self) -> &str {
+        match self {
+            Self::MsMarcoMiniLmL6V2  => RERANKER_NAME[0],
+            Self::MsMarcoMiniLmL12V2 => RERANKER_NAME[1],
+            Self::BgeRerankerBase    => RERANKER_NAME[2],
+            Self::BgeRerankerV2M3    => RERANKER_NAME[3],
+            Self::Custom(name)       => name,
+        }
+    }
+}
+
+/// transformer with classification head, architecture is model_type in config.json
+#[cfg(feature = "embedding")]
+enum Classifier {
+    Bert{bert: BertModel, pooler: Linear, classifier: Linear}, // BertForSequenceClassification, e.g. ms-marco-MiniLM
+    XlmRoberta(XLMRobertaForSequenceClassification),            // XLMRobertaForSequenceClassification, e.g. bge-reranker
+}
+
+/// cross-encoder, scores (query, document) pairs jointly, slower but more accurate than comparing embeddings
+#[cfg(feature = "embedding")]
+pub struct CrossEncoder {
+    model:     Classifier,
+    tokenizer: Tokenizer,
+    device:    Device,
+}
+
+#[cfg(feature = "embedding")]
+impl CrossEncoder {
+    /// Loads the model and tokenizer from local, weights are memory-mapped and loaded as dtype
+    pub fn load(model_file: &Path, config_file: &Path, tokenizer_file: &Path, use_cpu: bool, dtype: ModelDtype) -> Result<Self, EmbeddingError> {
+        let device = select_device(use_cpu)?;
+        let config = read_to_string(config_file)?;
+        let model_type: serde_json::Value = serde_json::from_str(&config).map_err(|e| EmbeddingError::JsonFromStrError{error: e})?;
+        let num_labels = model_type.get("id2label").and_then(|l| l.as_object()).map(|l| l.len()).unwrap_or(1);
+        let model_type = model_type.get("model_type").and_then(|t| t.as_str()).unwrap_or_default().to_string();
+
+        // Load model weights, f32 if the architecture can't run in dtype
+        let architecture = if model_type == "bert" { Architecture::Bert } else { Architecture::XlmRoberta };
+        let vb = mmap_var_builder(model_file, dtype.resolve(&architecture, &device), &device)?;
+
+        // init model
+        let (model, max_position_embeddings) = match model_type.as_str() {
+            "bert" => {
+                let config: bert::Config = serde_json::from_str(&config).map_err(|e| EmbeddingError::JsonFromStrError{error: e})?;
+                let bert_vb = if vb.contains_tensor("bert.embeddings.word_embeddings.weight") { vb.pp("bert") } else { vb.clone() };
+                let pooler = linear(config.hidden_size, config.hidden_size, bert_vb.pp("pooler").pp("dense"))?;
+                let classifier = linear(config.hidden_size, num_labels, vb.pp("classifier"))?;
+                (Classifier::Bert{bert: BertModel::load(bert_vb, &config)?, pooler, classifier}, config.max_position_embeddings)
+            },
+            "xlm-roberta" => {
+                let config: xlm_roberta::Config = serde_json::from_str(&config).map_err(|e| EmbeddingError::JsonFromStrError{error: e})?;
+                (Classifier::XlmRoberta(XLMRobertaForSequenceClassification::new(num_labels, &config, vb)?), config.max_position_embeddings)
+            },
+            t => return Err(EmbeddingError::ParaError{para: format!("cross-encoder only support bert and xlm-roberta, not {:?} in {}", t, config_file.display())}),
+        };
+
+        // Load model tokenizer
+        let mut tokenizer = Tokenizer::from_file(tokenizer_file).map_err(|e| EmbeddingError::TokenizerFromFileError{error: e})?;
+
+        // tokenizer truncation, the longer one of query and document is truncated first
+        let truncation_params = TruncationParams {
+            max_length: max_position_embeddings.min(512),
+            ..Default::default()
+        };
+        let _ = tokenizer.with_truncation(Some(truncation_params));
+
+        // tokenizer padding
+        let padding_params = PaddingParams {
+            strategy: tokenizers::PaddingStrategy::BatchLongest,
+            ..Default::default()
+        };
+        tokenizer.with_padding(Some(padding_params));
+
+        Ok(Self { model, tokenizer, device })
+    }
+
+    /// relevance score of each document to the query, in (0, 1), higher is more relevant
+    pub fn score(&self, query: &str, documents: &[&str]) -> Result<Vec<f32>, EmbeddingError> {
+        if documents.is_empty() {
+            return Ok(Vec::new())
+        }
+        // encode (query, document) pairs, padding to the longest pair
+        let pairs: Vec<(&str, &str)> = documents.iter().map(|d| (query, *d)).collect();
+        let encodings = self.tokenizer.encode_batch(pairs, true).map_err(|e| EmbeddingError::TokenizerEncodeError{error: e})?;
+        let mut token_ids = Vec::with_capacity(encodings.len());
+        let mut token_type_ids = Vec::with_capacity(encodings.len());
+        let mut attention_mask = Vec::with_capacity(encodings.len());
+        for encoding in &encodings {
+            token_ids.push(Tensor::new(encoding.get_ids(), &self.device)?);
+            token_type_ids.push(Tensor::new(encoding.get_type_ids(), &self.device)?);
+            attention_mask.push(Tensor::new(encoding.get_attention_mask(), &self.device)?);
+        }
+        let token_ids = Tensor::stack(&token_ids, 0)?;
+        let token_type_ids = Tensor::stack(&token_type_ids, 0)?;
+        let attention_mask = Tensor::stack(&attention_mask, 0)?;
+
+        // forward pass, logits: [batch, num_labels]
+        let logits = match &self.model {
+            Classifier::Bert{bert, pooler, classifier} => {
+                let last_hidden_state = bert.forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
+                let pooled = pooler.forward(&last_hidden_state.i((.., 0))?)?.tanh()?;
+                classifier.forward(&pooled)?
+            },
+            Classifier::XlmRoberta(model) => model.forward(&token_ids, &attention_mask, &token_type_ids)?,
+        }.to_dtype(DType::F32)?;
+
+        // relevance is the first label, sigmoid to (0, 1)
+        let scores = candle_nn::ops::sigmoid(&logits.i((.., 0))?)?;
+        Ok(scores.to_vec1::<f32>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    #[test]
+    fn find_by_id_name_or_folder() {
+        let base = std::env::temp_dir().join(format!("snippets-reranker-{}", std::process::id()));
+        let _ = remove_dir_all(&base);
+        for name in ["bge-reranker-base", "my-reranker"] {
+            create_dir_all(base.join(name)).unwrap();
+            for f in ["model.safetensors", "config.json", "tokenizer.json"] {
+                write(base.join(name).join(f), "").unwrap();
+            }
+        }
+        assert!(Reranker::find("3", &base).unwrap().0 == Reranker::BgeRerankerBase);
+        assert!(Reranker::find("bge-reranker-base", &base).unwrap().0 == Reranker::BgeRerankerBase);
+        assert!(Reranker::find("my-reranker", &base).unwrap().0 == Reranker::Custom("my-reranker".to_string()));
+        assert!(Reranker::find("1", &base).is_err()); // not downloaded
+        assert!(Reranker::find("5", &base).is_err());
+        remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn repo_ids() {
+        assert_eq!(Reranker::MsMarcoMiniLmL6V2.repo_id().unwrap(), "cross-encoder/ms-marco-MiniLM-L6-v2");
+        assert_eq!(Reranker::BgeRerankerV2M3.repo_id().unwrap(), "BAAI/bge-reranker-v2-m3");
+        assert_eq!(Reranker::Custom("my-reranker".to_string()).repo_id(), None);
+        // only built-in cross-encoders are searched in the Hugging Face cache
+        assert!(Reranker::find_in_hf_cache("org/my-reranker").err().unwrap().to_string().contains("not a built-in cross-encoder"));
+    }
+}
@@ -1,40 +1,65 @@
 use std::collections::{HashMap, HashSet};
+use std::env;
 use std::path::{Path, PathBuf};
 use std::fs::{self, create_dir_all};
 
 use ron::de::from_str;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 
+#[cfg(feature = "embedding")]
+use embedding_lib::{
+    CHUNK_OVERLAP,
+    CHUNK_WORDS,
+    EmbedMode,
+    chunk_text,
+    truncate_embedding,
+};
 use embedding_lib::{
     Model,
     ModelType,
+    snippet_id,
     split_discription,
 };
 
+/// the number of snippets embedded in one forward pass, you can set the environment variable SNIPPETS_BATCH_SIZE to change it
+#[cfg(feature = "embedding")]
+const DEFAULT_BATCH_SIZE: usize = 32;
+
 /// single snippet
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize)]
 struct SingleSnippet {
+    #[serde(default)]
+    id:          String,                           // id in the built-in namespace, default: discription in lowercase, words joined by '-'
     tags:        HashSet<String>,                  // one snippet could have multiple tag
     discription: String,                           // discription of this snippet
     embedding:   Option<HashMap<Model, Vec<f32>>>, // discription's embedding, key: model, value: embedding vec
+    #[serde(default)]
+    embedding_prompt: Option<HashMap<Model, String>>, // document prompt used to calculate the embedding, key: model, value: prompt, missing means raw discription
+    #[serde(default)]
+    content_embedding: Option<HashMap<Model, Vec<Vec<f32>>>>, // embedding of each content chunk, key: model, value: chunk embeddings, only if SNIPPETS_CONTENT_EMBEDDING="true"
     content:     String,                           // snippet content
 }
 
-/// merge all ../snippets_database/*.snippets to ../snippets_database/default.snippets
+// BINARY_MAGIC and the vector codes, shared with the reader in the program
+include!("src/api/binary_codes.rs");
+
+/// merge all ../snippets_database/*.snippets to ../snippets_database/default.bin
 /// create ../snippets_database/enum.rs based on all *.snippets tags
 /// verbose: cargo build --release -vv
 fn main() {
+    println!("cargo:rerun-if-env-changed=SNIPPETS_BATCH_SIZE");
+    println!("cargo:rerun-if-env-changed=SNIPPETS_CONTENT_EMBEDDING");
+    println!("cargo:rerun-if-env-changed=SNIPPETS_QUANTIZE");
+    println!("cargo:rerun-if-env-changed=SNIPPETS_MATRYOSHKA_DIM");
     // get all embedding models from ../embedding_models
     let mut all_models: Vec<(ModelType, Model, (PathBuf, PathBuf, PathBuf))> = Vec::new(); // (Model, (model.safetensors, config.json, tokenizer.json))
     let model_path = PathBuf::from("../embedding_models");
     if model_path.exists() && model_path.is_dir() {
         if let Ok(dirs) = model_path.read_dir() {
-            for i in dirs {
-                if let Ok(entry) = i {
-                    let tmp_path = entry.path();
-                    if let Ok(Some(m)) = Model::check_model(&tmp_path) {
-                        all_models.push(m);
-                    }
+            for entry in dirs.flatten() {
+                let tmp_path = entry.path();
+                if let Ok(Some(m)) = Model::check_model(&tmp_path) {
+                    all_models.push(m);
                 }
             }
         }
@@ -45,16 +70,14 @@ fn main() {
     let tmp_dir = Path::new("../snippets_database/");
     if tmp_dir.exists() && tmp_dir.is_dir() {
         if let Ok(dirs) = tmp_dir.read_dir() {
-            for i in dirs {
-                if let Ok(entry) = i {
-                    let tmp_file_path = entry.path();
-                    if tmp_file_path.is_file() {
-                        if let (Some(name), Some(ext)) = (tmp_file_path.file_name(), tmp_file_path.extension()) {
-                            if name != "default.snippets" && name != "example.snippets" && ext == "snippets" {
-                                let (tags, snippets) = read_file_as_snippets(&tmp_file_path, &all_models);
-                                all_tags.extend(tags);
-                                all_snippets.extend(snippets);
-                            }
+            for entry in dirs.flatten() {
+                let tmp_file_path = entry.path();
+                if tmp_file_path.is_file() {
+                    if let (Some(name), Some(ext)) = (tmp_file_path.file_name(), tmp_file_path.extension()) {
+                        if name != "default.snippets" && name != "example.snippets" && ext == "snippets" { // default.snippets was created by older versions
+                            let (tags, snippets) = read_file_as_snippets(&tmp_file_path, &all_models);
+                            all_tags.extend(tags);
+                            all_snippets.extend(snippets);
                         }
                     }
                 }
@@ -66,16 +89,15 @@ fn main() {
         }
     }
 
-    // save default.snippets
-    let f = fs::File::options()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open("../snippets_database/default.snippets")
-        .expect("Error - Failed opening file ../snippets_database/default.snippets");
-    ron::Options::default()
-        .to_io_writer_pretty(f, &all_snippets, ron::ser::PrettyConfig::new().escape_strings(false).compact_arrays(true))
-        .expect("Error - Failed to write to file ../snippets_database/default.snippets");
+    // save default.bin
+    // stored embeddings are quantized if SNIPPETS_QUANTIZE="int8" or "binary"
+    let quantize = match env::var("SNIPPETS_QUANTIZE").map(|q| q.to_lowercase()).as_deref() {
+        Ok("int8") => 1,
+        Ok("binary") => 2,
+        Ok("") | Err(_) => 0,
+        Ok(q) => panic!("SNIPPETS_QUANTIZE only support int8 and binary, not {}", q),
+    };
+    fs::write("../snippets_database/default.bin", binary_database(&all_snippets, quantize)).expect("Error - save ../snippets_database/default.bin failed");
     // save enum
     if all_tags.is_empty() { // default tags
         all_tags = HashSet::from([
@@ -115,6 +137,7 @@ pub enum SnipTag {{
 
 impl SnipTag {{
     /// tag to string
+    #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {{
         match self {{
             {},
@@ -183,57 +206,197 @@ where
     fs::write("../snippets_database/tags.rs", enum_str).expect("Error - save ../snippets_database/tags.rs failed");
 }
 
+/// encode snippets to the compact binary database, all numbers are little-endian, str is u32 byte length + UTF-8
+/// text section:
+///     u32 snippet number, each snippet: id (str), u32 tag number, tags (str), discription (str), content (str)
+/// embedding sections:
+///     u32 model number, each model: model (RON str), u32 dim, u8 quantization, u64 section byte length, then each snippet in text section order:
+///     document prompt (str), u8 has discription embedding, if 1: one vector, u32 content chunk number, chunk number vectors
+/// vector: quantization 0: dim f32, 1 (int8): f32 scale and dim i8, x ≈ scale * code, 2 (binary): dim / 8 bytes, bit i of byte i / 8 is 1 if x > 0
+/// the program only parses the text section at startup, a model section is decoded only when semantic search uses this model
+fn binary_database(snippets: &[SingleSnippet], quantize: u8) -> Vec<u8> {
+    fn put_str(buf: &mut Vec<u8>, s: &str) {
+        buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        buf.extend_from_slice(s.as_bytes());
+    }
+    let put_vec = |buf: &mut Vec<u8>, v: &[f32]| {
+        match quantize {
+            1 => { // int8
+                let (scale, codes) = quantize_int8(v);
+                buf.extend_from_slice(&scale.to_le_bytes());
+                buf.extend(codes.iter().map(|c| *c as u8));
+            },
+            2 => buf.extend_from_slice(&quantize_binary(v)), // binary, sign bits
+            _ => {
+                for x in v {
+                    buf.extend_from_slice(&x.to_le_bytes());
+                }
+            },
+        }
+    };
+
+    let mut buf: Vec<u8> = BINARY_MAGIC.to_vec();
+    // text section
+    buf.extend_from_slice(&(snippets.len() as u32).to_le_bytes());
+    for s in snippets {
+        put_str(&mut buf, &s.id);
+        let mut tags: Vec<&String> = s.tags.iter().collect();
+        tags.sort();
+        buf.extend_from_slice(&(tags.len() as u32).to_le_bytes());
+        for t in tags {
+            put_str(&mut buf, t);
+        }
+        put_str(&mut buf, &s.discription);
+        put_str(&mut buf, &s.content);
+    }
+
+    // all models and their dimension, from the first stored embedding
+    let mut models: Vec<(Model, usize)> = Vec::new();
+    for s in snippets {
+        let discription = s.embedding.iter().flatten().map(|(m, e)| (m, e.len()));
+        let content = s.content_embedding.iter().flatten().filter_map(|(m, e)| e.first().map(|c| (m, c.len())));
+        for (m, dim) in discription.chain(content) {
+            if !models.iter().any(|(model, _)| model == m) {
+                models.push((m.clone(), dim));
+            }
+        }
+    }
+
+    // one embedding section per model, embeddings with another dimension are dropped
+    buf.extend_from_slice(&(models.len() as u32).to_le_bytes());
+    for (model, dim) in &models {
+        let mut section: Vec<u8> = Vec::new();
+        for s in snippets {
+            put_str(&mut section, s.embedding_prompt.as_ref().and_then(|p| p.get(model)).map(|p| p.as_str()).unwrap_or(""));
+            match s.embedding.as_ref().and_then(|e| e.get(model)).filter(|e| e.len() == *dim) {
+                Some(e) => {
+                    section.push(1);
+                    put_vec(&mut section, e);
+                },
+                None => section.push(0),
+            }
+            let chunks: Vec<&Vec<f32>> = s.content_embedding.as_ref().and_then(|e| e.get(model)).map(|e| e.iter().filter(|c| c.len() == *dim).collect()).unwrap_or_default();
+            section.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+            for c in chunks {
+                put_vec(&mut section, c);
+            }
+        }
+        put_str(&mut buf, &ron::to_string(model).expect("Error - serialize model name failed"));
+        buf.extend_from_slice(&(*dim as u32).to_le_bytes());
+        buf.push(quantize);
+        buf.extend_from_slice(&(section.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&section);
+    }
+    buf
+}
+
 /// read file to string, skip first line and last line
 fn read_file_as_snippets(
     file_path: &Path,
     #[cfg_attr(not(feature = "embedding"), allow(unused_variables))]
-    all_models: &Vec<(ModelType, Model, (PathBuf, PathBuf, PathBuf))>,
+    all_models: &[(ModelType, Model, (PathBuf, PathBuf, PathBuf))],
 ) -> (HashSet<String>, Vec<SingleSnippet>) {
-    let content = fs::read_to_string(file_path).expect(&format!("Error - read file to string failed: {}", file_path.display()));
+    let content = fs::read_to_string(file_path).unwrap_or_else(|e| panic!("Error - read file to string failed: {}: {}", file_path.display(), e));
     // calculate embedding
     let mut tags: HashSet<String> = HashSet::new();
     let snippets = match from_str::<Vec<SingleSnippet>>(&content) {
         Ok(mut snippets) => {
             // if use embedding features in Cargo.toml, calculate embeddings
             #[cfg(feature = "embedding")]
+            let batch_size = env::var("SNIPPETS_BATCH_SIZE").ok().and_then(|b| b.parse::<usize>().ok()).filter(|b| *b > 0).unwrap_or(DEFAULT_BATCH_SIZE);
+            // content embeddings make the database larger, only calculate them if SNIPPETS_CONTENT_EMBEDDING="true"
+            #[cfg(feature = "embedding")]
+            let content_embedding = env::var("SNIPPETS_CONTENT_EMBEDDING").map(|c| c == "true").unwrap_or(false);
+            // embeddings of Matryoshka models are truncated to the first SNIPPETS_MATRYOSHKA_DIM dimensions and renormalized
+            #[cfg(feature = "embedding")]
+            let matryoshka_dim = env::var("SNIPPETS_MATRYOSHKA_DIM").ok().and_then(|d| d.parse::<usize>().ok()).filter(|d| *d > 0);
+            #[cfg(feature = "embedding")]
             for (model_type, model, (model_path, config_path, tokenizer_path)) in all_models {
                 // load embedding model
                 match embedding_lib::EmbeddingModel::load_model(
-                    &model_type,
-                    &model_path, // model.safetensors
-                    &config_path, // config.json
-                    &tokenizer_path, // tokenizer.json
+                    model_type,
+                    model_path, // model.safetensors
+                    config_path, // config.json
+                    tokenizer_path, // tokenizer.json
                     false, // use cpu
+                    embedding_lib::ModelDtype::F32, // stored embeddings are calculated in full precision
                 ) {
                     Ok(embedding_model) => {
-                        for i in 0..snippets.len() {
-                            match &snippets[i].embedding {
-                                Some(snip_embed) => {
-                                    if let Ok(embedding) = embedding_model.get_embedding(&snippets[i].discription) {
-                                        if !snip_embed.contains_key(&model) {
-                                            if let Some(snip_embed) = &mut snippets[i].embedding {
-                                                snip_embed.insert(model.clone(), embedding);
-                                            }
-                                        }
+                        // snippets without this model's embedding, or the embedding was calculated with another prompt (stale)
+                        let prompt = model_type.prompt(EmbedMode::Document);
+                        let stale: Vec<bool> = snippets.iter()
+                            .map(|s| s.embedding_prompt.as_ref().and_then(|p| p.get(model)).map(|p| p.as_str()).unwrap_or("") != prompt)
+                            .collect();
+                        let missing: Vec<usize> = snippets.iter()
+                            .enumerate()
+                            .filter(|(i, s)| s.embedding.as_ref().map(|e| !e.contains_key(model)).unwrap_or(true) || stale[*i])
+                            .map(|(i, _)| i)
+                            .collect();
+                        // content chunks of snippets without this model's content embedding, (snippet index, chunk text)
+                        let missing_chunks: Vec<(usize, String)> = if content_embedding {
+                            snippets.iter()
+                                .enumerate()
+                                .filter(|(i, s)| s.content_embedding.as_ref().map(|e| !e.contains_key(model)).unwrap_or(true) || stale[*i])
+                                .flat_map(|(i, s)| chunk_text(&s.content, CHUNK_WORDS, CHUNK_OVERLAP).into_iter().map(move |c| (i, c)))
+                                .collect()
+                        } else {
+                            Vec::new()
+                        };
+                        for (i, s) in snippets.iter_mut().enumerate() {
+                            if let Some(e) = s.content_embedding.as_mut().filter(|_| stale[i] || !content_embedding) {
+                                e.remove(model);
+                            }
+                        }
+                        // one forward pass per batch
+                        for batch in missing.chunks(batch_size) {
+                            let sentences: Vec<&str> = batch.iter().map(|i| snippets[*i].discription.as_str()).collect();
+                            match embedding_model.get_embeddings(&sentences, EmbedMode::Document) {
+                                Ok(embeddings) => {
+                                    for (i, embedding) in batch.iter().zip(embeddings) {
+                                        snippets[*i].embedding.get_or_insert_with(HashMap::new).insert(model.clone(), embedding);
+                                        snippets[*i].embedding_prompt.get_or_insert_with(HashMap::new).insert(model.clone(), prompt.to_string());
                                     }
                                 },
-                                None => {
-                                    if let Ok(embedding) = embedding_model.get_embedding(&snippets[i].discription) {
-                                        snippets[i].embedding = Some(HashMap::from([(model.clone(), embedding)]));
+                                Err(e) => println!("{}", e),
+                            }
+                        }
+                        for batch in missing_chunks.chunks(batch_size) {
+                            let sentences: Vec<&str> = batch.iter().map(|(_, c)| c.as_str()).collect();
+                            match embedding_model.get_embeddings(&sentences, EmbedMode::Document) {
+                                Ok(embeddings) => {
+                                    for ((i, _), embedding) in batch.iter().zip(embeddings) {
+                                        snippets[*i].content_embedding.get_or_insert_with(HashMap::new).entry(model.clone()).or_default().push(embedding);
                                     }
                                 },
+                                Err(e) => println!("{}", e),
+                            }
+                        }
+                        if let Some(dim) = matryoshka_dim.filter(|_| model_type.matryoshka()) {
+                            for s in snippets.iter_mut() {
+                                if let Some(e) = s.embedding.as_mut().and_then(|e| e.get_mut(model)) {
+                                    *e = truncate_embedding(e, dim);
+                                }
+                                if let Some(chunks) = s.content_embedding.as_mut().and_then(|e| e.get_mut(model)) {
+                                    for c in chunks.iter_mut() {
+                                        *c = truncate_embedding(c, dim);
+                                    }
+                                }
                             }
                         }
                     },
                     Err(e) => println!("{}", e),
                 }
             }
-            for i in 0..snippets.len() {
-                tags.extend(snippets[i].tags.clone());
+            for snippet in snippets.iter_mut() {
+                tags.extend(snippet.tags.clone());
                 // trim space and remove `\r`
-                snippets[i].discription = snippets[i].discription.trim().replace("\r", "");
+                snippet.discription = snippet.discription.trim().replace("\r", "");
+                // id from the discription before it is split
+                if snippet.id.is_empty() {
+                    snippet.id = snippet_id(&snippet.discription);
+                }
                 // split long discription to multiple short lines
-                snippets[i].discription = split_discription(&snippets[i].discription, 20);
+                snippet.discription = split_discription(&snippet.discription, 20);
             }
             snippets
         },
@@ -0,0 +1,306 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use ron::de::from_str;
+
+use crate::{
+    SnipTag,
+    snip::SingleSnippet,
+    utils::SnippetSource,
+    quantize::{
+        Quantization,
+        dequantize_binary,
+        dequantize_int8,
+    },
+    error::MyError,
+};
+
+use embedding_lib::Model;
+
+// BINARY_MAGIC and the vector codes, shared with the writer in build.rs
+include!("binary_codes.rs");
+
+/// compact binary database compiled into the program, written by build.rs
+/// the text section is parsed at startup, the embedding section of a model is decoded only when semantic search uses this model
+pub(crate) struct BinaryDatabase {
+    data:      &'static [u8],
+    sections:  Vec<(Model, usize, Option<Quantization>, Range<usize>)>, // model, dimension, quantization of stored vectors, byte range of its embedding section
+    positions: Vec<Option<usize>>,                                      // index of each snippet in Snippets.data, in database order, None if overridden or hidden
+    loaded:    HashSet<Model>,                                          // models whose embeddings are already decoded
+}
+
+/// little-endian reader of the binary database
+struct Reader {
+    data: &'static [u8],
+    pos:  usize,
+}
+
+impl Reader {
+    /// next n bytes
+    fn take(&mut self, n: usize) -> Result<&'static [u8], MyError> {
+        let bytes = self.data.get(self.pos..self.pos+n).ok_or(MyError::ParaError{para: "the built-in database is truncated, rebuild the program".to_string()})?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, MyError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, MyError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap_or_default()) as usize)
+    }
+
+    fn u64(&mut self) -> Result<usize, MyError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap_or_default()) as usize)
+    }
+
+    /// u32 byte length + UTF-8
+    fn str(&mut self) -> Result<&'static str, MyError> {
+        let len = self.u32()?;
+        std::str::from_utf8(self.take(len)?).map_err(|e| MyError::ParaError{para: format!("the built-in database is not valid UTF-8: {}", e)})
+    }
+
+    fn f32(&mut self) -> Result<f32, MyError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap_or_default()))
+    }
+
+    /// one stored vector, quantized vectors are decoded to approximate f32 vectors
+    /// the f32 vectors are not stored, so --quantize cannot rescore built-in snippets with exact f32 embeddings
+    fn vector(&mut self, dim: usize, quantization: Option<Quantization>) -> Result<Vec<f32>, MyError> {
+        match quantization {
+            None => Ok(self.take(dim * 4)?.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()),
+            Some(Quantization::Int8) => {
+                let scale = self.f32()?;
+                Ok(dequantize_int8(scale, self.take(dim)?))
+            },
+            Some(Quantization::Binary) => Ok(dequantize_binary(self.take(dim.div_ceil(8))?, dim)),
+        }
+    }
+}
+
+impl BinaryDatabase {
+    /// parse the text section and the model table, embeddings are not decoded
+    pub(crate) fn parse(data: &'static [u8]) -> Result<(Self, Vec<SingleSnippet>), MyError> {
+        let mut reader = Reader { data, pos: 0 };
+        if reader.take(BINARY_MAGIC.len())? != BINARY_MAGIC {
+            return Err(MyError::ParaError{para: "the built-in database has an unknown format, rebuild the program".to_string()})
+        }
+
+        // text section
+        let num = reader.u32()?;
+        let mut snippets: Vec<SingleSnippet> = Vec::with_capacity(num);
+        for _ in 0..num {
+            let id = reader.str()?.to_string();
+            let mut tags: HashSet<SnipTag> = HashSet::new();
+            for _ in 0..reader.u32()? {
+                let tag = reader.str()?;
+                tags.insert(SnipTag::string_to_tag(&tag.to_lowercase()).ok_or(MyError::ParaError{para: format!("unknown tag {} in the built-in database", tag)})?);
+            }
+            snippets.push(SingleSnippet {
+                id,
+                tags,
+                discription:       reader.str()?.to_string(),
+                content:           reader.str()?.to_string(),
+                embedding:         None,
+                embedding_prompt:  None,
+                content_embedding: None,
+                source:            None,
+                namespace:         SnippetSource::Builtin.namespace(),
+                overrides:         String::new(),
+                hide:              Vec::new(),
+            });
+        }
+
+        // model table, skip each embedding section
+        let mut sections = Vec::new();
+        for _ in 0..reader.u32()? {
+            let model: Model = from_str(reader.str()?).map_err(|e| MyError::ParaError{para: format!("unknown model in the built-in database: {}", e)})?;
+            let dim = reader.u32()?;
+            let quantization = match reader.u8()? {
+                0 => None,
+                1 => Some(Quantization::Int8),
+                2 => Some(Quantization::Binary),
+                q => return Err(MyError::ParaError{para: format!("unknown quantization {} in the built-in database", q)}),
+            };
+            let len = reader.u64()?;
+            let start = reader.pos;
+            reader.take(len)?;
+            sections.push((model, dim, quantization, start..start+len));
+        }
+
+        let database = Self {
+            data,
+            sections,
+            positions: (0..snippets.len()).map(Some).collect(),
+            loaded:    HashSet::new(),
+        };
+        Ok((database, snippets))
+    }
+
+    /// the number of snippets in the database
+    pub(crate) fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// index of each snippet in Snippets.data after sorting, in database order, None if overridden or hidden
+    pub(crate) fn set_positions(&mut self, positions: Vec<Option<usize>>) {
+        self.positions = positions;
+    }
+
+    /// dimension of the stored embeddings of the model, None if the model is not stored
+    pub(crate) fn dim(&self, model: &Model) -> Option<usize> {
+        self.sections.iter().find(|(m, _, _, _)| m == model).map(|(_, dim, _, _)| *dim)
+    }
+
+    /// quantization of the stored embeddings of the model, None if stored as f32 or the model is not stored
+    pub(crate) fn quantization(&self, model: &Model) -> Option<Quantization> {
+        self.sections.iter().find(|(m, _, _, _)| m == model).and_then(|(_, _, q, _)| *q)
+    }
+
+    /// decode the stored embeddings of the model into the snippets, only once for each model
+    pub(crate) fn load_embeddings(&mut self, model: &Model, snippets: &mut [SingleSnippet]) -> Result<(), MyError> {
+        if self.loaded.contains(model) {
+            return Ok(())
+        }
+        if let Some((_, dim, quantization, range)) = self.sections.iter().find(|(m, _, _, _)| m == model) {
+            let mut reader = Reader { data: &self.data[..range.end], pos: range.start };
+            for i in &self.positions {
+                // vectors of overridden or hidden snippets are read and dropped
+                let prompt = reader.str()?;
+                let embedding = if reader.u8()? == 1 { Some(reader.vector(*dim, *quantization)?) } else { None };
+                let chunks = reader.u32()?;
+                let embeddings = (0..chunks).map(|_| reader.vector(*dim, *quantization)).collect::<Result<Vec<_>, MyError>>()?;
+                let snippet = match i {
+                    Some(i) => &mut snippets[*i],
+                    None => continue,
+                };
+                if let Some(e) = embedding {
+                    snippet.embedding.get_or_insert_with(HashMap::new).insert(model.clone(), e);
+                }
+                if chunks > 0 {
+                    snippet.content_embedding.get_or_insert_with(HashMap::new).insert(model.clone(), embeddings);
+                }
+                if !prompt.is_empty() {
+                    snippet.embedding_prompt.get_or_insert_with(HashMap::new).insert(model.clone(), prompt.to_string());
+                }
+            }
+        }
+        self.loaded.insert(model.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantize::{
+        dequantize_binary,
+        dequantize_int8,
+    };
+
+    /// discription embedding and content chunks of each snippet, same for all models
+    type Vectors = Vec<(Option<Vec<f32>>, Vec<Vec<f32>>)>;
+
+    fn put_u32(buf: &mut Vec<u8>, n: usize) {
+        buf.extend_from_slice(&(n as u32).to_le_bytes());
+    }
+
+    fn put_str(buf: &mut Vec<u8>, s: &str) {
+        put_u32(buf, s.len());
+        buf.extend_from_slice(s.as_bytes());
+    }
+
+    /// one vector in the layout of build.rs
+    fn put_vector(buf: &mut Vec<u8>, v: &[f32], quantization: u8) {
+        match quantization {
+            1 => {
+                let (scale, codes) = quantize_int8(v);
+                buf.extend_from_slice(&scale.to_le_bytes());
+                buf.extend(codes.iter().map(|c| *c as u8));
+            },
+            2 => buf.extend_from_slice(&quantize_binary(v)),
+            _ => buf.extend(v.iter().flat_map(|x| x.to_le_bytes())),
+        }
+    }
+
+    /// database of (id, discription) snippets, one section of the vectors per (model, quantization)
+    fn database(snippets: &[(&str, &str)], vectors: &Vectors, sections: &[(Model, u8)]) -> &'static [u8] {
+        let mut buf = BINARY_MAGIC.to_vec();
+        put_u32(&mut buf, snippets.len());
+        for (id, discription) in snippets {
+            put_str(&mut buf, id);
+            put_u32(&mut buf, 1);
+            put_str(&mut buf, "shell");
+            put_str(&mut buf, discription);
+            put_str(&mut buf, &format!("echo {}", id));
+        }
+        put_u32(&mut buf, sections.len());
+        for (model, quantization) in sections {
+            let mut section = Vec::new();
+            for (embedding, chunks) in vectors {
+                put_str(&mut section, "passage: ");
+                match embedding {
+                    Some(e) => {
+                        section.push(1);
+                        put_vector(&mut section, e, *quantization);
+                    },
+                    None => section.push(0),
+                }
+                put_u32(&mut section, chunks.len());
+                for c in chunks {
+                    put_vector(&mut section, c, *quantization);
+                }
+            }
+            put_str(&mut buf, &ron::to_string(model).unwrap());
+            put_u32(&mut buf, 3);
+            buf.push(*quantization);
+            buf.extend_from_slice(&(section.len() as u64).to_le_bytes());
+            buf.extend_from_slice(&section);
+        }
+        Box::leak(buf.into_boxed_slice())
+    }
+
+    #[test]
+    fn round_trip_with_overridden_snippets() {
+        let vectors: Vectors = vec![
+            (Some(vec![0.6, -0.8, 0.0]), vec![]),
+            (Some(vec![0.0, 1.0, 0.0]), vec![vec![1.0, 0.0, 0.0], vec![0.0, 0.0, -1.0]]), // overridden, its vectors are skipped
+            (None, vec![vec![-0.5, 0.5, 0.7]]),
+        ];
+        let models = [(Model::AllMiniLmL6V2, 0), (Model::E5BaseV2, 1), (Model::MultilingualE5Small, 2)];
+        let data = database(&[("ls", "list files"), ("du", "disk usage"), ("df", "free space")], &vectors, &models);
+        let (mut database, snippets) = BinaryDatabase::parse(data).unwrap();
+        assert_eq!(database.len(), 3);
+        assert_eq!(snippets.iter().map(|s| (s.id.as_str(), s.discription.as_str(), s.content.as_str())).collect::<Vec<_>>(),
+            vec![("ls", "list files", "echo ls"), ("du", "disk usage", "echo du"), ("df", "free space", "echo df")]);
+        assert!(snippets.iter().all(|s| s.tags.contains(&SnipTag::string_to_tag("shell").unwrap())));
+        assert_eq!(database.dim(&Model::E5BaseV2), Some(3));
+        assert_eq!(database.dim(&Model::MxbaiEmbedLargeV1), None);
+        assert!(database.quantization(&Model::AllMiniLmL6V2).is_none());
+        assert!(database.quantization(&Model::MultilingualE5Small) == Some(Quantization::Binary));
+
+        // du is overridden, df is sorted before ls
+        let mut loaded = vec![snippets[2].clone(), snippets[0].clone()];
+        database.set_positions(vec![Some(1), None, Some(0)]);
+        for (model, quantization) in &models {
+            database.load_embeddings(model, &mut loaded).unwrap();
+            let decode = |v: &[f32]| -> Vec<f32> {
+                match quantization {
+                    1 => {
+                        let (scale, codes) = quantize_int8(v);
+                        dequantize_int8(scale, &codes.iter().map(|c| *c as u8).collect::<Vec<_>>())
+                    },
+                    2 => dequantize_binary(&quantize_binary(v), v.len()),
+                    _ => v.to_vec(),
+                }
+            };
+            let (df, ls) = (&loaded[0], &loaded[1]);
+            assert!(df.embedding.as_ref().is_none_or(|e| !e.contains_key(model)));
+            assert_eq!(df.content_embedding.as_ref().unwrap()[model], vec![decode(&vectors[2].1[0])]);
+            assert_eq!(ls.embedding.as_ref().unwrap()[model], decode(vectors[0].0.as_ref().unwrap()));
+            assert!(ls.content_embedding.as_ref().is_none_or(|e| !e.contains_key(model)));
+            assert_eq!(ls.embedding_prompt.as_ref().unwrap()[model], "passage: ");
+        }
+    }
+}
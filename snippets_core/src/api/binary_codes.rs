@@ -0,0 +1,23 @@
+// format constants and vector codes of the built-in binary database
+// included by build.rs (writer) and binary.rs (reader), so both sides always agree
+
+/// first 8 bytes of the binary database, the last byte is the format version
+const BINARY_MAGIC: &[u8; 8] = b"SNIPBIN\x03";
+
+/// int8 scalar quantization, scale is max |x| / 127, x ≈ scale * code
+pub(crate) fn quantize_int8(vector: &[f32]) -> (f32, Vec<i8>) {
+    let max = vector.iter().fold(0.0f32, |m, x| m.max(x.abs()));
+    let scale = if max > 0.0 { max / 127.0 } else { 1.0 };
+    (scale, vector.iter().map(|x| (x / scale).round().clamp(-127.0, 127.0) as i8).collect())
+}
+
+/// 1-bit quantization, dim / 8 bytes, bit i of byte i / 8 (lowest bit first) is 1 if the value > 0
+pub(crate) fn quantize_binary(vector: &[f32]) -> Vec<u8> {
+    let mut bits = vec![0u8; vector.len().div_ceil(8)];
+    for (i, x) in vector.iter().enumerate() {
+        if *x > 0.0 {
+            bits[i / 8] |= 1 << (i % 8);
+        }
+    }
+    bits
+}
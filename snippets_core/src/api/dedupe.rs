@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use tabled::{
+    builder::Builder,
+    settings::Style,
+};
+
+use crate::{
+    snip::Snippets,
+    snippets_text::SnippetsText,
+    utils::{
+        cosine_similarity,
+        is_markdown,
+        text_trigrams,
+        trigram_similarity,
+    },
+    error::MyError,
+};
+
+#[cfg(feature = "sqlite")]
+use crate::{
+    sqlite::SnippetDb,
+    utils::is_sqlite,
+};
+
+/// default threshold when use -m embedding model
+const EMBEDDING_THRESHOLD: f32 = 0.9;
+
+/// default threshold when use text similarity
+const TEXT_THRESHOLD: f32 = 0.6;
+
+/// merge one cluster into the kept snippet
+struct MergePlan {
+    keep:   usize,      // index in Snippets::data
+    remove: Vec<usize>, // index in Snippets::data
+}
+
+impl Snippets {
+    /// find clusters of near-duplicate snippets and print them side by side
+    /// use discription embedding if -m is specified, otherwise use text similarity of discription and content
+    /// if merge is true, ask which snippet to keep for each cluster, the kept snippet gets the union of tags, the others are removed from their files
+    pub fn dedupe(&self, threshold: Option<f32>, merge: bool) -> Result<(), MyError> {
+        let embeddings = self.discription_embeddings()?;
+        let trigrams: Vec<HashSet<String>> = if embeddings.is_none() {
+            self.data.iter().map(|s| text_trigrams(&s.full_text())).collect()
+        } else {
+            Vec::new()
+        };
+        let threshold = threshold.unwrap_or(if embeddings.is_some() { EMBEDDING_THRESHOLD } else { TEXT_THRESHOLD });
+        let similarity = |i: usize, j: usize| -> Result<f32, MyError> {
+            match &embeddings {
+                Some(e) => cosine_similarity(&e[i], &e[j]),
+                None => Ok(trigram_similarity(&trigrams[i], &trigrams[j])),
+            }
+        };
+
+        // union-find, all pairs above the threshold are in the same cluster
+        let num = self.data.len();
+        let mut parent: Vec<usize> = (0..num).collect();
+        fn find(parent: &mut [usize], i: usize) -> usize {
+            let mut root = i;
+            while parent[root] != root {
+                root = parent[root];
+            }
+            parent[i] = root;
+            root
+        }
+        for i in 0..num {
+            for j in (i+1)..num {
+                if similarity(i, j)? >= threshold {
+                    let (root_i, root_j) = (find(&mut parent, i), find(&mut parent, j));
+                    if root_i != root_j {
+                        parent[root_j] = root_i;
+                    }
+                }
+            }
+        }
+        let mut clusters: BTreeMap<usize, Vec<usize>> = BTreeMap::new(); // key: root, value: snippet ids
+        for i in 0..num {
+            let root = find(&mut parent, i);
+            clusters.entry(root).or_default().push(i);
+        }
+        let clusters: Vec<Vec<usize>> = clusters.into_values().filter(|c| c.len() > 1).collect();
+        if clusters.is_empty() {
+            println!("no near-duplicate snippets found, {} similarity threshold: {:.2}", if embeddings.is_some() { "embedding" } else { "text" }, threshold);
+            return Ok(())
+        }
+
+        // print each cluster side by side
+        let mut plans: Vec<MergePlan> = Vec::new();
+        for (n, cluster) in clusters.iter().enumerate() {
+            println!("cluster {}/{}, {} similarity threshold: {:.2}", n+1, clusters.len(), if embeddings.is_some() { "embedding" } else { "text" }, threshold);
+            let mut builder = Builder::default();
+            let mut row_id = vec!["id".to_string()];
+            let mut row_similarity = vec!["similarity".to_string()];
+            let mut row_discription = vec!["discription".to_string()];
+            let mut row_categories = vec!["categories".to_string()];
+            let mut row_source = vec!["source".to_string()];
+            let mut row_content = vec!["content".to_string()];
+            for (k, i) in cluster.iter().enumerate() {
+                let s = &self.data[*i];
+                row_id.push(i.to_string());
+                row_similarity.push(if k == 0 { "-".to_string() } else { format!("{:.4}", similarity(cluster[0], *i)?) });
+                row_discription.push(s.discription.clone());
+                row_categories.push(s.format_tags());
+                row_source.push(s.source_name());
+                row_content.push(s.content.trim().replace("\r", ""));
+            }
+            builder.push_record(row_id);
+            builder.push_record(row_similarity);
+            builder.push_record(row_discription);
+            builder.push_record(row_categories);
+            builder.push_record(row_source);
+            builder.push_record(row_content);
+            let mut table = builder.build();
+            table.with(Style::modern()); // table style: ascii, extended, markdown, re_structured_text, dots, psql, ascii_rounded, blank, empty, rounded, modern, sharp
+            println!("{}", table);
+
+            if merge {
+                if let Some(plan) = ask_merge(self, cluster)? {
+                    plans.push(plan);
+                }
+            }
+        }
+
+        if !plans.is_empty() {
+            self.apply_merge(&plans)?;
+        }
+        Ok(())
+    }
+
+    /// edit *.snippets files (one transaction for each SQLite database), kept snippet gets the union of tags, other snippets are removed
+    /// only the tags of kept snippets and the removed snippets are edited, comments and stored embeddings are kept
+    /// all plans are applied together, because removing snippets changes the index in the file
+    fn apply_merge(&self, plans: &[MergePlan]) -> Result<(), MyError> {
+        let mut update: HashMap<PathBuf, Vec<(usize, Vec<String>)>> = HashMap::new(); // key: file, value: (index in file, new tags)
+        let mut remove: HashMap<PathBuf, Vec<usize>> = HashMap::new(); // key: file, value: index in file
+        for plan in plans {
+            let mut tags: HashSet<_> = self.data[plan.keep].tags.clone();
+            for i in &plan.remove {
+                tags.extend(self.data[*i].tags.clone());
+                if let Some((file, idx)) = &self.data[*i].source {
+                    remove.entry(file.clone()).or_default().push(*idx);
+                }
+            }
+            let mut tags: Vec<_> = tags.into_iter().collect();
+            tags.sort();
+            if let Some((file, idx)) = &self.data[plan.keep].source {
+                update.entry(file.clone()).or_default().push((*idx, tags.iter().map(|t| t.to_string()).collect()));
+            }
+        }
+        let files: HashSet<&PathBuf> = update.keys().chain(remove.keys()).collect();
+        for file in files {
+            #[cfg(feature = "sqlite")]
+            if is_sqlite(file) {
+                SnippetDb::open_write(file)?.apply_merge(update.get(file).map(|u| u.as_slice()).unwrap_or_default(), remove.get(file).map(|r| r.as_slice()).unwrap_or_default())?;
+                println!("updated {}", file.display());
+                continue
+            }
+            let mut text = SnippetsText::read(file)?;
+            if let Some(u) = update.get(file) {
+                for (idx, tags) in u {
+                    text.set_tags(*idx, tags)?;
+                }
+            }
+            if let Some(r) = remove.get(file) {
+                text.remove(r)?;
+            }
+            text.write(file)?;
+            println!("updated {}", file.display());
+        }
+        Ok(())
+    }
+}
+
+/// ask user which snippet in the cluster to keep, return None to skip this cluster
+fn ask_merge(snippets: &Snippets, cluster: &[usize]) -> Result<Option<MergePlan>, MyError> {
+    if cluster.iter().any(|i| snippets.data[*i].source.is_none()) {
+        println!("Warning - snippets in the binary file cannot be merged, skip this cluster");
+        return Ok(None)
+    }
+    if cluster.iter().any(|i| snippets.data[*i].source.as_ref().is_some_and(|(f, _)| is_markdown(f))) {
+        println!("Warning - snippets in markdown files cannot be merged, skip this cluster");
+        return Ok(None)
+    }
+    loop {
+        print!("input the id to keep (others will be merged into it), press enter to skip: ");
+        io::stdout().flush()?;
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Ok(None)
+        }
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(None)
+        }
+        match input.parse::<usize>() {
+            Ok(keep) if cluster.contains(&keep) => {
+                return Ok(Some(MergePlan{
+                    keep,
+                    remove: cluster.iter().filter(|i| **i != keep).copied().collect(),
+                }))
+            },
+            _ => println!("{} is not in this cluster: {:?}", input, cluster),
+        }
+    }
+}
@@ -3,6 +3,7 @@ use std::num::ParseIntError;
 use std::string::FromUtf8Error;
 
 use arboard::Error as clipboard_error;
+use rustyline::error::ReadlineError;
 use serde_json::Error as json_error;
 use thiserror::Error;
 
@@ -80,6 +81,10 @@ pub enum MyError {
     #[error("Error - failed to copy to clipboard: {error}")]
     ClipboardError{error: clipboard_error},
 
+    // 交互式shell错误
+    #[error("Error - shell: {error}")]
+    ShellError{error: ReadlineError},
+
     // candle does not have some dtype in safetensors
     #[error("Error - {info}")]
     DtypeError{info: String},
@@ -88,6 +93,15 @@ pub enum MyError {
     #[error("Error - {info}")]
     EnvVarError{info: String},
 
+    // sqlite error
+    #[cfg(feature = "sqlite")]
+    #[error("Error - sqlite {file}: {error}")]
+    SqliteError{file: String, error: rusqlite::Error},
+
+    // git error of sync
+    #[error("Error - git {args}: {info}")]
+    GitError{args: String, info: String},
+
     // 参数使用错误
     #[error("Error - {para}")]
     ParaError{para: String},
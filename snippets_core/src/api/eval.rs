@@ -0,0 +1,170 @@
+use std::fs::read_to_string;
+use std::path::Path;
+use std::time::Instant;
+
+use serde::Deserialize;
+use tabled::{
+    builder::Builder,
+    settings::Style,
+};
+
+use crate::{
+    parse_paras::ModelInfo,
+    snip::Snippets,
+    error::MyError,
+};
+
+/// one line of the labelled queries file
+#[derive(Deserialize)]
+struct LabelledQuery {
+    query:    String,      // search keyword
+    expected: Vec<String>, // namespace:id (or id if only one namespace has it) of the relevant snippets, stable when snippets are added
+}
+
+/// ranking quality of one search method over all queries
+struct MethodReport {
+    name:     String,                   // keyword or model name
+    recall:   f64,                      // mean recall@k
+    mrr:      f64,                      // mean reciprocal rank of the first relevant result in top k
+    ndcg:     f64,                      // mean nDCG@k, binary relevance
+    load_ms:  f64,                      // model loading and calculating missing embeddings
+    query_ms: f64,                      // mean latency of one query
+    failures: Vec<(usize, Vec<usize>)>, // (query index, top k ids) of queries without any relevant result in top k
+}
+
+/// recall@k, reciprocal rank and nDCG@k of one ranked result list
+fn metrics(ranked: &[usize], expected: &[usize], k: usize) -> (f64, f64, f64) {
+    let top = &ranked[..k.min(ranked.len())];
+    let hits = top.iter().filter(|i| expected.contains(i)).count();
+    let recall = hits as f64 / expected.len() as f64;
+    let rr = top.iter().position(|i| expected.contains(i)).map(|p| 1.0 / (p + 1) as f64).unwrap_or(0.0);
+    let dcg: f64 = top.iter().enumerate().filter(|(_, i)| expected.contains(i)).map(|(p, _)| 1.0 / (p as f64 + 2.0).log2()).sum();
+    let idcg: f64 = (0..expected.len().min(k)).map(|p| 1.0 / (p as f64 + 2.0).log2()).sum();
+    (recall, rr, dcg / idcg)
+}
+
+impl Snippets {
+    /// evaluate keyword search and each model on labelled queries
+    /// report recall@k, MRR, nDCG@k, latency and the queries without any relevant result in top k
+    pub fn eval(&mut self, queries_file: &Path, k: usize, models: &[ModelInfo]) -> Result<(), MyError> {
+        let content = read_to_string(queries_file).map_err(|e| MyError::ReadFileError{file: queries_file.display().to_string(), error: e})?;
+        let mut queries: Vec<(String, Vec<usize>)> = Vec::new(); // (query, index of the expected snippets)
+        for (n, line) in content.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let error = |msg: String| MyError::ParaError{para: format!("{} line {}: {}", queries_file.display(), n+1, msg)};
+            let query: LabelledQuery = serde_json::from_str(line).map_err(|e| error(format!("{}, expected is a list of namespace:id strings", e)))?;
+            if query.expected.is_empty() {
+                return Err(error("expected is empty".to_string()))
+            }
+            let expected = query.expected.iter().map(|e| self.resolve(e).map_err(&error)).collect::<Result<Vec<usize>, MyError>>()?;
+            queries.push((query.query, expected));
+        }
+        if queries.is_empty() {
+            return Err(MyError::ParaError{para: format!("no labelled query in {}", queries_file.display())})
+        }
+
+        // keyword search first, then each model, -n is replaced by k
+        let mut options = self.options.clone();
+        options.top_num = k;
+        self.set_search_options(options);
+        let mut methods: Vec<(String, Option<ModelInfo>)> = vec![("keyword".to_string(), None)];
+        methods.extend(models.iter().map(|m| (m.model.name().to_string(), Some(m.clone()))));
+        let mut reports: Vec<MethodReport> = Vec::new();
+        for (name, model) in methods {
+            self.set_model(model);
+            let start = Instant::now();
+            if let Err(e) = self.cache_embeddings() {
+                println!("Warning - skip {}: {}", name, e);
+                continue
+            }
+            let load_ms = start.elapsed().as_secs_f64() * 1000.0;
+            let mut report = MethodReport { name, recall: 0.0, mrr: 0.0, ndcg: 0.0, load_ms, query_ms: 0.0, failures: Vec::new() };
+            for (q, (query, expected)) in queries.iter().enumerate() {
+                let start = Instant::now();
+                let ranked: Vec<usize> = self.search(query)?.into_iter().map(|(i, _)| i).take(k).collect();
+                report.query_ms += start.elapsed().as_secs_f64() * 1000.0;
+                let (recall, rr, ndcg) = metrics(&ranked, expected, k);
+                report.recall += recall;
+                report.mrr += rr;
+                report.ndcg += ndcg;
+                if rr == 0.0 {
+                    report.failures.push((q, ranked));
+                }
+            }
+            let num = queries.len() as f64;
+            report.recall /= num;
+            report.mrr /= num;
+            report.ndcg /= num;
+            report.query_ms /= num;
+            reports.push(report);
+        }
+
+        // summary, one row per method
+        let mut builder = Builder::default();
+        builder.push_record(["method".to_string(), format!("recall@{}", k), "MRR".to_string(), format!("nDCG@{}", k), "failures".to_string(), "load (ms)".to_string(), "latency (ms/query)".to_string()]);
+        for r in &reports {
+            builder.push_record([
+                r.name.clone(),
+                format!("{:.3}", r.recall),
+                format!("{:.3}", r.mrr),
+                format!("{:.3}", r.ndcg),
+                format!("{}/{}", r.failures.len(), queries.len()),
+                format!("{:.1}", r.load_ms),
+                format!("{:.2}", r.query_ms),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Style::modern()); // table style: ascii, extended, markdown, re_structured_text, dots, psql, ascii_rounded, blank, empty, rounded, modern, sharp
+        println!("{}", table);
+
+        // failures of each method, expected namespace:id and the top k namespace:id
+        for r in reports.iter().filter(|r| !r.failures.is_empty()) {
+            let mut builder = Builder::default();
+            builder.push_record(["query", "expected", &format!("top {}", k)]);
+            for (q, ranked) in &r.failures {
+                let ids = |ids: &[usize]| ids.iter().map(|i| self.data[*i].qualified_id()).collect::<Vec<_>>().join("\n");
+                builder.push_record([queries[*q].0.clone(), ids(&queries[*q].1), ids(ranked)]);
+            }
+            let mut table = builder.build();
+            table.with(Style::modern()); // table style: ascii, extended, markdown, re_structured_text, dots, psql, ascii_rounded, blank, empty, rounded, modern, sharp
+            println!("failures of {}, no expected snippet in top {}:\n{}", r.name, k, table);
+        }
+        Ok(())
+    }
+
+    /// index of the snippet referred to by namespace:id, or by id if only one namespace has it
+    fn resolve(&self, target: &str) -> Result<usize, String> {
+        let found: Vec<usize> = self.data.iter().enumerate().filter(|(_, s)| s.is(target)).map(|(i, _)| i).collect();
+        match found.as_slice() {
+            [i] => Ok(*i),
+            [] => Err(format!("snippet {} not found", target)),
+            _ => Err(format!("{} is in several namespaces, use one of {}", target, found.iter().map(|i| self.data[*i].qualified_id()).collect::<Vec<_>>().join(", "))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perfect_ranking() {
+        assert_eq!(metrics(&[3, 5, 1], &[3, 5], 3), (1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn partial_ranking() {
+        let (recall, rr, ndcg) = metrics(&[7, 3, 9, 5], &[3, 5], 3);
+        assert_eq!(recall, 0.5);
+        assert_eq!(rr, 0.5);
+        let idcg = 1.0 + 1.0 / 3f64.log2();
+        assert!((ndcg - (1.0 / 3f64.log2()) / idcg).abs() < 1e-12);
+    }
+
+    #[test]
+    fn no_hit_and_short_list() {
+        assert_eq!(metrics(&[1, 2], &[4], 10), (0.0, 0.0, 0.0));
+        assert_eq!(metrics(&[], &[4], 10), (0.0, 0.0, 0.0));
+        // more expected snippets than k, ideal DCG only counts k of them
+        assert_eq!(metrics(&[1, 2], &[1, 2, 3], 2), (2.0 / 3.0, 1.0, 1.0));
+    }
+}
@@ -0,0 +1,297 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    utils::{
+        cosine_similarity,
+        my_writer,
+    },
+    error::MyError,
+};
+
+/// max neighbours of each node on layers > 0, layer 0 uses 2 * M
+const M: usize = 16;
+
+/// candidate list size when building the index
+const EF_CONSTRUCTION: usize = 100;
+
+/// what the index is built from, the saved index is rebuilt if any of them changed
+/// checking it is cheap, the vectors are never read to validate the index
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct IndexKey {
+    pub snippets: usize,  // the number of snippets
+    pub model:    String, // model name
+    pub prompt:   String, // document prompt of the model
+    pub dim:      usize,  // embedding dimension
+    pub sources:  u64,    // hash of the source files with their size and modification time
+    pub order:    u64,    // hash of the snippets in their order, each node is the row of a snippet
+}
+
+/// HNSW (Hierarchical Navigable Small World) approximate nearest neighbour index
+/// https://arxiv.org/abs/1603.09320
+/// only the graph is stored, vectors are the snippets' embeddings
+/// saved as two JSON lines, the key and the index, the graph is only parsed if the key matches
+#[derive(Deserialize, Serialize)]
+pub struct Hnsw {
+    key:         IndexKey,             // rebuild if the snippets or the model changed
+    entry:       Option<usize>,        // entry point, node on the top layer
+    max_level:   usize,                // top layer
+    links:       Vec<Vec<Vec<usize>>>, // node -> layer -> neighbours
+}
+
+/// distance and node, ordered by distance
+#[derive(PartialEq)]
+struct Candidate(f32, usize);
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+impl Hnsw {
+    /// build index from vectors
+    pub fn build(vectors: &[&[f32]], key: IndexKey) -> Result<Self, MyError> {
+        let mut index = Self {
+            key,
+            entry:       None,
+            max_level:   0,
+            links:       Vec::with_capacity(vectors.len()),
+        };
+        let level_mult = 1.0 / (M as f64).ln();
+        let mut seed: u64 = 0x2545F4914F6CDD1D; // fixed seed, same vectors always get the same index
+        for i in 0..vectors.len() {
+            // xorshift random number in (0, 1]
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let r = ((seed >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+            let level = (-r.ln() * level_mult).floor() as usize;
+            index.insert(vectors, i, level)?;
+        }
+        Ok(index)
+    }
+
+    /// load index from file, return None if the file does not exist or the key is different
+    pub fn load(file: &Path, key: &IndexKey) -> Option<Self> {
+        let mut reader = BufReader::new(File::open(file).ok()?);
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        if serde_json::from_str::<IndexKey>(&line).ok()? != *key {
+            return None
+        }
+        let index: Self = serde_json::from_reader(reader).ok()?;
+        if index.key == *key && index.links.len() == key.snippets {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// save index to file
+    pub fn save(&self, file: &Path) -> Result<(), MyError> {
+        let error = |e| MyError::ToJsonStirngError{uuid: file.display().to_string(), error: e};
+        let mut writer = my_writer(file)?;
+        serde_json::to_writer(&mut writer, &self.key).map_err(error)?;
+        writer.write_all(b"\n")?;
+        serde_json::to_writer(&mut writer, self).map_err(error)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// search the k nearest nodes of query, larger ef gets higher recall but slower
+    /// return (node, cosine similarity) sorted by similarity
+    pub fn search(&self, vectors: &[&[f32]], query: &[f32], k: usize, ef: usize) -> Result<Vec<(usize, f32)>, MyError> {
+        let mut entry = match self.entry {
+            Some(e) => vec![e],
+            None => return Ok(Vec::new()),
+        };
+        for level in (1..=self.max_level).rev() {
+            entry = self.search_layer(vectors, query, &entry, 1, level)?.into_iter().map(|c| c.1).collect();
+        }
+        Ok(
+            self.search_layer(vectors, query, &entry, ef.max(k), 0)?
+                .into_iter()
+                .take(k)
+                .map(|c| (c.1, 1.0 - c.0))
+                .collect()
+        )
+    }
+
+    /// insert node i on layers 0..=level
+    fn insert(&mut self, vectors: &[&[f32]], i: usize, level: usize) -> Result<(), MyError> {
+        self.links.push(vec![Vec::new(); level+1]);
+        let mut entry = match self.entry {
+            Some(e) => vec![e],
+            None => {
+                self.entry = Some(i);
+                self.max_level = level;
+                return Ok(())
+            },
+        };
+        // greedy search on upper layers
+        for l in ((level+1)..=self.max_level).rev() {
+            entry = self.search_layer(vectors, vectors[i], &entry, 1, l)?.into_iter().map(|c| c.1).collect();
+        }
+        // connect neighbours on lower layers
+        for l in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_layer(vectors, vectors[i], &entry, EF_CONSTRUCTION, l)?;
+            let max_links = if l == 0 { 2 * M } else { M };
+            let neighbours: Vec<usize> = candidates.iter().take(M).map(|c| c.1).collect();
+            self.links[i][l] = neighbours.clone();
+            for n in neighbours {
+                self.links[n][l].push(i);
+                if self.links[n][l].len() > max_links { // keep the nearest neighbours
+                    let mut links: Vec<Candidate> = Vec::with_capacity(self.links[n][l].len());
+                    for j in &self.links[n][l] {
+                        links.push(Candidate(distance(vectors[n], vectors[*j])?, *j));
+                    }
+                    links.sort();
+                    self.links[n][l] = links.into_iter().take(max_links).map(|c| c.1).collect();
+                }
+            }
+            entry = candidates.into_iter().map(|c| c.1).collect();
+        }
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry = Some(i);
+        }
+        Ok(())
+    }
+
+    /// beam search on one layer, return the ef nearest nodes sorted by distance
+    fn search_layer(&self, vectors: &[&[f32]], query: &[f32], entry: &[usize], ef: usize, level: usize) -> Result<Vec<Candidate>, MyError> {
+        let mut visited: HashSet<usize> = entry.iter().copied().collect();
+        let mut candidates: BinaryHeap<Reverse<Candidate>> = BinaryHeap::new(); // nearest first
+        let mut results: BinaryHeap<Candidate> = BinaryHeap::new(); // farthest first
+        for e in entry {
+            let d = distance(query, vectors[*e])?;
+            candidates.push(Reverse(Candidate(d, *e)));
+            results.push(Candidate(d, *e));
+        }
+        while let Some(Reverse(Candidate(d, c))) = candidates.pop() {
+            if let Some(farthest) = results.peek() {
+                if d > farthest.0 && results.len() >= ef {
+                    break
+                }
+            }
+            for n in self.links[c].get(level).map(|l| l.as_slice()).unwrap_or(&[]) {
+                if !visited.insert(*n) {
+                    continue
+                }
+                let d = distance(query, vectors[*n])?;
+                if results.len() < ef || results.peek().map(|f| d < f.0).unwrap_or(true) {
+                    candidates.push(Reverse(Candidate(d, *n)));
+                    results.push(Candidate(d, *n));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+        Ok(results.into_sorted_vec())
+    }
+}
+
+/// cosine distance
+fn distance(vec_a: &[f32], vec_b: &[f32]) -> Result<f32, MyError> {
+    Ok(1.0 - cosine_similarity(vec_a, vec_b)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// deterministic random unit vectors
+    fn random_vectors(num: usize, dim: usize) -> Vec<Vec<f32>> {
+        let mut seed: u64 = 42;
+        (0..num).map(|_| {
+            let v: Vec<f32> = (0..dim).map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                (seed >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+            }).collect();
+            let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+            v.into_iter().map(|x| x / norm).collect()
+        }).collect()
+    }
+
+    fn key(snippets: usize) -> IndexKey {
+        IndexKey{snippets, model: "test".to_string(), prompt: String::new(), dim: 16, sources: 1, order: 1}
+    }
+
+    /// exact k nearest nodes
+    fn exact(vectors: &[&[f32]], query: &[f32], k: usize) -> Vec<usize> {
+        let mut similarity: Vec<(usize, f32)> = vectors.iter().enumerate().map(|(i, v)| (i, cosine_similarity(query, v).unwrap())).collect();
+        similarity.sort_by(|a, b| b.1.total_cmp(&a.1));
+        similarity.into_iter().take(k).map(|(i, _)| i).collect()
+    }
+
+    #[test]
+    fn graph_links() {
+        let vectors = random_vectors(500, 16);
+        let vectors: Vec<&[f32]> = vectors.iter().map(|v| v.as_slice()).collect();
+        let index = Hnsw::build(&vectors, key(vectors.len())).unwrap();
+        assert_eq!(index.links.len(), vectors.len());
+        for (i, layers) in index.links.iter().enumerate() {
+            assert!(!layers.is_empty() && layers.len() <= index.max_level + 1);
+            assert!(layers[0].len() <= 2 * M && layers.iter().skip(1).all(|l| l.len() <= M));
+            assert!(layers.iter().flatten().all(|n| *n != i && *n < vectors.len()));
+        }
+        assert!(index.entry.is_some_and(|e| index.links[e].len() == index.max_level + 1));
+    }
+
+    #[test]
+    fn recall() {
+        let vectors = random_vectors(2000, 16);
+        let vectors: Vec<&[f32]> = vectors.iter().map(|v| v.as_slice()).collect();
+        let index = Hnsw::build(&vectors, key(vectors.len())).unwrap();
+        let queries = random_vectors(2050, 16).split_off(2000);
+        let (k, mut found) = (10, 0);
+        for q in &queries {
+            let result = index.search(&vectors, q, k, 64).unwrap();
+            assert!(result.windows(2).all(|w| w[0].1 >= w[1].1));
+            let truth = exact(&vectors, q, k);
+            found += result.iter().filter(|(i, _)| truth.contains(i)).count();
+        }
+        let recall = found as f32 / (k * queries.len()) as f32;
+        assert!(recall >= 0.9, "recall@10 {}", recall);
+        // a node is its own nearest neighbour
+        assert_eq!(index.search(&vectors, vectors[7], 1, 64).unwrap()[0].0, 7);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let vectors = random_vectors(50, 16);
+        let vectors: Vec<&[f32]> = vectors.iter().map(|v| v.as_slice()).collect();
+        let index = Hnsw::build(&vectors, key(vectors.len())).unwrap();
+        let file = std::env::temp_dir().join(format!("snippets_hnsw_test_{}.hnsw", std::process::id()));
+        index.save(&file).unwrap();
+        let loaded = Hnsw::load(&file, &key(vectors.len())).unwrap();
+        assert_eq!(loaded.links, index.links);
+        assert!(Hnsw::load(&file, &key(vectors.len() + 1)).is_none());
+        assert!(Hnsw::load(&file, &IndexKey{sources: 2, ..key(vectors.len())}).is_none());
+        assert!(Hnsw::load(&file, &IndexKey{order: 2, ..key(vectors.len())}).is_none()); // same files, snippets in another order
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn empty_index() {
+        let index = Hnsw::build(&[], key(0)).unwrap();
+        assert!(index.search(&[], &[1.0, 0.0], 5, 64).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{
+    SnipTag,
+    deserialize_tags_from_strings,
+    error::MyError,
+    snip::SingleSnippet,
+};
+
+use embedding_lib::snippet_id;
+
+/// front matter of a snippet, written as YAML (between ---) or TOML (between +++) right after its heading
+/// the front matter before the first heading only sets tags shared by all snippets in the file
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FrontMatter {
+    #[serde(default)]
+    id:          String,
+    #[serde(default, deserialize_with = "deserialize_tags_from_strings")]
+    tags:        HashSet<SnipTag>,
+    #[serde(default, alias = "description")]
+    discription: String,
+    #[serde(default)]
+    overrides:   String,
+    #[serde(default)]
+    hide:        Vec<String>,
+}
+
+impl FrontMatter {
+    /// only tags are set, required for the front matter before the first heading
+    fn only_tags(&self) -> bool {
+        self.id.is_empty() && self.discription.is_empty() && self.overrides.is_empty() && self.hide.is_empty()
+    }
+}
+
+/// line number (start from 1) and text
+type Line<'a> = (usize, &'a str);
+
+/// heading and the following lines until the next heading
+struct Section<'a> {
+    title: Option<&'a str>, // heading text, None for lines before the first heading
+    line:  usize,           // line number of the heading, start from 1
+    lines: Vec<Line<'a>>,
+}
+
+/// read snippets from markdown file, each heading followed by a fenced code block is a snippet
+/// the heading is the discription, the code block is the content, the info string of the code block is added as tag if supported
+/// headings without code block (e.g. "## Git" grouping the following snippets) are skipped, unless their front matter hides snippets
+pub(crate) fn read_markdown_snippets(file: &Path, text: &str) -> Result<Vec<SingleSnippet>, MyError> {
+    let error = |line: usize, msg: String| MyError::ParaError{para: format!("{} line {}: {}", file.display(), line, msg)};
+    let mut snippets: Vec<SingleSnippet> = Vec::new();
+    let mut file_tags: HashSet<SnipTag> = HashSet::new();
+    for section in split_sections(text) {
+        let (front, rest) = front_matter(&section.lines).map_err(|(l, e)| error(l, e))?;
+        let Some(title) = section.title else {
+            if !front.only_tags() {
+                return Err(error(1, "only tags are allowed in the front matter before the first heading".to_string()))
+            }
+            file_tags = front.tags;
+            continue
+        };
+        let code = first_code_block(rest).map_err(|(l, e)| error(l, e))?;
+        if code.is_none() && front.hide.is_empty() {
+            continue
+        }
+        let mut tags = front.tags;
+        tags.extend(file_tags.iter().cloned());
+        let (content, discription) = match code {
+            Some((lang, body)) => {
+                tags.extend(lang_tag(lang));
+                let discription = if front.discription.is_empty() { title.to_string() } else { front.discription };
+                (format!("\n{}\n", body), discription)
+            },
+            None => (String::new(), front.discription), // only hides snippets
+        };
+        if discription.is_empty() && front.hide.is_empty() {
+            return Err(error(section.line, "snippet has no discription".to_string()))
+        }
+        snippets.push(SingleSnippet {
+            id:                front.id,
+            tags,
+            discription,
+            embedding:         None,
+            embedding_prompt:  None,
+            content_embedding: None,
+            content,
+            overrides:         front.overrides,
+            hide:              front.hide,
+            source:            None,
+            namespace:         "",
+        });
+    }
+    Ok(snippets)
+}
+
+/// text before the first heading, and (key, text) of each section
+type Sections<'a> = (&'a str, Vec<(String, String)>);
+
+/// split the text by headings for sync, key is the id of the front matter, or the default id of the discription
+/// the lines of each section are kept unchanged
+pub(crate) fn markdown_sections(text: &str) -> Result<Sections<'_>, String> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let sections = split_sections(text);
+    let mut bounds: Vec<usize> = sections.iter().skip(1).map(|s| s.line - 1).collect(); // index of the heading lines
+    bounds.push(lines.len());
+    let head: usize = lines[..bounds[0]].iter().map(|l| l.len()).sum();
+    let mut result = Vec::new();
+    for (i, section) in sections.iter().skip(1).enumerate() {
+        let (front, _) = front_matter(&section.lines).map_err(|(l, e)| format!("line {}: {}", l, e))?;
+        let key = if !front.id.is_empty() {
+            front.id
+        } else if !front.discription.is_empty() {
+            snippet_id(&front.discription)
+        } else {
+            snippet_id(section.title.unwrap_or_default())
+        };
+        let mut text = lines[bounds[i]..bounds[i + 1]].concat();
+        if !text.ends_with('\n') {
+            text.push('\n'); // the last line, other sections may follow after merging
+        }
+        result.push((key, text));
+    }
+    Ok((&text[..head], result))
+}
+
+/// the file starts with snippet front matter (only tags), so it is a snippet file
+/// front matter of other tools (e.g. title or layout of a static site) has other fields and does not match
+pub(crate) fn has_snippet_front_matter(text: &str) -> bool {
+    let sections = split_sections(text);
+    let lines = &sections[0].lines;
+    lines.iter().find(|(_, l)| !l.trim().is_empty()).is_some_and(|(_, l)| l.trim() == "---" || l.trim() == "+++")
+        && front_matter(lines).is_ok_and(|(front, _)| front.only_tags())
+}
+
+/// split markdown text by ATX headings (# to ######), headings inside code blocks or front matter are ignored
+fn split_sections(text: &str) -> Vec<Section<'_>> {
+    let mut sections = vec![Section{title: None, line: 1, lines: Vec::new()}];
+    let mut fence: Option<&str> = None;        // opening fence of the current code block
+    let mut front: Option<&str> = None;        // delimiter of the current front matter
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let section_start = sections.last().is_some_and(|s| s.lines.iter().all(|(_, l)| l.trim().is_empty()));
+        if let Some(f) = fence {
+            if fence_end(line, f) {
+                fence = None;
+            }
+        } else if let Some(d) = front {
+            if line.trim() == d {
+                front = None;
+            }
+        } else if section_start && (line.trim() == "---" || line.trim() == "+++") {
+            front = Some(line.trim());
+        } else if let Some(f) = fence_start(line) {
+            fence = Some(f);
+        } else if let Some(title) = heading(line) {
+            sections.push(Section{title: Some(title), line: i + 1, lines: Vec::new()});
+            continue
+        }
+        if let Some(s) = sections.last_mut() {
+            s.lines.push((i + 1, line));
+        }
+    }
+    sections
+}
+
+/// heading text of ATX heading line
+fn heading(line: &str) -> Option<&str> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None // e.g. #hashtag
+    }
+    Some(rest.trim().trim_end_matches('#').trim_end())
+}
+
+/// opening fence of a code block, e.g. ``` or ~~~~, return the fence
+fn fence_start(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    for c in ['`', '~'] {
+        let n = trimmed.chars().take_while(|x| *x == c).count();
+        if n >= 3 {
+            return Some(&trimmed[..n])
+        }
+    }
+    None
+}
+
+/// closing fence of the code block opened by fence, the same character and at least as long
+fn fence_end(line: &str, fence: &str) -> bool {
+    let t = line.trim();
+    t.starts_with(fence) && t.chars().all(|c| fence.starts_with(c))
+}
+
+/// parse the front matter at the beginning of the section (blank lines are skipped), return the remaining lines
+fn front_matter<'a, 'b>(lines: &'b [Line<'a>]) -> Result<(FrontMatter, &'b [Line<'a>]), (usize, String)> {
+    let start = lines.iter().position(|(_, l)| !l.trim().is_empty()).unwrap_or(lines.len());
+    let Some((line, delimiter)) = lines.get(start).map(|(n, l)| (*n, l.trim())).filter(|(_, l)| *l == "---" || *l == "+++") else {
+        return Ok((FrontMatter::default(), lines))
+    };
+    let Some(end) = lines[start + 1..].iter().position(|(_, l)| l.trim() == delimiter).map(|e| start + 1 + e) else {
+        return Err((line, format!("front matter is not closed by {}", delimiter)))
+    };
+    let text = lines[start + 1..end].iter().map(|(_, l)| *l).collect::<Vec<_>>().join("\n");
+    let front = if delimiter == "---" {
+        serde_yaml::from_str::<Option<FrontMatter>>(&text).map(|f| f.unwrap_or_default()).map_err(|e| (line, format!("YAML front matter: {}", e)))?
+    } else {
+        toml::from_str::<FrontMatter>(&text).map_err(|e| (line, format!("TOML front matter: {}", e.message())))?
+    };
+    Ok((front, &lines[end + 1..]))
+}
+
+/// language and body of the first fenced code block, the indentation of the opening fence is removed from the body
+fn first_code_block<'a>(lines: &[Line<'a>]) -> Result<Option<(&'a str, String)>, (usize, String)> {
+    let Some(start) = lines.iter().position(|(_, l)| fence_start(l).is_some()) else {
+        return Ok(None)
+    };
+    let (line, open) = lines[start];
+    let indent = open.len() - open.trim_start().len();
+    let fence = fence_start(open).unwrap_or("```");
+    let lang = open.trim_start()[fence.len()..].split_whitespace().next().unwrap_or("").trim_start_matches('{').trim_end_matches('}');
+    let mut body: Vec<&str> = Vec::new();
+    for (_, l) in &lines[start + 1..] {
+        if fence_end(l, fence) {
+            return Ok(Some((lang, body.join("\n"))))
+        }
+        let spaces = l.len() - l.trim_start_matches(' ').len();
+        body.push(&l[spaces.min(indent)..]);
+    }
+    Err((line, format!("code block is not closed by {}", fence)))
+}
+
+/// tag of the code block language, e.g. sh and bash are Shell, None if not supported
+fn lang_tag(lang: &str) -> Option<SnipTag> {
+    let lang = lang.to_lowercase();
+    let tag = match lang.as_str() {
+        "py" | "python3" => "python",
+        "rs" => "rust",
+        "sh" | "bash" | "zsh" | "fish" | "console" | "shell-session" => "shell",
+        l => l,
+    };
+    SnipTag::string_to_tag(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> Result<Vec<SingleSnippet>, MyError> {
+        read_markdown_snippets(Path::new("test.md"), text)
+    }
+
+    fn tags(s: &SingleSnippet) -> Vec<SnipTag> {
+        let mut tags: Vec<SnipTag> = s.tags.iter().cloned().collect();
+        tags.sort();
+        tags
+    }
+
+    #[test]
+    fn headings_and_code_blocks() {
+        let text = "# Git\n\n## show log\n\n```bash\ngit log --oneline\n```\n\n## list files ##\n\n```py\nimport os\nprint(os.listdir(\".\"))\n```\n";
+        let snippets = read(text).unwrap();
+        assert_eq!(snippets.len(), 2); // "# Git" only groups snippets
+        assert_eq!(snippets[0].discription, "show log");
+        assert_eq!(snippets[0].content, "\ngit log --oneline\n");
+        assert_eq!(tags(&snippets[0]), vec![SnipTag::Shell]);
+        assert_eq!(snippets[1].discription, "list files");
+        assert_eq!(tags(&snippets[1]), vec![SnipTag::Python]);
+    }
+
+    #[test]
+    fn front_matter_yaml_and_toml() {
+        let text = "---\ntags: [git]\n---\n\n# status\n---\nid: st\ndescription: show the working tree status\n---\n```sh\ngit status\n```\n\n# diff\n+++\ntags = [\"rust\"]\nhide = [\"old-diff\"]\n+++\n```\ngit diff\n```\n";
+        let snippets = read(text).unwrap();
+        assert_eq!(snippets.len(), 2);
+        assert_eq!(snippets[0].id, "st");
+        assert_eq!(snippets[0].discription, "show the working tree status");
+        assert_eq!(tags(&snippets[0]), vec![SnipTag::Git, SnipTag::Shell]);
+        assert_eq!(snippets[1].discription, "diff");
+        assert_eq!(snippets[1].hide, vec!["old-diff".to_string()]);
+        assert_eq!(tags(&snippets[1]), vec![SnipTag::Git, SnipTag::Rust]);
+    }
+
+    #[test]
+    fn nested_and_tilde_fences() {
+        // headings and shorter fences inside a code block belong to the content
+        let text = "# readme template\n\n````markdown\n# Title\n```rust\nfn main() {}\n```\n````\n\n# indented\n\n  ~~~ {sh}\n  ls -l\n    cd ..\n  ~~~\n";
+        let snippets = read(text).unwrap();
+        assert_eq!(snippets.len(), 2);
+        assert_eq!(snippets[0].content, "\n# Title\n```rust\nfn main() {}\n```\n");
+        assert!(snippets[0].tags.is_empty());
+        assert_eq!(snippets[1].content, "\nls -l\n  cd ..\n");
+        assert_eq!(tags(&snippets[1]), vec![SnipTag::Shell]);
+    }
+
+    #[test]
+    fn hide_without_code_block() {
+        let snippets = read("# old\n---\nhide: [a, b]\n---\n\n# no code\n\ntext only\n").unwrap();
+        assert_eq!(snippets.len(), 1);
+        assert_eq!(snippets[0].hide, vec!["a".to_string(), "b".to_string()]);
+        assert!(snippets[0].content.is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text: &str| read(text).err().map(|e| e.to_string()).unwrap_or_default();
+        assert!(error("# a\n```sh\nls\n").contains("line 2: code block is not closed by ```"));
+        assert!(error("# a\n---\ntags: [git]\n```sh\nls\n```\n").contains("line 2: front matter is not closed by ---"));
+        assert!(error("# a\n---\ntitle: a\n---\n```sh\nls\n```\n").contains("YAML front matter"));
+        assert!(error("---\nid: a\n---\n").contains("line 1: only tags are allowed"));
+        assert!(error("# a\n+++\ntags = [\"unknown-tag\"]\n+++\n```\nls\n```\n").contains("TOML front matter"));
+    }
+
+    #[test]
+    fn sections_for_sync() {
+        let text = "---\ntags: [git]\n---\n# Git\n## show log\n```\n# not a heading\n```\n## status\n---\nid: st\n---\n```\ngit status\n```";
+        let (head, sections) = markdown_sections(text).unwrap();
+        assert_eq!(head, "---\ntags: [git]\n---\n");
+        let keys: Vec<&str> = sections.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, vec![snippet_id("Git").as_str(), snippet_id("show log").as_str(), "st"]);
+        assert_eq!(sections[1].1, "## show log\n```\n# not a heading\n```\n");
+        assert!(sections[2].1.ends_with("```\n")); // newline added to the last line
+        assert_eq!(format!("{}{}", head, sections.iter().map(|(_, t)| t.as_str()).collect::<String>()), format!("{}\n", text));
+        assert!(markdown_sections("# a\n---\nid: [\n---\n").is_err());
+    }
+
+    #[test]
+    fn snippet_front_matter() {
+        assert!(has_snippet_front_matter("---\ntags: [git]\n---\n# log\n```\ngit log\n```\n"));
+        assert!(has_snippet_front_matter("\n+++\ntags = [\"shell\"]\n+++\n"));
+        assert!(!has_snippet_front_matter("# Notes\n\n```sh\nls\n```\n")); // no front matter
+        assert!(!has_snippet_front_matter("---\ntitle: Blog post\nlayout: post\n---\n")); // front matter of a static site
+        assert!(!has_snippet_front_matter("---\nid: a\n---\n")); // only tags before the first heading
+        assert!(!has_snippet_front_matter("---\ntags: [git]\n")); // not closed
+    }
+}
@@ -1,4 +1,16 @@
 pub mod parse_paras;
 pub mod snip;
+pub mod snippets_text;
+pub mod markdown;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 pub mod error;
 pub mod utils;
+pub mod dedupe;
+pub mod suggest;
+pub mod hnsw;
+pub mod binary;
+pub mod quantize;
+pub mod eval;
+pub mod shell;
+pub mod sync;
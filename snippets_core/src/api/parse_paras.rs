@@ -3,11 +3,29 @@ use std::fs::create_dir_all;
 use std::path::PathBuf;
 
 use argh::FromArgs;
-use embedding_lib::{Model, ModelType};
+use embedding_lib::{
+    Model,
+    ModelDtype,
+    ModelType,
+    model_dimension,
+    reranker::Reranker,
+};
 
 use crate::{
     //snip::SnipTag,
     SnipTag,
+    quantize::Quantization,
+    suggest::SuggestTarget,
+    sync::Prefer,
+    utils::{
+        SnippetFile,
+        SnippetSource,
+        expand_home,
+        find_snippet_sources,
+        glob_snippets,
+        is_sqlite,
+        walk_snippets,
+    },
     error::MyError,
 };
 
@@ -27,13 +45,25 @@ struct Paras {
     #[argh(option, short = 'e')]
     search: Option<String>,
 
-    /// specify *.snippets files or path, multiple files separated by commas, you can also set the environment variable SNIPPETS_FILE to set this argument
+    /// specify *.snippets, markdown (*.md) or SQLite (*.db, only valid for sqlite feature) snippet files, paths (searched recursively for *.snippets, *.snippets.md and markdown starting with tags front matter, skip files matched by .snippetsignore) or glob patterns (e.g. "~/notes/**/*.snippets") of the project namespace, multiple items separated by commas, default: *.snippets in the current path, .snippets file or .snippets/**/*.snippets in the current path and its parents, merged with the built-in, team and user (~/.snippets) snippets, you can also set the environment variable SNIPPETS_FILE to set this argument
     #[argh(option, short = 'f')]
     file: Option<String>,
 
-    /// select one model for semantic search, valid for -e, support: 1(granite-embedding-small-english-r2), 2(granite-embedding-english-r2), 3(granite-embedding-107m-multilingual), 4(granite-embedding-278m-multilingual), 5(all-MiniLM-L6-v2), 6(all-MiniLM-L12-v2), 7(paraphrase-multilingual-MiniLM-L12-v2), 8(mxbai-embed-large-v1), 9(mxbai-embed-xsmall-v1), 10(e5-base-v2), 11(multilingual-e5-small), you can also set the environment variable SNIPPETS_MODEL to set this argument
+    /// specify *.snippets files, paths or glob patterns of the team namespace like -f, multiple items separated by commas, overridden by user and project snippets, you can also set the environment variable SNIPPETS_TEAM to set this argument
+    #[argh(option)]
+    team: Option<String>,
+
+    /// local git repository of shared snippet files, loaded like --team and kept up to date by the sync subcommand, you can also set the environment variable SNIPPETS_REPO to set this argument
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// folder names between the path (or the glob pattern before the first wildcard) and each *.snippets file become tags of its snippets, only supported tags are used, you can also set the environment variable SNIPPETS_FOLDER_TAGS="true" to set this argument
+    #[argh(switch)]
+    folder_tags: bool,
+
+    /// select one model for semantic search, valid for -e, support: 1(granite-embedding-small-english-r2), 2(granite-embedding-english-r2), 3(granite-embedding-107m-multilingual), 4(granite-embedding-278m-multilingual), 5(all-MiniLM-L6-v2), 6(all-MiniLM-L12-v2), 7(paraphrase-multilingual-MiniLM-L12-v2), 8(mxbai-embed-large-v1), 9(mxbai-embed-xsmall-v1), 10(e5-base-v2), 11(multilingual-e5-small), 12(potion-base-8M), 13(potion-multilingual-128M), or the name of a model folder containing snippets_model.json, you can also set the environment variable SNIPPETS_MODEL to set this argument
     #[argh(option, short = 'm')]
-    model: Option<usize>,
+    model: Option<String>,
 
     /// path of the model folder, valid for -m, default: ./embedding_models/, you can also set the environment variable SNIPPETS_MODEL_PATH to set this argument
     #[argh(option, short = 'p')]
@@ -43,10 +73,66 @@ struct Paras {
     #[argh(switch, short = 'C')]
     cpu: bool,
 
-    /// the number of most similar results, valid for -m, default: 5, you can also set the environment variable SNIPPETS_NUM to set this argument
+    /// dtype of the model weights, support: f32, f16, bf16, f16 and bf16 use half the memory and are faster on gpu, f32 is used with a warning if candle can't run the model in this dtype, static models (12, 13) keep their lookup table in this dtype also on cpu, valid for -m and --rerank, default: f32, you can also set the environment variable SNIPPETS_DTYPE to set this argument
+    #[argh(option)]
+    dtype: Option<String>,
+
+    /// the number of most similar results, valid for -m and --rerank, default: 5, you can also set the environment variable SNIPPETS_NUM to set this argument
     #[argh(option, short = 'n')]
     num: Option<usize>,
 
+    /// use approximate nearest neighbour (HNSW) index for semantic search, only for collections of at least --ann-min snippets, otherwise use exact scan, valid for -m, you can also set the environment variable SNIPPETS_ANN="true" to set this argument
+    #[argh(switch, short = 'a')]
+    ann: bool,
+
+    /// candidate list size of the ANN index search, larger is more accurate but slower, valid for -a, default: 64
+    #[argh(option)]
+    ef: Option<usize>,
+
+    /// collections smaller than this use exact scan even with -a, valid for -a, default: 1000, you can also set the environment variable SNIPPETS_ANN_MIN to set this argument
+    #[argh(option)]
+    ann_min: Option<usize>,
+
+    /// the number of snippets embedded in one forward pass when calculating missing embeddings, valid for -m, default: 32, you can also set the environment variable SNIPPETS_BATCH_SIZE to set this argument
+    #[argh(option)]
+    batch_size: Option<usize>,
+
+    /// also compare the keyword with the snippet content, long content is split into overlapping chunks, the best chunk is the content score, valid for -m, you can also set the environment variable SNIPPETS_CONTENT="true" to set this argument
+    #[argh(switch)]
+    content: bool,
+
+    /// weight of the content score in [0, 1], the score is (1 - weight) * discription + weight * content, default: the max of discription and content score, valid for --content
+    #[argh(option)]
+    content_weight: Option<f32>,
+
+    /// compare only the first dimensions of the embeddings (renormalized), only for models trained with Matryoshka representation learning: 8, 9, or custom models with "matryoshka": true, default: the dimension of the built-in embeddings, valid for -m, you can also set the environment variable SNIPPETS_DIM to set this argument
+    #[argh(option)]
+    dim: Option<usize>,
+
+    /// quantized first pass of semantic search, the shortlist is rescored with f32 embeddings, support: int8, binary, valid for -m, ignored when -a uses the ANN index, you can also set the environment variable SNIPPETS_QUANTIZE to set this argument
+    #[argh(option)]
+    quantize: Option<String>,
+
+    /// the number of candidates of the quantized pass rescored with f32 embeddings, at least -n, valid for --quantize and quantize-report, built-in embeddings stored quantized cannot be rescored, default: 10 * -n
+    #[argh(option)]
+    rescore: Option<usize>,
+
+    /// minimum similarity of semantic search results, if no snippet reaches it, fall back to keyword search, valid for -m, you can also set the environment variable SNIPPETS_MIN_SCORE to set this argument
+    #[argh(option)]
+    min_score: Option<f32>,
+
+    /// only keep semantic search results within this margin of the best similarity, e.g. 0.1 keeps results >= best - 0.1, valid for -m, you can also set the environment variable SNIPPETS_SCORE_MARGIN to set this argument
+    #[argh(option)]
+    score_margin: Option<f32>,
+
+    /// rerank the top candidates of -e with a cross-encoder, support: 1(ms-marco-MiniLM-L6-v2), 2(ms-marco-MiniLM-L12-v2), 3(bge-reranker-base), 4(bge-reranker-v2-m3), or the name of any BERT or XLM-RoBERTa cross-encoder folder in the model path, searched like -m, you can also set the environment variable SNIPPETS_RERANK to set this argument
+    #[argh(option)]
+    rerank: Option<String>,
+
+    /// the number of candidates rescored by the cross-encoder, at least -n, valid for --rerank, default: 20, you can also set the environment variable SNIPPETS_RERANK_POOL to set this argument
+    #[argh(option)]
+    rerank_pool: Option<usize>,
+
     /// print selected snippets summary, support all and categories, multiple categories separated by commas
     #[argh(option, short = 'u')]
     summary: Option<String>,
@@ -63,9 +149,142 @@ struct Paras {
     #[argh(switch, short = 'T')]
     show_tags: bool,
 
+    /// print where the snippets are loaded from, you can also set the environment variable SNIPPETS_VERBOSE="true" to set this argument
+    #[argh(switch, short = 'v')]
+    verbose: bool,
+
     /// output path, default: ./saved_snippets/, you can also set the environment variable SNIPPETS_OUTPATH to set this argument
     #[argh(option, short = 'o')]
     outpath: Option<String>,
+
+    #[argh(subcommand)]
+    command: Option<SubCommand>,
+}
+
+/// subcommands
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum SubCommand {
+    Dedupe(DedupeParas),
+    SuggestTags(SuggestTagsParas),
+    Related(RelatedParas),
+    QuantizeReport(QuantizeReportParas),
+    Eval(EvalParas),
+    Shell(ShellParas),
+    Migrate(MigrateParas),
+    Sync(SyncParas),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "dedupe", help_triggers("-h", "--help"))]
+/// find near-duplicate snippets, use -m embedding model or text similarity
+struct DedupeParas {
+    /// similarity threshold, default: 0.9 for -m, 0.6 for text similarity
+    #[argh(option, short = 's')]
+    threshold: Option<f32>,
+
+    /// interactively merge each cluster into one snippet, keeping the union of tags
+    #[argh(switch, short = 'M')]
+    merge: bool,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "suggest-tags", help_triggers("-h", "--help"))]
+/// suggest tags by nearest tagged snippets, use -m embedding model or keyword heuristic
+struct SuggestTagsParas {
+    /// snippet id or *.snippets file (may contain untagged snippets)
+    #[argh(positional)]
+    target: Option<String>,
+
+    /// the number of nearest neighbours, default: 5
+    #[argh(option, short = 'k')]
+    neighbours: Option<usize>,
+
+    /// check all snippets, report snippets whose tags disagree with their neighbours
+    #[argh(switch, short = 'b')]
+    batch: bool,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "related", help_triggers("-h", "--help"))]
+/// get snippets similar to the given snippet id, use -m embedding model or lexical similarity
+struct RelatedParas {
+    /// snippet id
+    #[argh(positional)]
+    id: usize,
+
+    /// the number of most similar results, default: -n or 5
+    #[argh(option, short = 'n')]
+    num: Option<usize>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "quantize-report", help_triggers("-h", "--help"))]
+/// report the recall loss of int8 and binary quantization on your snippets, use -m embedding model
+struct QuantizeReportParas {
+    /// the number of nearest neighbours compared, default: -n or 5
+    #[argh(option, short = 'k')]
+    neighbours: Option<usize>,
+
+    /// the number of snippets used as queries, default: 100
+    #[argh(option, short = 'q')]
+    queries: Option<usize>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "eval", help_triggers("-h", "--help"))]
+/// evaluate keyword search and every available embedding model (or only -m) on labelled queries: recall@k, MRR, nDCG, failures and latency
+struct EvalParas {
+    /// labelled queries, one JSON object per line: {"query": "find files by name", "expected": [3, 17]}, expected are snippet ids
+    #[argh(option, short = 'q')]
+    queries: String,
+
+    /// the number of results evaluated for each query, default: 10
+    #[argh(option, short = 'k')]
+    k: Option<usize>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "shell", help_triggers("-h", "--help"))]
+/// interactive shell, the snippets and the model are loaded only once, type help to list the commands
+struct ShellParas {}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "migrate", help_triggers("-h", "--help"))]
+/// convert the -f *.snippets and markdown files to a SQLite database (created if missing), snippets migrated from the same file with the same id are updated and the snippets removed from it are deleted, the missing embeddings of -m are calculated, only valid for sqlite feature
+struct MigrateParas {
+    /// SQLite database file, e.g. team.db
+    #[argh(positional)]
+    db: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "sync", help_triggers("-h", "--help"))]
+/// commit local changes of the --repo git repository, rebase them on the default branch of the remote and push, conflicting *.snippets and markdown snippet files are merged snippet by snippet
+struct SyncParas {
+    /// remote repository, e.g. the path of a bare repository on a shared filesystem, saved as origin, default: origin of --repo, the repository is cloned if --repo does not exist
+    #[argh(option, short = 'r')]
+    remote: Option<String>,
+
+    /// commit message of local changes, default: "update snippets"
+    #[argh(option, short = 'M')]
+    message: Option<String>,
+
+    /// keep the local or remote version of snippets changed on both sides, support: local, remote, default: report them and abort
+    #[argh(option)]
+    prefer: Option<String>,
+}
+
+/// parsed subcommand
+pub enum Command {
+    Dedupe{threshold: Option<f32>, merge: bool},                                                 // find near-duplicate snippets
+    SuggestTags{target: SuggestTarget, neighbours: usize},                                       // suggest tags by nearest neighbours
+    Related{id: usize, num: usize},                                                              // get snippets similar to the given snippet
+    QuantizeReport{neighbours: usize, queries: usize},                                           // recall loss of quantization
+    Eval{queries: PathBuf, k: usize, models: Vec<ModelInfo>},                                    // evaluate keyword search and models on labelled queries
+    Shell{model_path: Option<String>, use_cpu: bool, dtype: ModelDtype, options: SearchOptions}, // interactive shell, switch models with -p, -C, --dtype and the options of -m
+    Migrate{db: PathBuf, files: Vec<PathBuf>},                                                   // convert -f files to SQLite database
+    Sync{repo: PathBuf, remote: Option<String>, message: String, prefer: Option<Prefer>},         // commit, rebase and push the --repo git repository
 }
 
 /// model info
@@ -76,22 +295,75 @@ pub struct ModelInfo {
     pub model_path:     PathBuf,
     pub config_path:    PathBuf,
     pub tokenizer_path: PathBuf,
-    pub top_num:        usize, // the number of most similar results
     pub use_cpu:        bool,
+    pub dtype:          ModelDtype,
+    pub options:        SearchOptions,
+}
+
+/// cross-encoder info
+#[derive(Clone)]
+pub struct RerankInfo {
+    pub reranker:       Reranker,
+    pub model_path:     PathBuf,
+    pub config_path:    PathBuf,
+    pub tokenizer_path: PathBuf,
+    pub use_cpu:        bool,
+    pub dtype:          ModelDtype,
+    pub pool:           usize, // the number of candidates rescored by the cross-encoder
+}
+
+/// semantic search options
+#[derive(Clone)]
+pub struct SearchOptions {
+    pub top_num:        usize,                // the number of most similar results
+    pub ann:            bool,                 // use approximate nearest neighbour index
+    pub ef:             usize,                // candidate list size of the ANN index search
+    pub ann_min:        usize,                // collections smaller than this use exact scan even with ann
+    pub batch_size:     usize,                // the number of snippets embedded in one forward pass
+    pub content:        bool,                 // also compare the keyword with content chunks
+    pub content_weight: Option<f32>,          // weight of the content score, None: max of discription and content score
+    pub quantize:       Option<Quantization>, // quantized first pass of semantic search
+    pub rescore:        Option<usize>,        // shortlist of the quantized pass, None: 10 * top_num
+    pub dim:            Option<usize>,        // Matryoshka truncation, None: full embeddings or the dimension of the built-in embeddings
+    pub min_score:      Option<f32>,          // minimum similarity of semantic search results, fall back to keyword search if none reaches it
+    pub score_margin:   Option<f32>,          // only keep results within this margin of the best similarity
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            top_num:        5,
+            ann:            false,
+            ef:             64,
+            ann_min:        1000,
+            batch_size:     32,
+            content:        false,
+            content_weight: None,
+            quantize:       None,
+            rescore:        None,
+            dim:            None,
+            min_score:      None,
+            score_margin:   None,
+        }
+    }
 }
 
 /// parsed paras
 pub struct ParsedParas {
-    pub ids:       Vec<usize>,        // get snippets by id, multiple ids separated by commas
-    pub tags:      Vec<SnipTag>,      // get snippets by tag, supported tags were written in snippets files or default 11 tags, multiple categories separated by commas
-    pub search:    Option<String>,    // get snippets by search keyword
-    pub files:     Vec<PathBuf>,      // if use -f specify *.snippets files, will not search from current path and binary file path
-    pub model:     Option<ModelInfo>, // selected model, model.safetensors, config.json, tokenizer.json, the number of most similar results
-    pub save:      bool,              // save -i or -t or -s to files, if not use -s, will print to terminal
-    pub clipboard: bool,              // copy -i or -t or -s to clipboard
-    pub show_tags: bool,              // print all supported tags
-    pub summary:   Vec<SnipTag>,      // print selected snippets summary
-    pub outpath:   PathBuf,           // save to this path, default: ./saved_snippets/
+    pub ids:       Vec<usize>,         // get snippets by id, multiple ids separated by commas
+    pub tags:      Vec<SnipTag>,       // get snippets by tag, supported tags were written in snippets files or default 11 tags, multiple categories separated by commas
+    pub search:    Option<String>,     // get snippets by search keyword
+    pub sources:   Vec<SnippetSource>, // snippet sources in precedence order, -f replaces the project sources
+    pub model:     Option<ModelInfo>,  // selected model, model.safetensors, config.json, tokenizer.json, the number of most similar results
+    pub rerank:    Option<RerankInfo>, // selected cross-encoder, rerank the top candidates of -e
+    pub options:   SearchOptions,      // the number of most similar results, ANN and batch options
+    pub save:      bool,               // save -i or -t or -s to files, if not use -s, will print to terminal
+    pub clipboard: bool,               // copy -i or -t or -s to clipboard
+    pub show_tags: bool,               // print all supported tags
+    pub verbose:   bool,               // print where the snippets are loaded from
+    pub summary:   Vec<SnipTag>,       // print selected snippets summary
+    pub outpath:   PathBuf,            // save to this path, default: ./saved_snippets/
+    pub command:   Option<Command>,    // subcommand
 }
 
 /// 解析参数
@@ -109,6 +381,35 @@ pub fn parse_para() -> Result<ParsedParas, MyError> {
             _ => unreachable!(),
         },
     };
+    let ann = if para.ann {
+        true
+    } else {
+        match EnvVarValue::Bool(false).get_env_var("SNIPPETS_ANN")? {
+            EnvVarValue::Bool(a) => a,
+            _ => unreachable!(),
+        }
+    };
+    let ef = para.ef.unwrap_or(64);
+    if ef == 0 {
+        return Err(MyError::ParaError{para: "--ef must > 0".to_string()})
+    }
+    let ann_min = match para.ann_min {
+        Some(m) => m,
+        None => match EnvVarValue::Str(String::new()).get_env_var("SNIPPETS_ANN_MIN")? {
+            EnvVarValue::Str(m) if !m.is_empty() => m.parse::<usize>().map_err(|e| MyError::ParseStringError{from: m.clone(), to: "usize".to_string(), error: e})?,
+            _ => 1000,
+        },
+    };
+    let batch_size = match para.batch_size {
+        Some(b) => b,
+        None => match EnvVarValue::Usize(32).get_env_var("SNIPPETS_BATCH_SIZE")? {
+            EnvVarValue::Usize(b) => b,
+            _ => unreachable!(),
+        },
+    };
+    if batch_size == 0 {
+        return Err(MyError::ParaError{para: "--batch-size must > 0".to_string()})
+    }
     let use_cpu = if para.cpu {
         true
     } else {
@@ -117,82 +418,171 @@ pub fn parse_para() -> Result<ParsedParas, MyError> {
             _ => unreachable!(),
         }
     };
-    let out: ParsedParas = ParsedParas{
+    let dtype = match para.dtype.or(match EnvVarValue::Str(String::new()).get_env_var("SNIPPETS_DTYPE")? {
+        EnvVarValue::Str(d) if !d.is_empty() => Some(d),
+        _ => None,
+    }) {
+        Some(d) => ModelDtype::from_name(&d).ok_or(MyError::ParaError{para: format!("--dtype only support f32, f16 and bf16, not {}", d)})?,
+        None => ModelDtype::F32,
+    };
+    let content = if para.content {
+        true
+    } else {
+        match EnvVarValue::Bool(false).get_env_var("SNIPPETS_CONTENT")? {
+            EnvVarValue::Bool(c) => c,
+            _ => unreachable!(),
+        }
+    };
+    if let Some(w) = para.content_weight {
+        if !(0.0..=1.0).contains(&w) {
+            return Err(MyError::ParaError{para: "--content-weight must be in [0, 1]".to_string()})
+        }
+    }
+    let quantize = match para.quantize.or(match EnvVarValue::Str(String::new()).get_env_var("SNIPPETS_QUANTIZE")? {
+        EnvVarValue::Str(q) if !q.is_empty() => Some(q),
+        _ => None,
+    }) {
+        Some(q) => Some(Quantization::from_name(&q).ok_or(MyError::ParaError{para: format!("--quantize only support int8 and binary, not {}", q)})?),
+        None => None,
+    };
+    if para.rescore == Some(0) {
+        return Err(MyError::ParaError{para: "--rescore must > 0".to_string()})
+    }
+    let dim = match para.dim {
+        Some(d) => Some(d),
+        None => match EnvVarValue::Str(String::new()).get_env_var("SNIPPETS_DIM")? {
+            EnvVarValue::Str(d) if !d.is_empty() => Some(d.parse::<usize>().map_err(|e| MyError::ParseStringError{from: d.clone(), to: "usize".to_string(), error: e})?),
+            _ => None,
+        },
+    };
+    if dim == Some(0) {
+        return Err(MyError::ParaError{para: "--dim must > 0".to_string()})
+    }
+    let min_score = match para.min_score {
+        Some(m) => Some(m),
+        None => match EnvVarValue::Str(String::new()).get_env_var("SNIPPETS_MIN_SCORE")? {
+            EnvVarValue::Str(m) if !m.is_empty() => Some(m.parse::<f32>().map_err(|e| MyError::ParaError{para: format!("couldn't parse SNIPPETS_MIN_SCORE {} to f32: {}", m, e)})?),
+            _ => None,
+        },
+    };
+    if let Some(m) = min_score {
+        if !(-1.0..=1.0).contains(&m) {
+            return Err(MyError::ParaError{para: "--min-score must be in [-1, 1]".to_string()})
+        }
+    }
+    let score_margin = match para.score_margin {
+        Some(m) => Some(m),
+        None => match EnvVarValue::Str(String::new()).get_env_var("SNIPPETS_SCORE_MARGIN")? {
+            EnvVarValue::Str(m) if !m.is_empty() => Some(m.parse::<f32>().map_err(|e| MyError::ParaError{para: format!("couldn't parse SNIPPETS_SCORE_MARGIN {} to f32: {}", m, e)})?),
+            _ => None,
+        },
+    };
+    if let Some(m) = score_margin {
+        if !(0.0..).contains(&m) {
+            return Err(MyError::ParaError{para: "--score-margin must >= 0".to_string()})
+        }
+    }
+    let options = SearchOptions{
+        top_num,
+        ann,
+        ef,
+        ann_min,
+        batch_size,
+        content,
+        content_weight: para.content_weight,
+        quantize,
+        rescore: para.rescore,
+        dim,
+        min_score,
+        score_margin,
+    };
+    let files = match para.file {
+        Some(f) => get_snippet_files(&f)?,
+        None => match EnvVarValue::VecPath(Vec::new()).get_env_var("SNIPPETS_FILE")? {
+            EnvVarValue::VecPath(f) => f,
+            _ => unreachable!(),
+        },
+    };
+    let mut team = match para.team {
+        Some(t) => get_snippet_files(&t)?,
+        None => match EnvVarValue::VecPath(Vec::new()).get_env_var("SNIPPETS_TEAM")? {
+            EnvVarValue::VecPath(t) => t,
+            _ => unreachable!(),
+        },
+    };
+    let repo = match para.repo {
+        Some(r) => Some(expand_home(&r)),
+        None => match EnvVarValue::Str(String::new()).get_env_var("SNIPPETS_REPO")? {
+            EnvVarValue::Str(r) if !r.is_empty() => Some(expand_home(&r)),
+            _ => None,
+        },
+    };
+    // snippet files of the repository are team snippets, it may not exist before the first sync
+    if let Some(r) = repo.as_ref().filter(|r| r.is_dir()) {
+        team.extend(walk_snippets(r));
+    }
+    let folder_tags = if para.folder_tags {
+        true
+    } else {
+        match EnvVarValue::Bool(false).get_env_var("SNIPPETS_FOLDER_TAGS")? {
+            EnvVarValue::Bool(f) => f,
+            _ => unreachable!(),
+        }
+    };
+    let model_selected = para.model.is_some();
+    let mut out: ParsedParas = ParsedParas{
         ids: match para.id {
-            Some(ids) => {
-                let mut ids_vec: Vec<usize> = Vec::new();
-                for i in ids.split(",") {
-                    match i.parse::<usize>() {
-                        Ok(idx) => ids_vec.push(idx),
-                        Err(e) => return Err(MyError::ParseStringError{from: i.to_string(), to: "usize".to_string(), error: e}),
-                    }
-                }
-                ids_vec
-            },
+            Some(ids) => parse_ids(&ids)?,
             None => Vec::new(),
         },
         tags: match para.tag {
-            Some(c) => {
-                let mut category_vec: Vec<SnipTag> = Vec::new();
-                for i in c.split(",") {
-                    let tmp_category = i.to_lowercase();
-                    if let Some(t) = SnipTag::string_to_tag(&tmp_category) {
-                        category_vec.push(t);
-                    } else {
-                        return Err(MyError::ParaError{para: format!("snippet tag only support: {}, not {}", SnipTag::supported_tags(), i)})
-                    }
-                }
-                category_vec
-            },
+            Some(c) => parse_tags(&c)?,
             None => Vec::new(),
         },
         search: para.search,
-        files: match para.file {
-            Some(f) => get_snippet_files(&f)?,
-            None => match EnvVarValue::VecPath(Vec::new()).get_env_var("SNIPPETS_FILE")? {
-                EnvVarValue::VecPath(f) => f,
-                _ => unreachable!(),
-            },
+        sources: if folder_tags {
+            find_snippet_sources(&files, &team)?
+        } else {
+            find_snippet_sources(&files, &team)?.into_iter().map(|s| s.without_folders()).collect()
         },
         model: match para.model {
-            Some(m) => {
-                let p = match &para.model_path {
-                    Some(p) => p.clone(),
+            Some(m) => Some(find_model(&m, &para.model_path, use_cpu, dtype, &options)?),
+            None => None,
+        },
+        rerank: match para.rerank.or(match EnvVarValue::Str(String::new()).get_env_var("SNIPPETS_RERANK")? {
+            EnvVarValue::Str(r) if !r.is_empty() => Some(r),
+            _ => None,
+        }) {
+            Some(r) => {
+                let (reranker, (model_path, config_path, tokenizer_path)) = match &para.model_path {
+                    Some(p) => Reranker::find(&r, &PathBuf::from(p)).map_err(|e| MyError::EmbeddingError{error: e})?,
                     None => {
-                        // get model from path of the current running executable
-                        let binary_path = match current_exe() {
-                            Ok(mut binary_path) => {
-                                if binary_path.pop() { // Truncates binary_path to parent
-                                    binary_path.join("embedding_models").to_str().unwrap().to_string()
-                                } else {
-                                    "./embedding_models/".to_string()
-                                }
-                            },
-                            Err(_) => "./embedding_models/".to_string(),
-                        };
-                        // get model from env
-                        let current_path_or_env = match EnvVarValue::Str("./embedding_models/".to_string()).get_env_var("SNIPPETS_MODEL_PATH")? {
-                            EnvVarValue::Str(p) => p,
-                            _ => unreachable!(),
-                        };
-                        // check modle exist, priority order: ./embedding_models/ > embedding_models in path of the current running executable > SNIPPETS_MODEL_PATH
-                        match (check_model_exist("./embedding_models/", m).is_ok(), check_model_exist(&binary_path, m).is_ok(), check_model_exist(&current_path_or_env, m).is_ok()) {
-                            (true, _, _) => "./embedding_models/".to_string(),
-                            (false, true, _) => binary_path,
-                            (false, false, true) => current_path_or_env,
-                            (false, false, false) => return Err(MyError::ParaError{para: format!(r#"couldn't find model in "./embedding_models/", "{}" and "SNIPPETS_MODEL_PATH""#, binary_path)}),
+                        // same priority order as -m
+                        let paths = default_model_paths()?;
+                        match paths.iter().find_map(|p| Reranker::find(&r, &PathBuf::from(p)).ok()) {
+                            Some(found) => found,
+                            None => Reranker::find_in_hf_cache(&r).map_err(|e| MyError::ParaError{para: format!(r#"couldn't find cross-encoder in "./embedding_models/", "{}" and "SNIPPETS_MODEL_PATH", {}"#, paths[1], e.to_string().trim_start_matches("Error - "))})?,
                         }
                     },
                 };
-                let (model, model_type, model_path, config_path, tokenizer_path) = check_model_exist(&p, m)?;
-                Some(ModelInfo{
-                    model,
-                    model_type,
+                let pool = match para.rerank_pool {
+                    Some(p) => p,
+                    None => match EnvVarValue::Usize(20).get_env_var("SNIPPETS_RERANK_POOL")? {
+                        EnvVarValue::Usize(p) => p,
+                        _ => unreachable!(),
+                    },
+                };
+                if pool == 0 {
+                    return Err(MyError::ParaError{para: "--rerank-pool must > 0".to_string()})
+                }
+                Some(RerankInfo{
+                    reranker,
                     model_path,
                     config_path,
                     tokenizer_path,
-                    top_num,
                     use_cpu,
+                    dtype,
+                    pool,
                 })
             },
             None => None,
@@ -232,7 +622,16 @@ pub fn parse_para() -> Result<ParsedParas, MyError> {
                 _ => unreachable!(),
             }
         },
+        options: options.clone(),
         show_tags: para.show_tags,
+        verbose: if para.verbose {
+            true
+        } else {
+            match EnvVarValue::Bool(false).get_env_var("SNIPPETS_VERBOSE")? {
+                EnvVarValue::Bool(v) => v,
+                _ => unreachable!(),
+            }
+        },
         outpath: match para.outpath {
             Some(o) => PathBuf::from(&o),
             None => match EnvVarValue::Path(PathBuf::from("./saved_snippets/")).get_env_var("SNIPPETS_OUTPATH")? {
@@ -240,8 +639,104 @@ pub fn parse_para() -> Result<ParsedParas, MyError> {
                 _ => unreachable!(),
             },
         },
+        command: match para.command {
+            Some(SubCommand::Dedupe(d)) => {
+                if let Some(t) = d.threshold {
+                    if !(0.0..=1.0).contains(&t) {
+                        return Err(MyError::ParaError{para: "dedupe -s must be in [0, 1]".to_string()})
+                    }
+                }
+                Some(Command::Dedupe{threshold: d.threshold, merge: d.merge})
+            },
+            Some(SubCommand::SuggestTags(s)) => {
+                let target = match (s.target, s.batch) {
+                    (Some(_), true) => return Err(MyError::ParaError{para: "cannot use suggest-tags <id|file> and -b simultaneously".to_string()}),
+                    (Some(t), false) => match t.parse::<usize>() {
+                        Ok(id) => SuggestTarget::Id(id),
+                        Err(_) => {
+                            let file = PathBuf::from(&t);
+                            if !(file.exists() && file.is_file()) {
+                                return Err(MyError::FileNotExistError{file: t})
+                            }
+                            SuggestTarget::File(file)
+                        },
+                    },
+                    (None, true) => SuggestTarget::All,
+                    (None, false) => return Err(MyError::ParaError{para: "suggest-tags needs a snippet id, a *.snippets file or -b".to_string()}),
+                };
+                let neighbours = s.neighbours.unwrap_or(5);
+                if neighbours == 0 {
+                    return Err(MyError::ParaError{para: "suggest-tags -k must > 0".to_string()})
+                }
+                Some(Command::SuggestTags{target, neighbours})
+            },
+            Some(SubCommand::Related(r)) => {
+                let num = r.num.unwrap_or(top_num);
+                if num == 0 {
+                    return Err(MyError::ParaError{para: "related -n must > 0".to_string()})
+                }
+                Some(Command::Related{id: r.id, num})
+            },
+            Some(SubCommand::QuantizeReport(q)) => {
+                let neighbours = q.neighbours.unwrap_or(top_num);
+                let queries = q.queries.unwrap_or(100);
+                if neighbours == 0 || queries == 0 {
+                    return Err(MyError::ParaError{para: "quantize-report -k and -q must > 0".to_string()})
+                }
+                Some(Command::QuantizeReport{neighbours, queries})
+            },
+            Some(SubCommand::Eval(e)) => {
+                let queries = PathBuf::from(&e.queries);
+                if !(queries.exists() && queries.is_file()) {
+                    return Err(MyError::FileNotExistError{file: e.queries})
+                }
+                let k = e.k.unwrap_or(10);
+                if k == 0 {
+                    return Err(MyError::ParaError{para: "eval -k must > 0".to_string()})
+                }
+                // -m selects one model (added below), otherwise all available models
+                let models = if model_selected || !cfg!(feature = "embedding") {
+                    Vec::new()
+                } else {
+                    available_models(&para.model_path, use_cpu, dtype, &options)?
+                };
+                Some(Command::Eval{queries, k, models})
+            },
+            Some(SubCommand::Shell(_)) => Some(Command::Shell{model_path: para.model_path.clone(), use_cpu, dtype, options: options.clone()}),
+            Some(SubCommand::Migrate(m)) => {
+                let db = expand_home(&m.db);
+                if !is_sqlite(&db) {
+                    return Err(MyError::ParaError{para: format!("migrate needs a *.db, *.sqlite or *.sqlite3 file, got {}", m.db)})
+                }
+                if files.is_empty() {
+                    return Err(MyError::ParaError{para: "migrate needs -f or SNIPPETS_FILE to specify the files to convert".to_string()})
+                }
+                Some(Command::Migrate{db, files: files.iter().map(|(f, _)| f.clone()).collect()})
+            },
+            Some(SubCommand::Sync(s)) => {
+                let repo = repo.ok_or(MyError::ParaError{para: "sync needs --repo or SNIPPETS_REPO to specify the git repository".to_string()})?;
+                let prefer = match s.prefer {
+                    Some(p) => Some(Prefer::from_name(&p).ok_or(MyError::ParaError{para: format!("sync --prefer must be local or remote, got {}", p)})?),
+                    None => None,
+                };
+                Some(Command::Sync{repo, remote: s.remote, message: s.message.unwrap_or("update snippets".to_string()), prefer})
+            },
+            None => None,
+        },
     };
-    if !out.show_tags {
+    // --dim is checked by find_model
+    if let Some(model_info) = &out.model {
+        out.options.dim = model_info.options.dim;
+    }
+    if let (Some(Command::Eval{models, ..}), Some(model_info)) = (out.command.as_mut(), &out.model) {
+        models.push(model_info.clone());
+    }
+    if out.command.is_some() {
+        // subcommand cannot be used with -i, -t, -e, -u
+        if !(out.ids.is_empty() && out.tags.is_empty() && out.search.is_none() && out.summary.is_empty()) {
+            return Err(MyError::ParaError{para: "cannot use -i, -t, -e, -u with subcommand".to_string()})
+        }
+    } else if !out.show_tags {
         // cannot use -i, -t, -e, -u simultaneously
         // -t and -e can be used simultaneously
         match (out.ids.is_empty(), out.tags.is_empty(), out.search.is_none(), out.summary.is_empty()) {
@@ -273,43 +768,261 @@ pub fn parse_para() -> Result<ParsedParas, MyError> {
             if para.cpu {
                 println!("Warning - -C is only valid for embedding feature");
             }
+            if dtype != ModelDtype::F32 {
+                println!("Warning - --dtype is only valid for embedding feature");
+            }
             if para.num.is_some() {
                 println!("Warning - -n is only valid for embedding feature");
             }
+            if para.ann {
+                println!("Warning - -a is only valid for embedding feature");
+            }
+            if para.content {
+                println!("Warning - --content is only valid for embedding feature");
+            }
+            if quantize.is_some() {
+                println!("Warning - --quantize is only valid for embedding feature");
+            }
+            if dim.is_some() {
+                println!("Warning - --dim is only valid for embedding feature");
+            }
+            if min_score.is_some() {
+                println!("Warning - --min-score is only valid for embedding feature");
+            }
+            if score_margin.is_some() {
+                println!("Warning - --score-margin is only valid for embedding feature");
+            }
+            if out.rerank.is_some() {
+                println!("Warning - --rerank is only valid for embedding feature");
+            }
         } else {
             // -m is only valid for -e
             if out.search.is_none() && out.model.is_some() {
                 println!("Warning - -m is only valid for -e");
             }
+            if content && ann {
+                println!("Warning - -a is ignored with --content, all snippets are compared");
+            }
+            if !content && para.content_weight.is_some() {
+                println!("Warning - --content-weight is only valid for --content");
+            }
+            if content && quantize.is_some() {
+                println!("Warning - --quantize is ignored with --content, all snippets are compared");
+            }
+            if quantize.is_none() && para.rescore.is_some() {
+                println!("Warning - --rescore is only valid for --quantize");
+            }
+            // --rerank is only valid for -e
+            if out.search.is_none() && out.rerank.is_some() {
+                println!("Warning - --rerank is only valid for -e");
+            }
+            if out.rerank.is_none() && para.rerank_pool.is_some() {
+                println!("Warning - --rerank-pool is only valid for --rerank");
+            }
             if out.model.is_none() {
-                if para.model_path.is_some() {
-                    println!("Warning - -p is only valid for -m");
+                if para.model_path.is_some() && out.rerank.is_none() {
+                    println!("Warning - -p is only valid for -m and --rerank");
+                }
+                if para.cpu && out.rerank.is_none() {
+                    println!("Warning - -C is only valid for -m and --rerank");
+                }
+                if dtype != ModelDtype::F32 && out.rerank.is_none() {
+                    println!("Warning - --dtype is only valid for -m and --rerank");
                 }
-                if para.cpu {
-                    println!("Warning - -C is only valid for -m");
+                if para.num.is_some() && out.rerank.is_none() {
+                    println!("Warning - -n is only valid for -m and --rerank");
                 }
-                if para.num.is_some() {
-                    println!("Warning - -n is only valid for -m");
+                if para.ann {
+                    println!("Warning - -a is only valid for -m");
+                }
+                if para.content {
+                    println!("Warning - --content is only valid for -m");
+                }
+                if quantize.is_some() {
+                    println!("Warning - --quantize is only valid for -m");
+                }
+                if dim.is_some() {
+                    println!("Warning - --dim is only valid for -m");
+                }
+                if min_score.is_some() {
+                    println!("Warning - --min-score is only valid for -m");
+                }
+                if score_margin.is_some() {
+                    println!("Warning - --score-margin is only valid for -m");
                 }
             }
         }
-        // if save, create output path
-        if out.save && !(out.outpath.exists() && out.outpath.is_dir()) {
-            if let Err(err) = create_dir_all(&out.outpath) {
-                return Err(MyError::CreateDirAllError{dir_name: out.outpath.to_str().unwrap().to_string(), error: err})
-            }
+    }
+    // if save, create output path
+    if !out.show_tags && out.save && !(out.outpath.exists() && out.outpath.is_dir()) {
+        if let Err(err) = create_dir_all(&out.outpath) {
+            return Err(MyError::CreateDirAllError{dir_name: out.outpath.to_str().unwrap().to_string(), error: err})
         }
     }
     Ok(out)
 }
 
+/// model paths searched when -p is not specified, priority order: ./embedding_models/ > embedding_models in path of the current running executable > SNIPPETS_MODEL_PATH
+fn default_model_paths() -> Result<[String; 3], MyError> {
+    // get model from path of the current running executable
+    let binary_path = match current_exe() {
+        Ok(mut binary_path) => {
+            if binary_path.pop() { // Truncates binary_path to parent
+                binary_path.join("embedding_models").to_str().unwrap().to_string()
+            } else {
+                "./embedding_models/".to_string()
+            }
+        },
+        Err(_) => "./embedding_models/".to_string(),
+    };
+    // get model from env
+    let current_path_or_env = match EnvVarValue::Str("./embedding_models/".to_string()).get_env_var("SNIPPETS_MODEL_PATH")? {
+        EnvVarValue::Str(p) => p,
+        _ => unreachable!(),
+    };
+    Ok(["./embedding_models/".to_string(), binary_path, current_path_or_env])
+}
+
+/// find model by -m value, in -p, or in the default model paths and then the Hugging Face cache
+pub(crate) fn find_model(name: &str, model_path: &Option<String>, use_cpu: bool, dtype: ModelDtype, options: &SearchOptions) -> Result<ModelInfo, MyError> {
+    let (model, model_type, model_path, config_path, tokenizer_path) = match model_path {
+        Some(p) => check_model_exist(p, name)?,
+        None => {
+            // check modle exist, priority order: ./embedding_models/ > embedding_models in path of the current running executable > SNIPPETS_MODEL_PATH > Hugging Face cache
+            let paths = default_model_paths()?;
+            match paths.iter().find_map(|p| check_model_exist(p, name).ok()) {
+                Some(found) => found,
+                None => match Model::find_in_hf_cache(name) {
+                    Ok((model_type, model, (model_path, config_path, tokenizer_path))) => (model, model_type, model_path, config_path, tokenizer_path),
+                    Err(e) => return Err(MyError::ParaError{para: format!(r#"couldn't find model in "./embedding_models/", "{}" and "SNIPPETS_MODEL_PATH", {}"#, paths[1], e.to_string().trim_start_matches("Error - "))}),
+                },
+            }
+        },
+    };
+    let mut model_info = ModelInfo{
+        model,
+        model_type,
+        model_path,
+        config_path,
+        tokenizer_path,
+        use_cpu,
+        dtype,
+        options: options.clone(),
+    };
+    check_dim(&mut model_info);
+    check_dtype(&mut model_info);
+    Ok(model_info)
+}
+
+/// --dim is only valid for Matryoshka models smaller than the model dimension, otherwise it is ignored with a warning
+fn check_dim(model_info: &mut ModelInfo) {
+    if let Some(d) = model_info.options.dim {
+        let ignored = if !model_info.model_type.matryoshka() {
+            Some(format!("{} is not trained with Matryoshka representation learning", model_info.model.name()))
+        } else {
+            match model_dimension(&model_info.config_path) {
+                Ok(full) if d >= full => Some(format!("it is not smaller than the {} dimensions of {}", full, model_info.model.name())),
+                _ => None,
+            }
+        };
+        if let Some(reason) = ignored {
+            println!("Warning - --dim is ignored, {}", reason);
+            model_info.options.dim = None;
+        }
+    }
+}
+
+/// --dtype f16 and bf16 are only valid for architectures candle can run in this dtype, bf16 also needs a gpu, otherwise f32 is used with a warning
+fn check_dtype(model_info: &mut ModelInfo) {
+    if !cfg!(feature = "embedding") {
+        return
+    }
+    let reason = match model_info.dtype.unsupported(&model_info.model_type.architecture()) {
+        Some(reason) => Some(format!("{}: {}", model_info.model.name(), reason)),
+        None if model_info.dtype.needs_gpu(&model_info.model_type.architecture()) && model_info.use_cpu => Some("bf16 needs a gpu, candle has no bf16 matmul on cpu".to_string()),
+        None => None,
+    };
+    if let Some(reason) = reason {
+        println!("Warning - --dtype {} is ignored, {}, f32 is used", model_info.dtype.name(), reason);
+        model_info.dtype = ModelDtype::F32;
+    }
+}
+
+/// all models in the model path (-p, or the default model paths) and built-in models in the Hugging Face cache
+fn available_models(model_path: &Option<String>, use_cpu: bool, dtype: ModelDtype, options: &SearchOptions) -> Result<Vec<ModelInfo>, MyError> {
+    let paths: Vec<String> = match model_path {
+        Some(p) => vec![p.clone()],
+        None => default_model_paths()?.to_vec(),
+    };
+    let mut found: Vec<(ModelType, Model, (PathBuf, PathBuf, PathBuf))> = Vec::new();
+    for p in paths {
+        if let Ok(dirs) = PathBuf::from(&p).read_dir() {
+            for entry in dirs.flatten() {
+                if let Ok(Some(m)) = Model::check_model(&entry.path()) {
+                    found.push(m);
+                }
+            }
+        }
+    }
+    if model_path.is_none() {
+        for model in (1..).map_while(|id| Model::new(id).ok()) {
+            if let Ok(m) = Model::find_in_hf_cache(model.name()) {
+                found.push(m);
+            }
+        }
+    }
+    let mut models: Vec<ModelInfo> = Vec::new();
+    for (model_type, model, (model_path, config_path, tokenizer_path)) in found {
+        if !models.iter().any(|m| m.model == model) { // same model in several paths, keep the one with higher priority
+            let mut model_info = ModelInfo{
+                model,
+                model_type,
+                model_path,
+                config_path,
+                tokenizer_path,
+                use_cpu,
+                dtype,
+                options: options.clone(),
+            };
+            check_dim(&mut model_info);
+            check_dtype(&mut model_info);
+            models.push(model_info);
+        }
+    }
+    Ok(models)
+}
+
+/// parse snippet ids separated by commas
+pub(crate) fn parse_ids(ids: &str) -> Result<Vec<usize>, MyError> {
+    let mut ids_vec: Vec<usize> = Vec::new();
+    for i in ids.split(",") {
+        match i.trim().parse::<usize>() {
+            Ok(idx) => ids_vec.push(idx),
+            Err(e) => return Err(MyError::ParseStringError{from: i.to_string(), to: "usize".to_string(), error: e}),
+        }
+    }
+    Ok(ids_vec)
+}
+
+/// parse snippet tags separated by commas, ignore case
+pub(crate) fn parse_tags(tags: &str) -> Result<Vec<SnipTag>, MyError> {
+    let mut category_vec: Vec<SnipTag> = Vec::new();
+    for i in tags.split(",") {
+        let tmp_category = i.trim().to_lowercase();
+        if let Some(t) = SnipTag::string_to_tag(&tmp_category) {
+            category_vec.push(t);
+        } else {
+            return Err(MyError::ParaError{para: format!("snippet tag only support: {}, not {}", SnipTag::supported_tags(), i)})
+        }
+    }
+    Ok(category_vec)
+}
+
 /// check path, model.safetensors, config.json, tokenizer.json exist
 /// return (model.safetensors path, config.json path, tokenizer.json path)
-fn check_model_exist(path_str: &str, id: usize) -> Result<(Model, ModelType, PathBuf, PathBuf, PathBuf), MyError> {
+fn check_model_exist(path_str: &str, name: &str) -> Result<(Model, ModelType, PathBuf, PathBuf, PathBuf), MyError> {
     let base_path = PathBuf::from(path_str);
-    let model = Model::new(id).map_err(|e| MyError::EmbeddingError{error: e})?;
-    let (model_type, model_dir) = model.model_type_and_path(&base_path);
-    let (model_path, config_path, tokenizer_path) = Model::check_model_files(&model_dir).map_err(|e| MyError::EmbeddingError{error: e})?;
+    let (model_type, model, (model_path, config_path, tokenizer_path)) = Model::find(name, &base_path).map_err(|e| MyError::EmbeddingError{error: e})?;
     Ok((model, model_type, model_path, config_path, tokenizer_path))
 }
 
@@ -319,7 +1032,7 @@ enum EnvVarValue {
     Str(String),
     Bool(bool),
     Path(PathBuf),
-    VecPath(Vec<PathBuf>),
+    VecPath(Vec<SnippetFile>),
 }
 
 impl EnvVarValue {
@@ -331,11 +1044,11 @@ impl EnvVarValue {
                     match str_var.parse::<usize>() {
                         Ok(n) => {
                             if n == 0 {
-                                return Err(MyError::ParaError{para: "environment variable SNIPPETS_NUM must > 0".to_string()})
+                                return Err(MyError::ParaError{para: format!("environment variable {} must > 0", var)})
                             }
                             Ok(EnvVarValue::Usize(n))
                         },
-                        Err(e) => return Err(MyError::ParseStringError{from: str_var.to_string(), to: "usize".to_string(), error: e}),
+                        Err(e) => Err(MyError::ParseStringError{from: str_var.to_string(), to: "usize".to_string(), error: e}),
                     }
                 },
                 EnvVarValue::Str(_) => Ok(EnvVarValue::Str(str_var)),
@@ -348,7 +1061,7 @@ impl EnvVarValue {
                 EnvVarValue::VecPath(_) => Ok(EnvVarValue::VecPath(get_snippet_files(&str_var)?)),
             },
             Err(e) => if let VarError::NotUnicode(s) = e {
-                return Err(MyError::EnvVarError{info: format!("couldn't interpret {}: {:?}", var, s)})
+                Err(MyError::EnvVarError{info: format!("couldn't interpret {}: {:?}", var, s)})
             } else {
                 Ok(self) // default value
             },
@@ -356,32 +1069,21 @@ impl EnvVarValue {
     }
 }
 
-/// get *.snippets files from commas seperated string or one path
-fn get_snippet_files(file: &str) -> Result<Vec<PathBuf>, MyError> {
-    let mut files: Vec<PathBuf> = Vec::new();
-    let tmp_dir = PathBuf::from(file);
-    if tmp_dir.exists() && tmp_dir.is_dir() {
-        if let Ok(dirs) = tmp_dir.read_dir() {
-            for i in dirs {
-                if let Ok(entry) = i {
-                    let tmp_file_path = entry.path();
-                    if tmp_file_path.is_file() {
-                        if let Some(ext) = tmp_file_path.extension() {
-                            if ext == "snippets" {
-                                files.push(tmp_file_path);
-                            }
-                        }
-                    }
-                }
-            }
+/// get snippet files from commas seperated string, each item is a file, a path (all *.snippets and markdown snippet files in it and its subdirectories, except those ignored by .snippetsignore), or a glob pattern
+fn get_snippet_files(file: &str) -> Result<Vec<SnippetFile>, MyError> {
+    let mut files: Vec<SnippetFile> = Vec::new();
+    for i in file.split(",").map(|i| i.trim()).filter(|i| !i.is_empty()) {
+        if i.contains(['*', '?', '[']) {
+            files.extend(glob_snippets(i)?);
+            continue
         }
-    } else {
-        for i in file.split(",") {
-            let tmp_file = PathBuf::from(i);
-            if !(tmp_file.exists() && tmp_file.is_file()) {
-                return Err(MyError::FileNotExistError{file: i.to_string()})
-            }
-            files.push(tmp_file);
+        let tmp_file = expand_home(i);
+        if tmp_file.is_dir() {
+            files.extend(walk_snippets(&tmp_file));
+        } else if tmp_file.is_file() {
+            files.push((tmp_file, Vec::new()));
+        } else {
+            return Err(MyError::FileNotExistError{file: i.to_string()})
         }
     }
     Ok(files)
@@ -0,0 +1,270 @@
+use std::cmp::Ordering;
+
+use tabled::{
+    builder::Builder,
+    settings::Style,
+};
+
+use crate::{
+    binary::{
+        quantize_binary,
+        quantize_int8,
+    },
+    snip::Snippets,
+    utils::cosine_similarity,
+    error::MyError,
+};
+
+/// default shortlist of the quantized pass is this many times the number of results
+pub(crate) const RESCORE_FACTOR: usize = 10;
+
+/// quantization of embeddings
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Quantization {
+    Int8,   // one signed byte per dimension, scaled by the max absolute value of each vector
+    Binary, // one bit per dimension, the sign of each value
+}
+
+impl Quantization {
+    /// parse --quantize or SNIPPETS_QUANTIZE value
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "int8"   => Some(Self::Int8),
+            "binary" => Some(Self::Binary),
+            _        => None,
+        }
+    }
+
+    /// quantization name
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Int8   => "int8",
+            Self::Binary => "binary",
+        }
+    }
+
+    /// bytes of one stored vector, int8 also stores the f32 scale
+    pub fn vector_bytes(&self, dim: usize) -> usize {
+        match self {
+            Self::Int8   => dim + 4,
+            Self::Binary => dim.div_ceil(8),
+        }
+    }
+}
+
+/// int8 codes back to f32
+pub(crate) fn dequantize_int8(scale: f32, codes: &[u8]) -> Vec<f32> {
+    codes.iter().map(|c| *c as i8 as f32 * scale).collect()
+}
+
+/// 1-bit codes of quantize_binary back to a unit vector of ±1/sqrt(dim)
+pub(crate) fn dequantize_binary(bits: &[u8], dim: usize) -> Vec<f32> {
+    let value = 1.0 / (dim.max(1) as f32).sqrt();
+    (0..dim).map(|i| if bits[i / 8] & (1 << (i % 8)) != 0 { value } else { -value }).collect()
+}
+
+/// 1-bit codes packed in little-endian words for popcount, bit i of word i / 64 is bit i of the codes
+fn binary_words(bits: &[u8]) -> Vec<u64> {
+    bits.chunks(8).map(|b| {
+        let mut word = [0u8; 8];
+        word[..b.len()].copy_from_slice(b);
+        u64::from_le_bytes(word)
+    }).collect()
+}
+
+/// quantized code of one vector
+enum Code {
+    Int8{codes: Vec<i8>, norm: f32}, // norm of the codes, the scale cancels out in cosine similarity
+    Binary(Vec<u64>),                // bits packed in words for popcount
+}
+
+impl Code {
+    fn new(quantization: Quantization, vector: &[f32]) -> Self {
+        match quantization {
+            Quantization::Int8 => {
+                let (_, codes) = quantize_int8(vector);
+                let norm = codes.iter().map(|c| (*c as f32).powi(2)).sum::<f32>().sqrt();
+                Self::Int8{codes, norm}
+            },
+            Quantization::Binary => Self::Binary(binary_words(&quantize_binary(vector))),
+        }
+    }
+
+    /// approximate cosine similarity, int8 dot product or 1 - 2 * hamming distance / dim
+    fn similarity(&self, other: &Self, dim: usize) -> f32 {
+        match (self, other) {
+            (Self::Int8{codes: a, norm: na}, Self::Int8{codes: b, norm: nb}) => {
+                if *na == 0.0 || *nb == 0.0 {
+                    return 0.0
+                }
+                let dot: i32 = a.iter().zip(b).map(|(x, y)| *x as i32 * *y as i32).sum();
+                dot as f32 / (na * nb)
+            },
+            (Self::Binary(a), Self::Binary(b)) => {
+                let hamming: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+                1.0 - 2.0 * hamming as f32 / dim.max(1) as f32
+            },
+            _ => 0.0,
+        }
+    }
+}
+
+/// quantized vectors of snippets, fast first pass of semantic search
+/// only the codes are kept, the shortlist is rescored with the f32 vectors given by reference
+pub(crate) struct QuantizedIndex {
+    quantization: Quantization,
+    dim:          usize,
+    codes:        Vec<Code>, // index is snippet id
+}
+
+impl QuantizedIndex {
+    /// quantize the vector of each snippet, index is snippet id
+    pub(crate) fn build(quantization: Quantization, vectors: &[&[f32]]) -> Self {
+        let dim = vectors.first().map(|v| v.len()).unwrap_or(0);
+        let codes = vectors.iter().map(|v| Code::new(quantization, v)).collect();
+        Self { quantization, dim, codes }
+    }
+
+    /// quantized pass only, the most similar `num` snippets accepted by `keep`, sorted by approximate similarity
+    pub(crate) fn shortlist(&self, query: &[f32], num: usize, keep: impl Fn(usize) -> bool) -> Vec<(usize, f32)> {
+        let query = Code::new(self.quantization, query);
+        let mut scores: Vec<(usize, f32)> = self.codes.iter()
+            .enumerate()
+            .filter(|(i, _)| keep(*i))
+            .map(|(i, c)| (i, query.similarity(c, self.dim)))
+            .collect();
+        let by_score = |a: &(usize, f32), b: &(usize, f32)| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal);
+        if num < scores.len() {
+            scores.select_nth_unstable_by(num, by_score);
+            scores.truncate(num);
+        }
+        scores.sort_by(by_score);
+        scores
+    }
+
+    /// quantized pass keeps `shortlist` candidates, then rescored with f32 cosine similarity of vectors, the vectors the index was built from
+    pub(crate) fn search(&self, vectors: &[&[f32]], query: &[f32], shortlist: usize, keep: impl Fn(usize) -> bool) -> Result<Vec<(usize, f32)>, MyError> {
+        self.shortlist(query, shortlist, keep)
+            .into_iter()
+            .map(|(i, _)| Ok((i, cosine_similarity(query, vectors[i])?)))
+            .collect()
+    }
+}
+
+impl Snippets {
+    /// report the recall of int8 and binary quantization on these snippets
+    /// each sampled snippet's discription embedding is used as query, the exact f32 top k (excluding the query itself) is the ground truth
+    pub fn quantize_report(&self, k: usize, queries: usize) -> Result<(), MyError> {
+        let mut calculated = Vec::new();
+        let embeddings = self.embedding_refs(&mut calculated)?.ok_or(MyError::ParaError{para: "quantize-report needs -m embedding model".to_string()})?;
+        let num = embeddings.len();
+        if num < 2 {
+            return Err(MyError::ParaError{para: "quantize-report needs at least 2 snippets".to_string()})
+        }
+        let dim = embeddings[0].len();
+        let k = k.min(num - 1);
+        let shortlist = self.options.rescore.unwrap_or(RESCORE_FACTOR * k).max(k);
+        // evenly spaced sample of snippets as queries
+        let queries = queries.min(num);
+        let samples: Vec<usize> = (0..queries).map(|q| q * num / queries).collect();
+
+        // exact top k of each query
+        let mut truth: Vec<Vec<usize>> = Vec::with_capacity(samples.len());
+        for q in &samples {
+            let mut scores: Vec<(usize, f32)> = Vec::with_capacity(num);
+            for (i, e) in embeddings.iter().enumerate() {
+                if i != *q {
+                    scores.push((i, cosine_similarity(embeddings[*q], e)?));
+                }
+            }
+            scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+            truth.push(scores.into_iter().take(k).map(|(i, _)| i).collect());
+        }
+        let recall = |found: &[(usize, f32)], truth: &[usize]| -> f32 {
+            found.iter().take(k).filter(|(i, _)| truth.contains(i)).count() as f32 / k as f32
+        };
+
+        let mut builder = Builder::default();
+        builder.push_record(["quantization", "bytes/vector", "size", &format!("recall@{}", k), &format!("recall@{} rescored ({})", k, shortlist)]);
+        builder.push_record(["f32".to_string(), (4 * dim).to_string(), "100.0%".to_string(), "1.000".to_string(), "1.000".to_string()]);
+        for quantization in [Quantization::Int8, Quantization::Binary] {
+            let index = QuantizedIndex::build(quantization, &embeddings);
+            let (mut quantized, mut rescored) = (0.0, 0.0);
+            for (q, t) in samples.iter().zip(&truth) {
+                quantized += recall(&index.shortlist(embeddings[*q], k, |i| i != *q), t);
+                let mut found = index.search(&embeddings, embeddings[*q], shortlist, |i| i != *q)?;
+                found.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+                rescored += recall(&found, t);
+            }
+            let bytes = quantization.vector_bytes(dim);
+            builder.push_record([
+                quantization.name().to_string(),
+                bytes.to_string(),
+                format!("{:.1}%", 100.0 * bytes as f32 / (4 * dim) as f32),
+                format!("{:.3}", quantized / samples.len() as f32),
+                format!("{:.3}", rescored / samples.len() as f32),
+            ]);
+        }
+        let mut table = builder.build();
+        table.with(Style::modern());
+        println!("{}", table);
+        println!("{} snippets, {} queries, recall loss is 1 - recall, rescored results keep the top {} of the quantized pass and compare them with f32 embeddings", num, samples.len(), shortlist);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn int8_round_trip() {
+        let vector = [0.5, -1.0, 0.25, 0.0, 0.999, -0.001];
+        let (scale, codes) = quantize_int8(&vector);
+        assert_eq!(codes[1], -127);
+        let restored = dequantize_int8(scale, &codes.iter().map(|c| *c as u8).collect::<Vec<_>>());
+        for (x, y) in vector.iter().zip(&restored) {
+            assert!((x - y).abs() <= scale / 2.0 + f32::EPSILON, "{} {}", x, y);
+        }
+        assert_eq!(quantize_int8(&[0.0, 0.0]), (1.0, vec![0, 0]));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let vector = [0.3, -0.2, 0.0, 1.0, -5.0, 0.1, 0.1, -0.1, 2.0];
+        let bits = quantize_binary(&vector);
+        let restored = dequantize_binary(&bits, vector.len());
+        let value = 1.0 / (vector.len() as f32).sqrt();
+        for (x, y) in vector.iter().zip(&restored) {
+            assert_eq!(*y, if *x > 0.0 { value } else { -value });
+        }
+        assert!((restored.iter().map(|y| y * y).sum::<f32>() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn binary_hamming_ranking() {
+        // 70 dimensions span two words, the query differs from vector i in 10 * i signs
+        let query: Vec<f32> = (0..70).map(|i| if i % 3 == 0 { 1.0 } else { -1.0 }).collect();
+        let vectors: Vec<Vec<f32>> = (0..5).map(|n| query.iter().enumerate().map(|(i, x)| if i < 10 * n { -x } else { *x }).collect()).collect();
+        let refs: Vec<&[f32]> = vectors.iter().rev().map(|v| v.as_slice()).collect(); // most different first
+        let index = QuantizedIndex::build(Quantization::Binary, &refs);
+        let shortlist = index.shortlist(&query, 3, |_| true);
+        assert_eq!(shortlist.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![4, 3, 2]);
+        assert_eq!(shortlist[0].1, 1.0);
+        assert!((shortlist[1].1 - (1.0 - 2.0 * 10.0 / 70.0)).abs() < 1e-6);
+        // keep filters candidates, rescoring uses the f32 vectors
+        let found = index.search(&refs, &query, 2, |i| i != 4).unwrap();
+        assert_eq!(found.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![3, 2]);
+        assert!((found[0].1 - cosine_similarity(&query, refs[3]).unwrap()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn int8_ranking() {
+        let vectors: Vec<Vec<f32>> = vec![vec![1.0, 0.0, 0.0], vec![0.7, 0.7, 0.0], vec![0.0, 0.0, 1.0]];
+        let refs: Vec<&[f32]> = vectors.iter().map(|v| v.as_slice()).collect();
+        let index = QuantizedIndex::build(Quantization::Int8, &refs);
+        let shortlist = index.shortlist(&[0.9, 0.1, 0.0], 3, |_| true);
+        assert_eq!(shortlist.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert!(shortlist[2].1.abs() < 1e-6);
+    }
+}
@@ -0,0 +1,230 @@
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use embedding_lib::ModelDtype;
+use rustyline::{
+    DefaultEditor,
+    error::ReadlineError,
+};
+
+use crate::{
+    SnipTag,
+    parse_paras::{
+        SearchOptions,
+        find_model,
+        parse_ids,
+        parse_tags,
+    },
+    snip::Snippets,
+    utils::home_dir,
+    error::MyError,
+};
+
+/// commands of the interactive shell
+const SHELL_HELP: &str = "commands:
+  search <keyword>        keyword search, or semantic search with the current model, like -e
+  tag <tags> [keyword]    snippets containing all tags (multiple tags separated by commas), search them if keyword is given, like -t and -e
+  show <ids>              print snippets, multiple ids separated by commas, like -i
+  copy <ids>              print snippets and copy them to clipboard, like -i -c
+  save <ids>              print snippets and save them to the output path, like -i -s
+  summary [all|tags]      print snippets summary, default: all, like -u
+  model [id|name|none]    switch the semantic search model, none: keyword search, print the current model without value
+  help                    print this help
+  exit, quit              leave the shell, or Ctrl-D";
+
+/// shell history file in the home directory
+fn history_file() -> Option<PathBuf> {
+    home_dir().map(|h| h.join(".snippets_history"))
+}
+
+/// split the first word (e.g. the command) from the trimmed rest of the line, the rest is empty if there is only one word
+fn split_first(line: &str) -> (&str, &str) {
+    match line.trim().split_once(char::is_whitespace) {
+        Some((first, rest)) => (first, rest.trim()),
+        None => (line.trim(), ""),
+    }
+}
+
+/// models of the shell are searched like -m
+struct ModelSearch<'a> {
+    model_path: &'a Option<String>, // -p
+    use_cpu:    bool,               // -C
+    dtype:      ModelDtype,         // --dtype
+    options:    &'a SearchOptions,  // options of -m, e.g. --dim
+}
+
+impl Snippets {
+    /// interactive shell, the snippets and the embedding model stay in memory between commands
+    /// line editing and history, the history is kept in ~/.snippets_history
+    pub fn shell(&mut self, model_path: &Option<String>, use_cpu: bool, dtype: ModelDtype, options: &SearchOptions, outpath: &Path) -> Result<(), MyError> {
+        let mut editor = DefaultEditor::new().map_err(|e| MyError::ShellError{error: e})?;
+        let history = history_file();
+        if let Some(h) = &history {
+            let _ = editor.load_history(h); // no history file at the first run
+        }
+        let search = ModelSearch { model_path, use_cpu, dtype, options };
+        // load the model of -m and calculate the missing embeddings before the first search
+        if let Err(e) = self.load_model() {
+            println!("{}", e);
+        }
+        println!("{} snippets, {}, type help to list the commands", self.data.len(), self.search_mode());
+        loop {
+            let line = match editor.readline("snippets> ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue, // Ctrl-C clears the line
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(MyError::ShellError{error: e}),
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue
+            }
+            let _ = editor.add_history_entry(line);
+            let (command, arg) = split_first(line);
+            if command == "exit" || command == "quit" {
+                break
+            }
+            if let Err(e) = self.shell_command(command, arg, &search, outpath) {
+                println!("{}", e);
+            }
+        }
+        if let Some(h) = &history {
+            if let Err(e) = editor.save_history(h) {
+                println!("Warning - couldn't save shell history to {}: {}", h.display(), e);
+            }
+        }
+        Ok(())
+    }
+
+    /// run one shell command
+    fn shell_command(&mut self, command: &str, arg: &str, search: &ModelSearch, outpath: &Path) -> Result<(), MyError> {
+        let needs_arg = |what: &str| MyError::ParaError{para: format!("{} needs {}, type help to list the commands", command, what)};
+        match command {
+            "help" => {
+                println!("{}", SHELL_HELP);
+                Ok(())
+            },
+            "search" => {
+                if arg.is_empty() {
+                    return Err(needs_arg("a keyword"))
+                }
+                let selected = self.get_by_search(None, arg)?;
+                self.output(selected, false, false, outpath)
+            },
+            "tag" => {
+                let (tags, keyword) = split_first(arg);
+                if tags.is_empty() {
+                    return Err(needs_arg("tags"))
+                }
+                let tags = parse_tags(tags)?;
+                let selected = if keyword.is_empty() {
+                    self.get_by_categories(&tags)?
+                } else {
+                    self.get_by_search(Some(tags), keyword)?
+                };
+                self.output(selected, false, false, outpath)
+            },
+            "show" | "copy" | "save" => {
+                if arg.is_empty() {
+                    return Err(needs_arg("snippet ids"))
+                }
+                let selected = self.get_by_ids(&parse_ids(arg)?)?;
+                let save = command == "save";
+                if save && !(outpath.exists() && outpath.is_dir()) {
+                    create_dir_all(outpath).map_err(|e| MyError::CreateDirAllError{dir_name: outpath.display().to_string(), error: e})?;
+                }
+                self.output(selected, save, command == "copy", outpath)?;
+                match command {
+                    "copy" => println!("copied to clipboard"),
+                    "save" => println!("saved to {}", outpath.display()),
+                    _ => (),
+                }
+                Ok(())
+            },
+            "summary" => {
+                let tags = if arg.is_empty() || arg.eq_ignore_ascii_case("all") {
+                    SnipTag::all_tags()
+                } else {
+                    parse_tags(arg)?
+                };
+                self.print_summary(&tags)
+            },
+            "model" => {
+                if arg.is_empty() {
+                    println!("{}", self.search_mode());
+                    return Ok(())
+                }
+                if !cfg!(feature = "embedding") {
+                    return Err(MyError::ParaError{para: "model is only valid for embedding feature".to_string()})
+                }
+                if arg.eq_ignore_ascii_case("none") {
+                    self.set_model(None);
+                } else {
+                    self.set_model(Some(find_model(arg, search.model_path, search.use_cpu, search.dtype, search.options)?));
+                    self.load_model()?;
+                }
+                println!("{}", self.search_mode());
+                Ok(())
+            },
+            _ => Err(MyError::ParaError{para: format!("unknown command {}, type help to list the commands", command)}),
+        }
+    }
+
+    /// load the semantic search model and calculate the missing embeddings, later searches only embed the keyword
+    fn load_model(&mut self) -> Result<(), MyError> {
+        if let Some((model, _)) = self.semantic_model() {
+            let start = Instant::now();
+            self.cache_embeddings()?;
+            println!("{} loaded in {:.1} s", model.name(), start.elapsed().as_secs_f64());
+        }
+        Ok(())
+    }
+
+    /// current search mode
+    fn search_mode(&self) -> String {
+        match self.semantic_model() {
+            Some((model, _)) => format!("semantic search with {}", model.name()),
+            None => "keyword search".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{remove_dir_all, write};
+
+    use crate::utils::SnippetSource;
+
+    #[test]
+    fn split_command_and_arg() {
+        assert_eq!(split_first("help"), ("help", ""));
+        assert_eq!(split_first("  search   list all files  "), ("search", "list all files"));
+        assert_eq!(split_first("tag\tgit,shell \t log"), ("tag", "git,shell \t log"));
+        assert_eq!(split_first("git,shell"), ("git,shell", ""));
+        assert_eq!(split_first(""), ("", ""));
+    }
+
+    #[test]
+    fn command_errors() {
+        let dir = std::env::temp_dir().join(format!("snippets-shell-{}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let file = dir.join("a.snippets");
+        write(&file, "[\n    SingleSnippet(id: \"ls\", tags: [\"shell\"], discription: \"list files\", content: \"ls -la\"),\n]\n").unwrap();
+        let mut snippets = Snippets::new(&[SnippetSource::Project((file, Vec::new()))], None).unwrap();
+        let options = SearchOptions::default();
+        let search = ModelSearch { model_path: &None, use_cpu: true, dtype: ModelDtype::F32, options: &options };
+        let mut error = |command: &str, arg: &str| snippets.shell_command(command, arg, &search, &dir).err().map(|e| e.to_string());
+        assert!(error("search", "").unwrap().contains("search needs a keyword"));
+        assert!(error("tag", "").unwrap().contains("tag needs tags"));
+        assert!(error("show", "").unwrap().contains("show needs snippet ids"));
+        assert!(error("show", "x").is_some());
+        assert!(error("tag", "no-such-tag files").unwrap().contains("snippet tag only support"));
+        assert!(error("run", "ls").unwrap().contains("unknown command run"));
+        assert_eq!(error("show", "0"), None);
+        assert_eq!(error("tag", "shell files"), None);
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,9 @@
+use std::cell::{OnceCell, RefCell};
 use std::collections::{HashMap, HashSet};
-use std::fs::read_to_string;
-use std::path::PathBuf;
+use std::env::current_exe;
+use std::fs::{create_dir_all, read_to_string};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
 use arboard::Clipboard;
 use ron::{
@@ -24,30 +27,66 @@ use tabled::{
 
 use crate::{
     SnipTag,
+    binary::BinaryDatabase,
     deserialize_tags_from_strings,
     parse_paras::{
         ModelInfo,
         ParsedParas,
+        RerankInfo,
+        SearchOptions,
+    },
+    quantize::{
+        QuantizedIndex,
+        RESCORE_FACTOR,
     },
     utils::{
-        get_snippet_files,
+        cache_dir,
+        cosine_similarity,
+        SnippetSource,
+        find_snippet_sources,
+        is_markdown,
+        is_sqlite,
         my_writer,
+        text_trigrams,
+        trigram_similarity,
     },
     error::MyError,
+    markdown::read_markdown_snippets,
+};
+
+use embedding_lib::{
+    CHUNK_OVERLAP,
+    CHUNK_WORDS,
+    EmbedMode,
+    Embedder,
+    Model,
+    chunk_text,
+    snippet_id,
+    split_discription,
+    truncate_embedding,
 };
 
 #[cfg(feature = "embedding")]
-use crate::utils::cosine_similarity;
+use embedding_lib::{
+    ModelEmbedder,
+    reranker::CrossEncoder,
+};
 
-use embedding_lib::split_discription;
+use crate::hnsw::{Hnsw, IndexKey};
 
-#[cfg(feature = "embedding")]
-use embedding_lib::Model;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::{
+    SnippetDb,
+    keyword_matches,
+    save_content_embeddings,
+    save_embeddings,
+    stored_content_embeddings,
+};
 
 
 /// compile default snippets file to binary
-/// build.rs will combine all ../snippets_database/*.snippets (exclude default.snippets) to default.snippets
-const SNIPPETS: &str = include_str!("../../../snippets_database/default.snippets");
+/// build.rs will combine all ../snippets_database/*.snippets (exclude example.snippets) to the compact binary database default.bin
+const SNIPPETS: &[u8] = include_bytes!("../../../snippets_database/default.bin");
 
 /*
 /// snippets category, add this tag to each snippet
@@ -105,98 +144,652 @@ impl SnipTag {
 /// single snippet
 /// #[allow(dead_code)]
 #[derive(Clone, Deserialize)]
-struct SingleSnippet {
-    #[serde(deserialize_with = "deserialize_tags_from_strings")]
-    tags:        HashSet<SnipTag>,                 // one snippet could have multiple tag
-    discription: String,                           // discription of this snippet
-    #[cfg(feature = "embedding")]
-    #[cfg_attr(not(feature = "embedding"), serde(skip))]
-    embedding:   Option<HashMap<Model, Vec<f32>>>, // discription's embedding, key: model, value: embedding vec
-    content:     String,                           // snippet content
+pub(crate) struct SingleSnippet {
+    #[serde(default)]
+    pub(crate) id:          String,                           // id in its source, default: discription in lowercase, words joined by '-'
+    #[serde(default, deserialize_with = "deserialize_tags_from_strings")]
+    pub(crate) tags:        HashSet<SnipTag>,                 // one snippet could have multiple tag
+    #[serde(default)]
+    pub(crate) discription: String,                           // discription of this snippet, empty for entries that only hide snippets
+    #[serde(default)]
+    pub(crate) embedding:   Option<HashMap<Model, Vec<f32>>>, // discription's embedding, key: model, value: embedding vec
+    #[serde(default)]
+    pub(crate) embedding_prompt: Option<HashMap<Model, String>>, // document prompt used to calculate the embedding, key: model, value: prompt, missing means raw discription
+    #[serde(default)]
+    pub(crate) content_embedding: Option<HashMap<Model, Vec<Vec<f32>>>>, // embedding of each content chunk, key: model, value: chunk embeddings
+    #[serde(default)]
+    pub(crate) content:     String,                           // snippet content
+    #[serde(default)]
+    pub(crate) overrides:   String,                           // namespace:id (or id of any namespace) of a snippet of a lower source replaced by this one
+    #[serde(default)]
+    pub(crate) hide:        Vec<String>,                      // namespace:id (or id of any namespace) of snippets of lower sources to hide
+    #[serde(skip)]
+    pub(crate) source:      Option<(PathBuf, usize)>,         // *.snippets file and index in this file, None for snippets in the binary file
+    #[serde(skip)]
+    pub(crate) namespace:   &'static str,                     // namespace of the source, e.g. builtin, team, user, project
 }
 
 impl SingleSnippet {
     /// convert tags to sorted Vec
-    fn sorted_tags(&self) -> Vec<SnipTag> {
+    pub(crate) fn sorted_tags(&self) -> Vec<SnipTag> {
         let mut tags: Vec<SnipTag> = self.tags.clone().into_iter().collect();
         tags.sort();
         tags
     }
 
     /// convert tags to string
-    fn format_tags(&self) -> String {
+    pub(crate) fn format_tags(&self) -> String {
         self.sorted_tags()
             .iter()
             .map(|t| t.to_string())
             .collect::<Vec<_>>()
             .join(", ")
     }
+
+    /// discription and content as one string, used for text similarity
+    pub(crate) fn full_text(&self) -> String {
+        format!("{} {}", self.discription.replace("\n", ""), self.content.trim())
+    }
+
+    /// stored discription embedding of the model
+    /// None if missing, or calculated with another document prompt than the model uses now (stale)
+    pub(crate) fn stored_embedding(&self, model: &Model, document_prompt: &str) -> Option<&Vec<f32>> {
+        let embedding = self.embedding.as_ref()?.get(model)?;
+        if self.stored_prompt(model) == document_prompt {
+            Some(embedding)
+        } else {
+            None
+        }
+    }
+
+    /// stored content chunk embeddings of the model, None if missing or stale like stored_embedding
+    pub(crate) fn stored_content_embedding(&self, model: &Model, document_prompt: &str) -> Option<&Vec<Vec<f32>>> {
+        let embedding = self.content_embedding.as_ref()?.get(model)?;
+        if self.stored_prompt(model) == document_prompt {
+            Some(embedding)
+        } else {
+            None
+        }
+    }
+
+    /// document prompt used to calculate the stored embeddings of the model
+    fn stored_prompt(&self, model: &Model) -> &str {
+        self.embedding_prompt.as_ref().and_then(|p| p.get(model)).map(|p| p.as_str()).unwrap_or("")
+    }
+
+    /// namespace:id, shown in results
+    pub(crate) fn qualified_id(&self) -> String {
+        format!("{}:{}", self.namespace, self.id)
+    }
+
+    /// whether namespace:id or id (any namespace) refers to this snippet
+    pub(crate) fn is(&self, target: &str) -> bool {
+        match target.split_once(':') {
+            Some((namespace, id)) => self.namespace == namespace && self.id == id,
+            None => self.id == target,
+        }
+    }
+
+    /// file name of this snippet, or "built-in" for snippets in the binary file
+    pub(crate) fn source_name(&self) -> String {
+        match &self.source {
+            Some((file, _)) => file.display().to_string(),
+            None => "built-in".to_string(),
+        }
+    }
+}
+
+/// snippet selected by -i, -t, -e or related, with its scores
+pub(crate) struct Selected {
+    pub(crate) id:         usize,
+    pub(crate) snippet:    SingleSnippet,
+    pub(crate) similarity: Option<f32>,          // cosine or lexical similarity, None for -i, -t and keyword search
+    pub(crate) rerank:     Option<f32>,          // cross-encoder score, only with --rerank
+    pub(crate) field:      Option<MatchedField>, // field that matched the keyword, only with --content
 }
 
+impl Selected {
+    /// selected snippet without scores
+    fn new(id: usize, snippet: &SingleSnippet, similarity: Option<f32>) -> Self {
+        Self { id, snippet: snippet.clone(), similarity, rerank: None, field: None }
+    }
+}
+
+/// snippet field that matched the keyword in semantic search
+#[derive(Clone, Copy)]
+pub(crate) enum MatchedField {
+    Discription,
+    Content(usize), // index of the content chunk
+}
+
+impl MatchedField {
+    /// field name shown in the summary, chunk number starts from 1
+    fn name(&self) -> String {
+        match self {
+            Self::Discription => "discription".to_string(),
+            Self::Content(c) => format!("content #{}", c + 1),
+        }
+    }
+}
+
+/// snippet as written in *.snippets file, without any modification
+/// other fields (e.g. embedding) are ignored, files are edited by SnippetsText to keep them
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(rename = "SingleSnippet")]
+pub(crate) struct FileSnippet {
+    #[serde(default)]
+    pub(crate) id:          String,
+    #[serde(default)]
+    pub(crate) tags:        Vec<String>,
+    #[serde(default)]
+    pub(crate) discription: String,
+    #[serde(default)]
+    pub(crate) content:     String,
+    #[serde(default)]
+    pub(crate) overrides:   String,
+    #[serde(default)]
+    pub(crate) hide:        Vec<String>,
+}
+
+/// FNV-1a hash continued with the bytes
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// read snippets of *.snippets, markdown or SQLite source file, discription is trimmed and the missing id is set
+pub(crate) fn read_source_file(file: &Path) -> Result<Vec<SingleSnippet>, MyError> {
+    let snippets = if is_sqlite(file) {
+        read_sqlite_snippets(file)?
+    } else {
+        let text = read_to_string(file)?;
+        if is_markdown(file) {
+            read_markdown_snippets(file, &text)?
+        } else {
+            from_str::<Vec<SingleSnippet>>(&text).map_err(ron_to_my_error)?
+        }
+    };
+    checked_snippets(file, snippets)
+}
+
+/// trim discription of the snippets read from file and set the missing id
+fn checked_snippets(file: &Path, mut snippets: Vec<SingleSnippet>) -> Result<Vec<SingleSnippet>, MyError> {
+    for (i, s) in snippets.iter_mut().enumerate() {
+        // trim space and remove `\r`
+        s.discription = s.discription.trim().replace("\r", "");
+        if s.discription.is_empty() && s.hide.is_empty() {
+            return Err(MyError::ParaError{para: format!("snippet {} in {} has no discription", i, file.display())})
+        }
+        // id from the discription before it is split
+        if s.id.is_empty() {
+            s.id = snippet_id(&s.discription);
+        }
+    }
+    Ok(snippets)
+}
+
+/// snippets in SQLite database
+#[cfg(feature = "sqlite")]
+fn read_sqlite_snippets(file: &Path) -> Result<Vec<SingleSnippet>, MyError> {
+    SnippetDb::open(file)?.snippets()
+}
+
+/// SQLite database is only valid for sqlite feature
+#[cfg(not(feature = "sqlite"))]
+fn read_sqlite_snippets(file: &Path) -> Result<Vec<SingleSnippet>, MyError> {
+    Err(MyError::ParaError{para: format!("{} is a SQLite database, only valid for sqlite feature", file.display())})
+}
+
+/// parse the text of *.snippets file without any modification, e.g. a version of the file in git
+pub(crate) fn parse_snippets_text(text: &str) -> Result<Vec<FileSnippet>, MyError> {
+    from_str::<Vec<FileSnippet>>(text).map_err(ron_to_my_error)
+}
+
+/// load the embedding model selected by -m
+#[cfg(feature = "embedding")]
+pub(crate) fn load_embedding_model(model_info: &ModelInfo) -> Result<Box<dyn Embedder>, MyError> {
+    let embedder = ModelEmbedder::load(
+        model_info.model.clone(),
+        model_info.model_type.clone(),
+        &model_info.model_path, // model.safetensors
+        &model_info.config_path, // config.json
+        &model_info.tokenizer_path, // tokenizer.json
+        model_info.use_cpu,
+        model_info.dtype,
+    ).map_err(|e| MyError::EmbeddingError{error: e})?;
+    Ok(Box::new(embedder))
+}
+
+/// calculate embedding of each text in batches, one forward pass per batch
+/// embeddings are truncated to the first dim dimensions if dim is specified
+pub(crate) fn batch_embeddings(embedder: &dyn Embedder, texts: &[String], mode: EmbedMode, batch_size: usize, dim: Option<usize>) -> Result<Vec<Vec<f32>>, MyError> {
+    let mut embeddings: Vec<Vec<f32>> = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(batch_size) {
+        let sentences: Vec<String> = batch.iter().map(|t| t.replace("\n", "")).collect();
+        let sentences: Vec<&str> = sentences.iter().map(|s| s.as_str()).collect();
+        let batch_embeddings = embedder.embed_batch(&sentences, mode).map_err(|e| MyError::EmbeddingError{error: e})?;
+        match dim {
+            Some(d) => embeddings.extend(batch_embeddings.iter().map(|e| truncate_embedding(e, d))),
+            None => embeddings.extend(batch_embeddings),
+        }
+    }
+    Ok(embeddings)
+}
+
+/// convert ron parse error to MyError
+fn ron_to_my_error(e: SpannedError) -> MyError {
+    if let SpannedError{code: ron_error::Message(m), position: Position{line, col}} = e {
+        MyError::ParaError{para: format!("{} position: line={}, column={}", m, line, col)}
+    } else {
+        MyError::ParaError{para: format!("parse snippets error: {:?}", e)}
+    }
+}
+
+/// keep the results (sorted by similarity) at least min_score and within score_margin of the best similarity
+/// the best result is always within the margin, so only min_score can drop all results
+/// return the best similarity if all results are dropped, semantic search falls back to keyword search
+fn score_cutoff(similarity: &mut Vec<(usize, f32)>, min_score: Option<f32>, score_margin: Option<f32>) -> Option<f32> {
+    let best = similarity.first()?.1;
+    let cutoff = score_margin.map(|m| best - m).into_iter().chain(min_score).reduce(f32::max)?;
+    similarity.retain(|(_, s)| *s >= cutoff);
+    if similarity.is_empty() { Some(best) } else { None }
+}
+
+/// content chunk embeddings calculated by --content, cached per (model, document prompt, --dim), value key: index in data
+type ContentCache = HashMap<(Model, String, Option<usize>), HashMap<usize, Vec<Vec<f32>>>>;
+
 /// store all snippets
 pub struct Snippets {
-    data:      Vec<SingleSnippet>,
+    pub(crate) data:      Vec<SingleSnippet>,
+    #[cfg(feature = "embedding")]
+    pub(crate) embedding: Option<ModelInfo>,                  // model selected by -m, loaded when needed
+    pub(crate) embedder:  OnceCell<Box<dyn Embedder>>,        // loaded model, or the embedder set by set_embedder
+    pub(crate) options:   SearchOptions,                      // semantic search options
+    pub(crate) rerank:    Option<RerankInfo>,                 // cross-encoder selected by --rerank, loaded when needed
     #[cfg(feature = "embedding")]
-    embedding: Option<ModelInfo>,
+    cross_encoder:        OnceCell<CrossEncoder>,             // loaded cross-encoder
+    builtin:              Option<BinaryDatabase>,             // snippets in the binary file, embeddings are decoded when needed
+    quantized:            OnceCell<QuantizedIndex>,           // quantized discription embeddings of --quantize, built only once
+    ann:                  OnceCell<Hnsw>,                     // ANN index of -a, loaded or built only once
+    dim:                  Option<usize>,                      // Matryoshka truncation of semantic search, None: full embeddings
+    content_cache:        RefCell<ContentCache>,              // content chunk embeddings of --content, calculated or loaded only once
+    sources:              Vec<(SnippetSource, usize, usize)>, // where the snippets are loaded from, the number of snippets and overridden or hidden snippets of each source
+    #[cfg(feature = "sqlite")]
+    databases:            HashMap<PathBuf, SnippetDb>,        // SQLite sources opened read-only once, for embeddings and keyword search
 }
 
 impl Snippets {
-    /// construct Snippets
-    pub fn new(
-        files: &Vec<PathBuf>,
-        #[cfg_attr(not(feature = "embedding"), allow(unused_variables))]
-        embedding: Option<ModelInfo>,
-    ) -> Result<Self, MyError> {
-        // if current path or binary file path contains *.snippets, read these files
-        // else use snippets in the binary file.
-        let files: Vec<PathBuf> = if files.is_empty() {
-            get_snippet_files()?
+    /// construct Snippets from sources in precedence order, see find_snippet_sources, empty: the default sources
+    /// all sources are merged, snippets of a source can override or hide snippets of lower sources by namespace:id
+    pub fn new(sources: &[SnippetSource], embedding: Option<ModelInfo>) -> Result<Self, MyError> {
+        let sources: Vec<SnippetSource> = if sources.is_empty() {
+            find_snippet_sources(&[], &[])?
         } else {
-            files.clone()
+            sources.to_vec()
         };
-        let mut data: Vec<SingleSnippet> = if files.is_empty() { // use snippets in the binary file
-            match from_str(SNIPPETS) {
-                Ok(p) => p,
-                Err(e) => if let SpannedError{code: ron_error::Message(m), position: Position{line, col}} = e {
-                    return Err(MyError::ParaError{para: format!("{} position: line={}, column={}", m, line, col)})
-                } else {
-                    return Err(MyError::ParaError{para: format!("parse snippets error: {:?}", e)})
+        let mut builtin: Option<BinaryDatabase> = None;
+        let mut data: Vec<SingleSnippet> = Vec::new();
+        let mut levels: Vec<usize> = Vec::new(); // index of the source of each snippet
+        #[cfg(feature = "sqlite")]
+        let mut databases: HashMap<PathBuf, SnippetDb> = HashMap::new();
+        for (level, source) in sources.iter().enumerate() {
+            let f = match source.path() {
+                Some(f) => f.to_path_buf(),
+                None => { // use snippets in the binary file, the lowest source, they are put first to decode the binary file embeddings
+                    let (database, builtin_data) = BinaryDatabase::parse(SNIPPETS)?;
+                    builtin = Some(database);
+                    levels.extend(vec![level; builtin_data.len()]);
+                    data.extend(builtin_data);
+                    continue
                 },
+            };
+            #[cfg(feature = "sqlite")]
+            let mut p = if is_sqlite(&f) {
+                let database = SnippetDb::open(&f)?;
+                let p = checked_snippets(&f, database.snippets()?)?;
+                databases.insert(f.clone(), database);
+                p
+            } else {
+                read_source_file(&f)?
+            };
+            #[cfg(not(feature = "sqlite"))]
+            let mut p = read_source_file(&f)?;
+            for (i, s) in p.iter_mut().enumerate() {
+                // split long discription to multiple short lines
+                s.discription = split_discription(&s.discription, 20);
+                // record source file and index in this file (rowid in SQLite database), used to rewrite the file
+                s.source = Some((f.clone(), s.source.take().map(|(_, rowid)| rowid).unwrap_or(i)));
+                s.namespace = source.namespace();
+                // supported tags of --folder-tags
+                s.tags.extend(source.folders().iter().filter_map(|d| SnipTag::string_to_tag(&d.to_lowercase())));
             }
-        } else { // get *.snippets files from current path or binary file path
-            let mut data: Vec<SingleSnippet> = Vec::new();
-            for f in files {
-                match from_str::<Vec<SingleSnippet>>(&read_to_string(&f)?) {
-                    Ok(mut p) => {
-                        for i in 0..p.len() {
-                            // trim space and remove `\r`
-                            p[i].discription = p[i].discription.trim().replace("\r", "");
-                            // split long discription to multiple short lines
-                            p[i].discription = split_discription(&p[i].discription, 20);
-                        }
-                        data.extend(p);
-                    },
-                    Err(e) => if let SpannedError{code: ron_error::Message(m), position: Position{line, col}} = e {
-                        return Err(MyError::ParaError{para: format!("{} position: line={}, column={}", m, line, col)})
-                    } else {
-                        return Err(MyError::ParaError{para: format!("parse snippets error: {:?}", e)})
-                    },
-                };
+            levels.extend(vec![level; p.len()]);
+            data.extend(p);
+        }
+
+        // override and hide snippets of lower sources, entries that only hide snippets are not snippets
+        let mut keep: Vec<bool> = data.iter().map(|s| !s.discription.is_empty()).collect();
+        for (i, s) in data.iter().enumerate() {
+            for target in Some(&s.overrides).filter(|o| !o.is_empty()).into_iter().chain(&s.hide) {
+                let lower: Vec<usize> = (0..data.len()).filter(|j| sources[levels[*j]].precedence() < sources[levels[i]].precedence() && data[*j].is(target)).collect();
+                if lower.is_empty() {
+                    println!("Warning - {} in {} is not found in lower sources", target, s.source_name());
+                }
+                for j in lower {
+                    keep[j] = false;
+                }
             }
-            data
-        };
-        // sort snippets
-        data.sort_by(|a, b| a.sorted_tags().cmp(&b.sorted_tags()).then(a.discription.cmp(&b.discription)));
+        }
+        let mut counts: Vec<(usize, usize)> = vec![(0, 0); sources.len()]; // (snippets, overridden or hidden) of each source
+        for (i, s) in data.iter().enumerate() {
+            if !s.discription.is_empty() {
+                counts[levels[i]].0 += 1;
+                if !keep[i] {
+                    counts[levels[i]].1 += 1;
+                }
+            }
+        }
+        let sources: Vec<(SnippetSource, usize, usize)> = sources.into_iter().zip(counts).map(|(s, (n, h))| (s, n, h)).collect();
 
-        Ok(Self {
+        // sort snippets, record the new index of each snippet for decoding the binary file embeddings
+        let mut order: Vec<usize> = (0..data.len()).filter(|i| keep[*i]).collect();
+        order.sort_by(|a, b| data[*a].sorted_tags().cmp(&data[*b].sorted_tags()).then(data[*a].discription.cmp(&data[*b].discription)));
+        let mut positions: Vec<Option<usize>> = vec![None; data.len()];
+        for (new, old) in order.iter().enumerate() {
+            positions[*old] = Some(new);
+        }
+        let mut data: Vec<Option<SingleSnippet>> = data.into_iter().map(Some).collect();
+        let data: Vec<SingleSnippet> = order.into_iter().filter_map(|i| data[i].take()).collect();
+        if let Some(database) = builtin.as_mut() {
+            positions.truncate(database.len());
+            database.set_positions(positions);
+        }
+
+        let mut snippets = Self {
             data,
+            options: embedding.as_ref().map(|m| m.options.clone()).unwrap_or_default(),
             #[cfg(feature = "embedding")]
             embedding,
-        })
+            embedder: OnceCell::new(),
+            rerank: None,
+            #[cfg(feature = "embedding")]
+            cross_encoder: OnceCell::new(),
+            builtin,
+            quantized: OnceCell::new(),
+            ann: OnceCell::new(),
+            dim: None,
+            content_cache: RefCell::new(HashMap::new()),
+            sources,
+            #[cfg(feature = "sqlite")]
+            databases,
+        };
+        snippets.load_stored_embeddings()?;
+        snippets.fit_dimensions();
+        snippets.warn_stale_embeddings();
+        snippets.warn_quantized_builtin();
+        Ok(snippets)
+    }
+
+    /// use this embedder for semantic search instead of the model selected by -m
+    pub fn set_embedder(&mut self, embedder: Box<dyn Embedder>) {
+        self.embedder = OnceCell::from(embedder);
+        self.quantized = OnceCell::new();
+        self.ann = OnceCell::new();
+        self.content_cache = RefCell::new(HashMap::new());
+        if let Err(e) = self.load_stored_embeddings() {
+            println!("Warning - {}, embeddings are calculated again", e);
+        }
+        self.fit_dimensions();
+        self.warn_stale_embeddings();
+        self.warn_quantized_builtin();
+    }
+
+    /// decode the stored embeddings of the semantic search model from the binary file and SQLite databases, other models are never decoded
+    fn load_stored_embeddings(&mut self) -> Result<(), MyError> {
+        let Some((model, _)) = self.semantic_model() else {
+            return Ok(())
+        };
+        if let Some(database) = self.builtin.as_mut() {
+            database.load_embeddings(&model, &mut self.data)?;
+        }
+        #[cfg(feature = "sqlite")]
+        for database in self.databases.values() {
+            database.load_embeddings(&model, &mut self.data)?;
+        }
+        Ok(())
+    }
+
+    /// use this model for semantic search (loaded when needed), or keyword search if None, only valid for embedding feature
+    /// --dim of the model is used, it was checked for this model
+    pub fn set_model(&mut self, model: Option<ModelInfo>) {
+        if let Some(m) = &model {
+            self.options.dim = m.options.dim;
+        }
+        #[cfg(feature = "embedding")]
+        {
+            self.embedding = model;
+        }
+        self.embedder = OnceCell::new();
+        self.quantized = OnceCell::new();
+        self.ann = OnceCell::new();
+        if let Err(e) = self.load_stored_embeddings() {
+            println!("Warning - {}, embeddings are calculated again", e);
+        }
+        self.fit_dimensions();
+        self.warn_stale_embeddings();
+        self.warn_quantized_builtin();
+    }
+
+    /// calculate the missing discription embeddings of the semantic search model once and keep them in memory
+    /// later searches only embed the keyword
+    pub(crate) fn cache_embeddings(&mut self) -> Result<(), MyError> {
+        let (model, prompt) = match self.semantic_model() {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+        if let Some(embeddings) = self.discription_embeddings()? {
+            for (s, e) in self.data.iter_mut().zip(embeddings) {
+                if s.stored_embedding(&model, &prompt).is_none() {
+                    s.embedding.get_or_insert_with(HashMap::new).insert(model.clone(), e);
+                    s.embedding_prompt.get_or_insert_with(HashMap::new).insert(model.clone(), prompt.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// set semantic search options, e.g. the number of most similar results
+    pub fn set_search_options(&mut self, options: SearchOptions) {
+        if options.quantize != self.options.quantize || options.dim != self.options.dim {
+            self.quantized = OnceCell::new();
+        }
+        if options.dim != self.options.dim {
+            self.ann = OnceCell::new();
+        }
+        let fit = options.dim != self.options.dim;
+        let rescore = options.quantize != self.options.quantize || options.rescore != self.options.rescore;
+        self.options = options;
+        if fit {
+            self.fit_dimensions();
+        }
+        if rescore {
+            self.warn_quantized_builtin();
+        }
+    }
+
+    /// rerank the top candidates of -e with this cross-encoder, only valid for embedding feature
+    pub fn set_reranker(&mut self, rerank: RerankInfo) {
+        self.rerank = Some(rerank);
+        #[cfg(feature = "embedding")]
+        {
+            self.cross_encoder = OnceCell::new();
+        }
+    }
+
+    /// Matryoshka truncation, semantic search compares the first --dim dimensions, renormalized
+    /// without --dim, the dimension of the built-in embeddings truncated at build time (SNIPPETS_MATRYOSHKA_DIM) is used
+    /// stored embeddings longer than the dimension are truncated, the others with another dimension than the query are calculated again
+    fn fit_dimensions(&mut self) {
+        let model = match self.semantic_model() {
+            Some((m, _)) => m,
+            None => return,
+        };
+        let full = self.model_dimension();
+        self.dim = self.options.dim.or_else(|| {
+            let stored = self.builtin.as_ref()?.dim(&model)?;
+            if stored < full? { Some(stored) } else { None }
+        });
+        let query_dim = match self.dim.or(full) {
+            Some(d) => d,
+            None => return,
+        };
+        let truncate = self.dim.is_some();
+        let mut mismatch: Vec<usize> = Vec::new(); // stored dimensions that disagree with the query
+        for s in self.data.iter_mut() {
+            if let Some(e) = s.embedding.as_mut() {
+                match e.get(&model).map(|v| v.len()) {
+                    Some(len) if len > query_dim && truncate => { e.insert(model.clone(), truncate_embedding(&e[&model], query_dim)); },
+                    Some(len) if len != query_dim => {
+                        e.remove(&model);
+                        mismatch.push(len);
+                    },
+                    _ => (),
+                }
+            }
+            if let Some(e) = s.content_embedding.as_mut() {
+                match e.get(&model).and_then(|c| c.first()).map(|v| v.len()) {
+                    Some(len) if len > query_dim && truncate => { e.insert(model.clone(), e[&model].iter().map(|c| truncate_embedding(c, query_dim)).collect()); },
+                    Some(len) if len != query_dim => { e.remove(&model); },
+                    _ => (),
+                }
+            }
+        }
+        if let Some(len) = mismatch.first() {
+            println!("Warning - {} stored {} embeddings have {} dimensions, but the query has {}, they are calculated again", mismatch.len(), model.name(), len, query_dim);
+        }
+    }
+
+    /// full embedding dimension of the semantic search model, without loading the model
+    fn model_dimension(&self) -> Option<usize> {
+        if let Some(embedder) = self.embedder.get() {
+            return Some(embedder.dimension())
+        }
+        #[cfg(feature = "embedding")]
+        if let Some(model_info) = &self.embedding {
+            return embedding_lib::model_dimension(&model_info.config_path).ok()
+        }
+        None
+    }
+
+    /// model identity and document prompt of semantic search, without loading the model
+    /// return None if no embedder is set and -m is not used or embedding feature is not enabled
+    pub(crate) fn semantic_model(&self) -> Option<(Model, String)> {
+        if let Some(embedder) = self.embedder.get() {
+            return Some((embedder.model(), embedder.prompt(EmbedMode::Document).to_string()))
+        }
+        #[cfg(feature = "embedding")]
+        if let Some(model_info) = &self.embedding {
+            return Some((model_info.model.clone(), model_info.model_type.prompt(EmbedMode::Document).to_string()))
+        }
+        None
+    }
+
+    /// embedder of semantic search, the one set by set_embedder, or the model selected by -m (loaded only once)
+    /// return None if no embedder is set and -m is not used or embedding feature is not enabled
+    pub(crate) fn embedder(&self) -> Result<Option<&dyn Embedder>, MyError> {
+        if let Some(embedder) = self.embedder.get() {
+            return Ok(Some(embedder.as_ref()))
+        }
+        #[cfg(feature = "embedding")]
+        if let Some(model_info) = &self.embedding {
+            let embedder = load_embedding_model(model_info)?;
+            return Ok(Some(self.embedder.get_or_init(|| embedder).as_ref()))
+        }
+        Ok(None)
+    }
+
+    /// stored embeddings calculated with another prompt convention will be calculated again
+    fn warn_stale_embeddings(&self) {
+        if let Some((model, prompt)) = self.semantic_model() {
+            let stale = self.data.iter().filter(|s| s.embedding.as_ref().map(|e| e.contains_key(&model)).unwrap_or(false) && s.stored_embedding(&model, &prompt).is_none()).count();
+            if stale > 0 {
+                println!("Warning - {} stored {} embeddings were calculated with another prompt convention, they are calculated again, rebuild the database to update them", stale, model.name());
+            }
+        }
+    }
+
+    /// built-in embeddings stored quantized (SNIPPETS_QUANTIZE) are decoded to approximate vectors, there are no f32 embeddings to rescore them
+    fn warn_quantized_builtin(&self) {
+        if let (Some((model, _)), Some(_), Some(database)) = (self.semantic_model(), self.options.quantize.and(self.options.rescore), &self.builtin) {
+            if let Some(quantization) = database.quantization(&model) {
+                println!("Warning - the built-in {} embeddings are stored as {}, --rescore compares the decoded {} vectors instead of f32 embeddings, rebuild without SNIPPETS_QUANTIZE to rescore them", model.name(), quantization.name(), quantization.name());
+            }
+        }
+    }
+
+    /// get discription embedding of each snippet for the semantic search model
+    /// use the embedding stored in snippets, calculate the missing ones
+    /// return None if no embedder is set and -m is not used or embedding feature is not enabled
+    pub(crate) fn discription_embeddings(&self) -> Result<Option<Vec<Vec<f32>>>, MyError> {
+        let mut calculated = Vec::new();
+        Ok(self.embedding_refs(&mut calculated)?.map(|e| e.into_iter().map(|v| v.to_vec()).collect()))
+    }
+
+    /// discription embedding of each snippet for the semantic search model, by reference, not copied
+    /// the missing ones are calculated into calculated
+    /// return None if no embedder is set and -m is not used or embedding feature is not enabled
+    pub(crate) fn embedding_refs<'a>(&'a self, calculated: &'a mut Vec<Vec<f32>>) -> Result<Option<Vec<&'a [f32]>>, MyError> {
+        let (model, prompt) = match self.semantic_model() {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+        let missing: Vec<usize> = self.data.iter().enumerate().filter(|(_, s)| s.stored_embedding(&model, &prompt).is_none()).map(|(i, _)| i).collect();
+        if !missing.is_empty() { // only load model when some embeddings are missing
+            let embedder = self.embedder()?.ok_or(MyError::ParaError{para: "no embedding model".to_string()})?;
+            let texts: Vec<String> = missing.iter().map(|i| self.data[*i].discription.clone()).collect();
+            *calculated = batch_embeddings(embedder, &texts, EmbedMode::Document, self.options.batch_size, self.dim)?;
+            #[cfg(feature = "sqlite")]
+            self.save_calculated_embeddings(&model, &prompt, &missing.iter().copied().zip(calculated.iter()).collect::<Vec<_>>());
+        }
+        let calculated: &'a Vec<Vec<f32>> = calculated;
+        let mut calculated = calculated.iter();
+        Ok(Some(self.data.iter().map(|s| match s.stored_embedding(&model, &prompt) {
+            Some(embed) => embed.as_slice(),
+            None => calculated.next().map(|e| e.as_slice()).unwrap_or_default(),
+        }).collect()))
+    }
+
+    /// keep the calculated full discription embeddings of snippets in SQLite databases, they are not calculated again next time
+    /// (index in data, embedding)
+    #[cfg(feature = "sqlite")]
+    fn save_calculated_embeddings(&self, model: &Model, prompt: &str, calculated: &[(usize, &Vec<f32>)]) {
+        if self.dim.is_none() {
+            if let Err(e) = save_embeddings(&self.data, calculated, model, prompt) {
+                println!("Warning - failed to save embeddings: {}", e);
+            }
+        }
+    }
+
+    /// calculate document embedding of each text with the semantic search model
+    /// return None if no embedder is set and -m is not used or embedding feature is not enabled
+    pub(crate) fn text_embeddings(&self, texts: &[String]) -> Result<Option<Vec<Vec<f32>>>, MyError> {
+        match self.embedder()? {
+            Some(embedder) => Ok(Some(batch_embeddings(embedder, texts, EmbedMode::Document, self.options.batch_size, self.dim)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// search snippets by keyword, semantic search if an embedder is set or -m is used, otherwise keyword search
+    /// return snippet id and similarity (None for keyword search)
+    pub fn search(&self, keyword: &str) -> Result<Vec<(usize, Option<f32>)>, MyError> {
+        Ok(self.get_by_search(None, keyword)?.into_iter().map(|s| (s.id, s.similarity)).collect())
     }
 
     /// get snippets by ids
-    fn get_by_ids(&self, ids: &Vec<usize>) -> Result<Vec<(usize, SingleSnippet, Option<f32>)>, MyError> {
+    pub(crate) fn get_by_ids(&self, ids: &Vec<usize>) -> Result<Vec<Selected>, MyError> {
         let mut snippets = Vec::new();
         for id in ids {
             if *id >= self.data.len() {
@@ -204,7 +797,7 @@ impl Snippets {
             }
             for (i, s) in self.data.iter().enumerate() {
                 if i == *id {
-                    snippets.push((*id, s.clone(), None));
+                    snippets.push(Selected::new(*id, s, None));
                 }
             }
         }
@@ -213,11 +806,11 @@ impl Snippets {
 
     /// get snippets by category
     /// if specify multiple categories, select only snippets that contain all specified tags
-    fn get_by_categories(&self, categories: &Vec<SnipTag>) -> Result<Vec<(usize, SingleSnippet, Option<f32>)>, MyError> {
+    pub(crate) fn get_by_categories(&self, categories: &[SnipTag]) -> Result<Vec<Selected>, MyError> {
         let mut snippets = Vec::new();
         for (i, s) in self.data.iter().enumerate() {
             if categories.iter().all(|c| s.tags.contains(c)) {
-                snippets.push((i, s.clone(), None));
+                snippets.push(Selected::new(i, s, None));
             }
         }
         Ok(snippets)
@@ -225,79 +818,292 @@ impl Snippets {
 
     /// get snippets by search keyword, ignore case
     /// -t and -e can be used simultaneously
-    fn get_by_search(&self, categories: Option<Vec<SnipTag>>, keyword: &str) -> Result<Vec<(usize, SingleSnippet, Option<f32>)>, MyError> {
+    /// with --rerank, the top candidates are rescored by the cross-encoder
+    pub(crate) fn get_by_search(&self, categories: Option<Vec<SnipTag>>, keyword: &str) -> Result<Vec<Selected>, MyError> {
         let mut snippets = Vec::new();
         let kw = keyword.to_lowercase();
-        if cfg!(feature = "embedding") {
-            // keyword search or semantic search
-            #[cfg(feature = "embedding")]
-            match &self.embedding {
-                Some(model_info) => { // semantic search
-                    // load embedding model
-                    let embedding_model = embedding_lib::EmbeddingModel::load_model(
-                        &model_info.model_type,
-                        &model_info.model_path, // model.safetensors
-                        &model_info.config_path, // config.json
-                        &model_info.tokenizer_path, // tokenizer.json
-                        model_info.use_cpu,
-                    ).map_err(|e| MyError::EmbeddingError{error: e})?;
-                    // calculate embedding
-                    let kw_embedding = embedding_model.get_embedding(&kw).map_err(|e| MyError::EmbeddingError{error: e})?;
-                    let mut snippets_similarity: Vec<(usize, f32)> = Vec::new();
-                    let mut discription_embedding: Vec<f32>;
+        // keyword search or semantic search
+        match self.semantic_model() {
+            Some((model, prompt)) => { // semantic search
+                // load embedding model
+                let embedder = self.embedder()?.ok_or(MyError::ParaError{para: "no embedding model".to_string()})?;
+                // calculate embedding
+                let mut kw_embedding = embedder.embed(&kw, EmbedMode::Query).map_err(|e| MyError::EmbeddingError{error: e})?;
+                if let Some(d) = self.dim {
+                    kw_embedding = truncate_embedding(&kw_embedding, d);
+                }
+                let mut snippets_similarity: Vec<(usize, f32)> = Vec::new();
+                if self.options.ann && !self.options.content && categories.is_none() && self.data.len() >= self.options.ann_min { // approximate nearest neighbour search
+                    snippets_similarity = self.ann_search(&model, &prompt, &kw_embedding, self.candidate_num())?;
+                } else if let (Some(quantization), false) = (self.options.quantize, self.options.content) { // quantized pass, then rescore the shortlist with the f32 embeddings
+                    let mut calculated = Vec::new();
+                    let embeddings = self.embedding_refs(&mut calculated)?.unwrap_or_default();
+                    let index = self.quantized.get_or_init(|| QuantizedIndex::build(quantization, &embeddings));
+                    let shortlist = self.options.rescore.unwrap_or(RESCORE_FACTOR * self.candidate_num()).max(self.candidate_num());
+                    snippets_similarity = index.search(&embeddings, &kw_embedding, shortlist, |i| categories.as_ref().map(|categ| categ.iter().all(|c| self.data[i].tags.contains(c))).unwrap_or(true))?;
+                } else {
+                    let mut missing: Vec<usize> = Vec::new(); // snippets without stored embedding
                     for (i, s) in self.data.iter().enumerate() {
                         if let Some(categ) = &categories {
                             if !categ.iter().all(|c| s.tags.contains(c)) {
                                 continue
                             }
                         }
-                        if let Some(snip_embed) = &s.embedding {
-                            if let Some(embed) = snip_embed.get(&model_info.model) {
-                                snippets_similarity.push((i, cosine_similarity(&kw_embedding, &embed)?));
-                            } else {
-                                discription_embedding = embedding_model.get_embedding(&s.discription.replace("\n", "")).map_err(|e| MyError::EmbeddingError{error: e})?;
-                                snippets_similarity.push((i, cosine_similarity(&kw_embedding, &discription_embedding)?));
-                            }
-                        } else {
-                            discription_embedding = embedding_model.get_embedding(&s.discription.replace("\n", "")).map_err(|e| MyError::EmbeddingError{error: e})?;
-                            snippets_similarity.push((i, cosine_similarity(&kw_embedding, &discription_embedding)?));
+                        match s.stored_embedding(&model, &prompt) {
+                            Some(embed) => snippets_similarity.push((i, cosine_similarity(&kw_embedding, embed)?)),
+                            None => missing.push(i),
                         }
                     }
-                    // sort by similarity
-                    snippets_similarity.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-                    let top_num = model_info.top_num.min(snippets_similarity.len());
-                    //println!("top {} similarity results:\nid\tsimilarity    discription", top_num);
-                    for (i, s) in &snippets_similarity[0..top_num] {
-                        //println!("{}\t{:.4}        {:}", i, s, self.data[*i].discription.replace("\n", ", "));
-                        snippets.push((*i, self.data[*i].clone(), Some(*s)));
+                    // calculate missing embeddings in batches
+                    let texts: Vec<String> = missing.iter().map(|i| self.data[*i].discription.clone()).collect();
+                    let calculated = batch_embeddings(embedder, &texts, EmbedMode::Document, self.options.batch_size, self.dim)?;
+                    #[cfg(feature = "sqlite")]
+                    self.save_calculated_embeddings(&model, &prompt, &missing.iter().copied().zip(calculated.iter()).collect::<Vec<_>>());
+                    for (i, embed) in missing.into_iter().zip(calculated) {
+                        snippets_similarity.push((i, cosine_similarity(&kw_embedding, &embed)?));
                     }
-                },
-                None => { // keyword search
-                    for (i, s) in self.data.iter().enumerate() {
-                        if let Some(categ) = &categories {
-                            if !categ.iter().all(|c| s.tags.contains(c)) {
-                                continue
-                            }
-                        }
-                        if s.discription.to_lowercase().replace("\n", "").contains(&kw) || s.content.to_lowercase().contains(&kw) {
-                            snippets.push((i, s.clone(), None));
-                        }
-                    }
-                },
+                }
+                // score content chunks, record the matched field
+                let fields = if self.options.content {
+                    self.content_scores(&model, &prompt, embedder, &kw_embedding, &mut snippets_similarity)?
+                } else {
+                    HashMap::new()
+                };
+                // sort by similarity
+                snippets_similarity.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+                // drop results below --min-score or more than --score-margin below the best similarity
+                if let Some(best) = score_cutoff(&mut snippets_similarity, self.options.min_score, self.options.score_margin) {
+                    println!("No snippet reaches --min-score {} with {} (best similarity {:.4}), fall back to keyword search", self.options.min_score.unwrap_or_default(), model.name(), best);
+                    return self.rerank(keyword, self.keyword_search(&categories, &kw))
+                }
+                let top_num = self.candidate_num().min(snippets_similarity.len());
+                //println!("top {} similarity results:\nid\tsimilarity    discription", top_num);
+                for (i, s) in &snippets_similarity[0..top_num] {
+                    //println!("{}\t{:.4}        {:}", i, s, self.data[*i].discription.replace("\n", ", "));
+                    let mut selected = Selected::new(*i, &self.data[*i], Some(*s));
+                    selected.field = fields.get(i).copied();
+                    snippets.push(selected);
+                }
+            },
+            None => snippets = self.keyword_search(&categories, &kw), // keyword search
+        }
+        self.rerank(keyword, snippets)
+    }
+
+    /// snippets whose discription or content contains the lowercase keyword
+    /// also the fallback of semantic search when no result reaches --min-score
+    fn keyword_search(&self, categories: &Option<Vec<SnipTag>>, kw: &str) -> Vec<Selected> {
+        let mut snippets = Vec::new();
+        #[cfg(feature = "sqlite")]
+        let fts = keyword_matches(&self.databases, kw); // snippets of SQLite databases are searched by FTS5
+        for (i, s) in self.data.iter().enumerate() {
+            if let Some(categ) = categories {
+                if !categ.iter().all(|c| s.tags.contains(c)) {
+                    continue
+                }
             }
-        } else {
-            for (i, s) in self.data.iter().enumerate() {
-                if let Some(categ) = &categories {
-                    if !categ.iter().all(|c| s.tags.contains(c)) {
-                        continue
+            #[cfg(feature = "sqlite")]
+            if let Some((f, rowid)) = &s.source {
+                if let Some(rows) = fts.get(f) {
+                    if rows.contains(rowid) {
+                        snippets.push(Selected::new(i, s, None));
                     }
+                    continue
                 }
-                if s.discription.to_lowercase().replace("\n", "").contains(&kw) || s.content.to_lowercase().contains(&kw) {
-                    snippets.push((i, s.clone(), None));
+            }
+            if s.discription.to_lowercase().replace("\n", "").contains(kw) || s.content.to_lowercase().contains(kw) {
+                snippets.push(Selected::new(i, s, None));
+            }
+        }
+        // keyword matches are not ranked, take the candidates most similar to the keyword for reranking
+        if self.rerank.is_some() && snippets.len() > self.candidate_num() {
+            let query = text_trigrams(kw);
+            let mut ranked: Vec<(f32, Selected)> = snippets.into_iter().map(|s| (trigram_similarity(&query, &text_trigrams(&s.snippet.full_text())), s)).collect();
+            ranked.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+            snippets = ranked.into_iter().take(self.candidate_num()).map(|(_, s)| s).collect();
+        }
+        snippets
+    }
+
+    /// score the content chunks of each candidate, the best chunk is the content score
+    /// combined score is the max of discription and content similarity, or weighted by --content-weight
+    /// chunk embeddings are stored in snippets or SQLite databases, the missing ones are calculated in batches and cached
+    /// return the matched field of each candidate
+    fn content_scores(&self, model: &Model, prompt: &str, embedder: &dyn Embedder, query: &[f32], similarity: &mut [(usize, f32)]) -> Result<HashMap<usize, MatchedField>, MyError> {
+        let mut best: HashMap<usize, (usize, f32)> = HashMap::new(); // key: snippet id, value: (chunk index, similarity)
+        let mut update = |i: usize, c: usize, simi: f32| {
+            let b = best.entry(i).or_insert((c, simi));
+            if simi > b.1 {
+                *b = (c, simi);
+            }
+        };
+        let mut cache = self.content_cache.borrow_mut();
+        let cached = cache.entry((model.clone(), prompt.to_string(), self.dim)).or_default();
+        // chunks saved in SQLite databases by earlier searches
+        #[cfg(feature = "sqlite")]
+        {
+            let unloaded: Vec<usize> = similarity.iter().map(|(i, _)| *i).filter(|i| self.data[*i].stored_content_embedding(model, prompt).is_none() && !cached.contains_key(i)).collect();
+            for (i, chunks) in stored_content_embeddings(&self.databases, &self.data, &unloaded, model, prompt) {
+                let chunks = match self.dim {
+                    Some(d) => chunks.iter().map(|c| truncate_embedding(c, d)).collect(),
+                    None => chunks,
+                };
+                cached.insert(i, chunks);
+            }
+        }
+        // calculate the remaining chunks in batches
+        let missing: Vec<usize> = similarity.iter().map(|(i, _)| *i).filter(|i| self.data[*i].stored_content_embedding(model, prompt).is_none() && !cached.contains_key(i)).collect();
+        let mut owners: Vec<usize> = Vec::new(); // snippet id of each chunk
+        let mut texts: Vec<String> = Vec::new();
+        for i in missing.iter() {
+            for t in chunk_text(&self.data[*i].content, CHUNK_WORDS, CHUNK_OVERLAP) {
+                owners.push(*i);
+                texts.push(t);
+            }
+        }
+        let mut calculated: HashMap<usize, Vec<Vec<f32>>> = missing.iter().map(|i| (*i, Vec::new())).collect(); // empty content has no chunk
+        for (i, e) in owners.into_iter().zip(batch_embeddings(embedder, &texts, EmbedMode::Document, self.options.batch_size, self.dim)?) {
+            calculated.entry(i).or_default().push(e);
+        }
+        #[cfg(feature = "sqlite")]
+        if self.dim.is_none() && !texts.is_empty() {
+            if let Err(e) = save_content_embeddings(&self.data, &calculated, model, prompt) {
+                println!("Warning - failed to save content embeddings: {}", e);
+            }
+        }
+        cached.extend(calculated);
+        for (i, _) in similarity.iter() {
+            let chunks = self.data[*i].stored_content_embedding(model, prompt).or_else(|| cached.get(i)).map(|c| c.as_slice()).unwrap_or_default();
+            for (c, e) in chunks.iter().enumerate() {
+                update(*i, c, cosine_similarity(query, e)?);
+            }
+        }
+        let mut fields: HashMap<usize, MatchedField> = HashMap::new();
+        for (i, score) in similarity.iter_mut() {
+            let (combined, field) = match (best.get(i), self.options.content_weight) {
+                (Some((c, content_score)), Some(w)) => {
+                    let field = if w * content_score > (1.0 - w) * *score { MatchedField::Content(*c) } else { MatchedField::Discription };
+                    ((1.0 - w) * *score + w * content_score, field)
+                },
+                (Some((c, content_score)), None) if content_score > score => (*content_score, MatchedField::Content(*c)),
+                _ => (*score, MatchedField::Discription), // empty content
+            };
+            *score = combined;
+            fields.insert(*i, field);
+        }
+        Ok(fields)
+    }
+
+    /// the number of candidates retrieved for -e, the larger of -n and --rerank-pool when reranking
+    fn candidate_num(&self) -> usize {
+        match &self.rerank {
+            Some(r) if cfg!(feature = "embedding") => r.pool.max(self.options.top_num),
+            _ => self.options.top_num,
+        }
+    }
+
+    /// rescore candidates with the --rerank cross-encoder (loaded only once), sort by rerank score and keep the top -n
+    /// return candidates unchanged if --rerank is not used or embedding feature is not enabled
+    #[cfg_attr(not(feature = "embedding"), allow(unused_variables))]
+    fn rerank(&self, keyword: &str, candidates: Vec<Selected>) -> Result<Vec<Selected>, MyError> {
+        #[cfg(feature = "embedding")]
+        if let Some(rerank) = &self.rerank {
+            let cross_encoder = match self.cross_encoder.get() {
+                Some(c) => c,
+                None => {
+                    let c = CrossEncoder::load(&rerank.model_path, &rerank.config_path, &rerank.tokenizer_path, rerank.use_cpu, rerank.dtype).map_err(|e| MyError::EmbeddingError{error: e})?;
+                    self.cross_encoder.get_or_init(|| c)
+                },
+            };
+            let mut candidates = candidates;
+            let texts: Vec<String> = candidates.iter().map(|s| s.snippet.full_text()).collect();
+            let mut scores: Vec<f32> = Vec::with_capacity(texts.len());
+            for batch in texts.chunks(self.options.batch_size) {
+                let documents: Vec<&str> = batch.iter().map(|t| t.as_str()).collect();
+                scores.extend(cross_encoder.score(keyword, &documents).map_err(|e| MyError::EmbeddingError{error: e})?);
+            }
+            for (s, score) in candidates.iter_mut().zip(scores) {
+                s.rerank = Some(score);
+            }
+            candidates.sort_by(|a, b| b.rerank.partial_cmp(&a.rerank).unwrap());
+            candidates.truncate(self.options.top_num);
+            return Ok(candidates)
+        }
+        Ok(candidates)
+    }
+
+    /// approximate nearest neighbour search with HNSW index
+    /// the index is built per model and saved in the cache path, rebuilt when the snippets or the model changed, kept in memory after the first search
+    fn ann_search(&self, model: &Model, prompt: &str, query: &[f32], k: usize) -> Result<Vec<(usize, f32)>, MyError> {
+        let mut calculated = Vec::new();
+        let embeddings = self.embedding_refs(&mut calculated)?.unwrap_or_default();
+        let index = match self.ann.get() {
+            Some(index) => index,
+            None => {
+                let key = IndexKey{
+                    snippets: embeddings.len(),
+                    model:    model.name().to_string(),
+                    prompt:   prompt.to_string(),
+                    dim:      embeddings.first().map(|e| e.len()).unwrap_or_default(),
+                    sources:  self.sources_hash(true),
+                    order:    self.order_hash(),
+                };
+                let index_file = self.ann_index_file(model);
+                let index = match index_file.as_ref().and_then(|f| Hnsw::load(f, &key)) {
+                    Some(index) => index,
+                    None => {
+                        let index = Hnsw::build(&embeddings, key)?;
+                        if let Some(f) = &index_file {
+                            if let Err(e) = index.save(f) {
+                                println!("Warning - save ANN index failed: {}", e);
+                            }
+                        }
+                        index
+                    },
+                };
+                self.ann.get_or_init(|| index)
+            },
+        };
+        index.search(&embeddings, query, k, self.options.ef)
+    }
+
+    /// ANN index file of the model in the cache path, one file for each set of sources
+    fn ann_index_file(&self, model: &Model) -> Option<PathBuf> {
+        let dir = cache_dir()?;
+        if let Err(e) = create_dir_all(&dir) {
+            println!("Warning - create cache path {} failed: {}", dir.display(), e);
+            return None
+        }
+        let name: String = model.name().chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect();
+        Some(dir.join(format!("ann-{}-{:016x}.hnsw", name, self.sources_hash(false))))
+    }
+
+    /// FNV-1a hash of the source files (the binary file for snippets in it), with their size and modification time if metadata is true
+    fn sources_hash(&self, metadata: bool) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for (source, _, _) in &self.sources {
+            let file = match source.path() {
+                Some(f) => f.to_path_buf(),
+                None => current_exe().unwrap_or_default(),
+            };
+            let mut bytes = file.display().to_string().into_bytes();
+            if metadata {
+                if let Ok(m) = file.metadata() {
+                    bytes.extend(m.len().to_le_bytes());
+                    let modified = m.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).unwrap_or_default();
+                    bytes.extend(modified.as_nanos().to_le_bytes());
                 }
             }
+            bytes.push(0);
+            hash = fnv1a(hash, &bytes);
         }
-        Ok(snippets)
+        hash
+    }
+
+    /// FNV-1a hash of namespace:id of the snippets in their order, the rows of the embeddings
+    /// e.g. --folder-tags changes the order (snippets are sorted by tags) without changing the source files
+    fn order_hash(&self) -> u64 {
+        self.data.iter().fold(0xcbf29ce484222325, |hash, s| fnv1a(hash, format!("{}\0", s.qualified_id()).as_bytes()))
     }
 
     /// get snippets by -i, -t, -e
@@ -307,21 +1113,63 @@ impl Snippets {
         } else {
             if let (true, Some(keyword)) = (!paras.tags.is_empty(), &paras.search) { // -t and -e can be used simultaneously
                 //self.get_by_categories_and_search(paras.tags, &keyword)?
-                self.get_by_search(Some(paras.tags), &keyword)?
+                self.get_by_search(Some(paras.tags.clone()), keyword)?
             } else if !paras.tags.is_empty() {
                 self.get_by_categories(&paras.tags)?
             } else if let Some(keyword) = &paras.search {
-                self.get_by_search(None, &keyword)?
+                self.get_by_search(None, keyword)?
             } else {
                 unreachable!()
             }
         };
+        self.output(selected_snippets, paras.save, paras.clipboard, &paras.outpath)
+    }
 
+    /// get snippets similar to the given snippet id
+    /// use the snippet's discription embedding as query if -m is specified, otherwise use its discription and content as lexical query
+    pub fn related(&self, id: usize, num: usize, paras: &ParsedParas) -> Result<(), MyError> {
+        let selected_snippets: Vec<Selected> = self.related_similarity(id)?.into_iter()
+            .take(num)
+            .map(|(i, s)| Selected::new(i, &self.data[i], Some(s)))
+            .collect();
+        self.output(selected_snippets, paras.save, paras.clipboard, &paras.outpath)
+    }
+
+    /// similarity of the other snippets to the given snippet id, sorted by similarity
+    fn related_similarity(&self, id: usize) -> Result<Vec<(usize, f32)>, MyError> {
+        if id >= self.data.len() {
+            return Err(MyError::ParaError{para: format!("no such snippet id: {}", id)})
+        }
+        let mut snippets_similarity: Vec<(usize, f32)> = Vec::new();
+        match self.discription_embeddings()? {
+            Some(embeddings) => { // semantic similarity
+                for (i, e) in embeddings.iter().enumerate() {
+                    if i != id {
+                        snippets_similarity.push((i, cosine_similarity(&embeddings[id], e)?));
+                    }
+                }
+            },
+            None => { // lexical similarity
+                let query = text_trigrams(&self.data[id].full_text());
+                for (i, s) in self.data.iter().enumerate() {
+                    if i != id {
+                        snippets_similarity.push((i, trigram_similarity(&query, &text_trigrams(&s.full_text()))));
+                    }
+                }
+            },
+        }
+        // sort by similarity
+        snippets_similarity.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        Ok(snippets_similarity)
+    }
+
+    /// save, copy to clipboard and print selected snippets
+    pub(crate) fn output(&self, selected_snippets: Vec<Selected>, save: bool, clipboard: bool, outpath: &Path) -> Result<(), MyError> {
         // save
-        if paras.save {
-            for (id, s, _) in &selected_snippets {
+        if save {
+            for Selected{id, snippet: s, ..} in &selected_snippets {
                 let (comment, fmt) = SnipTag::commit_format(&s.tags);
-                let out_file = paras.outpath.join(&format!("{}.{}", id, fmt));
+                let out_file = outpath.join(format!("{}.{}", id, fmt));
                 let mut writer = my_writer(&out_file)?;
                 if !comment.is_empty() {
                     writer.write_all(comment.as_bytes())?;
@@ -334,10 +1182,10 @@ impl Snippets {
         }
 
         // cp to clipboard
-        if paras.clipboard {
+        if clipboard {
             let mut clipboard = Clipboard::new().map_err(|e| MyError::ClipboardError{error: e})?;
             let mut all_content = "".to_string();
-            for (_, s, _) in &selected_snippets {
+            for Selected{snippet: s, ..} in &selected_snippets {
                 all_content += &s.content.trim().replace("\r", "");
                 all_content += "\n\n";
             }
@@ -348,37 +1196,55 @@ impl Snippets {
         let mut merge: Vec<usize> = Vec::new();
         let mut idx = 0;
         let mut builder = Builder::default();
-        for (i, s, _) in &selected_snippets {
-            builder.push_record(vec!["id", "discription", "categories"]);
-            builder.push_record(vec![&i.to_string(), &s.discription, &s.format_tags()]);
+        for Selected{id: i, snippet: s, ..} in &selected_snippets {
+            builder.push_record(vec!["id", "discription", "categories", "source"]);
+            builder.push_record(vec![&i.to_string(), &s.discription, &s.format_tags(), &s.qualified_id()]);
             idx += 2;
-            builder.push_record(vec![&s.content.trim().replace("\r", ""), "", ""]);
+            builder.push_record(vec![&s.content.trim().replace("\r", ""), "", "", ""]);
             merge.push(idx);
             idx += 1;
         }
         let mut table = builder.build();
         table.with(Style::modern()); // table style: ascii, extended, markdown, re_structured_text, dots, psql, ascii_rounded, blank, empty, rounded, modern, sharp
         for i in merge {
-            table.modify((i, 0), Span::column(3));
+            table.modify((i, 0), Span::column(4));
             table.modify((i, 0), BorderColor::filled(Color::FG_BRIGHT_RED)); // `FG_RED` not work in Windows Cmder
             table.modify((i, 1), BorderColor::filled(Color::FG_BRIGHT_RED));
             table.modify((i, 2), BorderColor::filled(Color::FG_BRIGHT_RED));
+            table.modify((i, 3), BorderColor::filled(Color::FG_BRIGHT_RED));
         }
         println!("{}", table);
         // print summary
-        if selected_snippets.len() > 1 || (selected_snippets.len() > 0 && selected_snippets[0].2.is_some()) {
+        let has_similarity = selected_snippets.first().map(|s| s.similarity.is_some()).unwrap_or(false);
+        let has_rerank = selected_snippets.first().map(|s| s.rerank.is_some()).unwrap_or(false);
+        let has_field = selected_snippets.first().map(|s| s.field.is_some()).unwrap_or(false);
+        if selected_snippets.len() > 1 || has_similarity || has_rerank {
             let mut builder = Builder::default();
-            if selected_snippets.len() > 0 && selected_snippets[0].2.is_some() {
-                builder.push_record(vec!["id", "similarity", "discription", "categories"]);
-            } else {
-                builder.push_record(vec!["id", "discription", "categories"]);
+            let mut header = vec!["id"];
+            if has_similarity {
+                header.push("similarity");
             }
-            for (i, s, similarity) in selected_snippets {
-                if let Some(simi) = similarity {
-                    builder.push_record(vec![&i.to_string(), &format!("{:.4}", simi), &s.discription, &s.format_tags()]);
-                } else {
-                    builder.push_record(vec![&i.to_string(), &s.discription, &s.format_tags()]);
+            if has_rerank {
+                header.push("rerank");
+            }
+            if has_field {
+                header.push("field");
+            }
+            header.extend(["discription", "categories", "source"]);
+            builder.push_record(header);
+            for s in selected_snippets {
+                let mut record = vec![s.id.to_string()];
+                if has_similarity {
+                    record.push(s.similarity.map(|simi| format!("{:.4}", simi)).unwrap_or_default());
                 }
+                if has_rerank {
+                    record.push(s.rerank.map(|score| format!("{:.4}", score)).unwrap_or_default());
+                }
+                if has_field {
+                    record.push(s.field.map(|f| f.name()).unwrap_or_default());
+                }
+                record.extend([s.snippet.discription.clone(), s.snippet.format_tags(), s.snippet.qualified_id()]);
+                builder.push_record(record);
             }
             let mut table = builder.build();
             table.with(Style::ascii()); // table style: ascii, extended, markdown, re_structured_text, dots, psql, ascii_rounded, blank, empty, rounded, modern, sharp
@@ -387,8 +1253,20 @@ impl Snippets {
         Ok(())
     }
 
+    /// print where the snippets are loaded from in precedence order, in verbose mode
+    pub fn print_sources(&self) {
+        let mut builder = Builder::default();
+        builder.push_record(["namespace", "path", "snippets", "overridden or hidden"]);
+        for (source, num, hidden) in &self.sources {
+            builder.push_record([source.namespace().to_string(), source.path().map(|p| p.display().to_string()).unwrap_or_default(), num.to_string(), hidden.to_string()]);
+        }
+        let mut table = builder.build();
+        table.with(Style::ascii()); // table style: ascii, extended, markdown, re_structured_text, dots, psql, ascii_rounded, blank, empty, rounded, modern, sharp
+        println!("{} snippets from {} sources:\n{}", self.data.len(), self.sources.len(), table);
+    }
+
     /// print all snippets summary
-    pub fn print_summary(&self, categories: &Vec<SnipTag>) -> Result<(), MyError> {
+    pub fn print_summary(&self, categories: &[SnipTag]) -> Result<(), MyError> {
         let mut stat: HashMap<SnipTag, usize> = HashMap::new(); // key: SnipTag, value: count
         let mut builder = Builder::default();
         builder.push_record(vec!["id", "discription", "categories"]);
@@ -410,7 +1288,7 @@ impl Snippets {
         println!("{}", table);
         // print each tag number
         let mut tags_count: Vec<(String, usize)> = stat.iter().map(|(k, v)| (k.to_string(), *v)).collect();
-        tags_count.sort_by(|a, b| b.1.cmp(&a.1)); // sort by tag count
+        tags_count.sort_by_key(|t| std::cmp::Reverse(t.1)); // sort by tag count
         let mut builder = Builder::default();
         builder.push_record(vec!["categories", "count"]);
         for t in tags_count {
@@ -423,3 +1301,181 @@ impl Snippets {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::fs::{remove_dir_all, write};
+    use std::rc::Rc;
+
+    use embedding_lib::error::EmbeddingError;
+
+    /// embeds the word count of each text, counts the embedded documents
+    struct CountingEmbedder {
+        documents: Rc<Cell<usize>>,
+    }
+
+    impl Embedder for CountingEmbedder {
+        fn model(&self) -> Model {
+            Model::Custom("counting".to_string())
+        }
+
+        fn dimension(&self) -> usize {
+            2
+        }
+
+        fn embed_batch(&self, sentences: &[&str], mode: EmbedMode) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+            if mode == EmbedMode::Document {
+                self.documents.set(self.documents.get() + sentences.len());
+            }
+            Ok(sentences.iter().map(|s| vec![s.split_whitespace().count() as f32, 1.0]).collect())
+        }
+    }
+
+    #[test]
+    fn batches_in_order() {
+        let documents = Rc::new(Cell::new(0));
+        let embedder = CountingEmbedder{documents: documents.clone()};
+        let texts: Vec<String> = ["a", "a b", "a b c", "a\nb\nc d", "a b c d e"].iter().map(|t| t.to_string()).collect();
+        let embeddings = batch_embeddings(&embedder, &texts, EmbedMode::Document, 2, None).unwrap();
+        assert_eq!(embeddings.iter().map(|e| e[0]).collect::<Vec<_>>(), vec![1.0, 2.0, 3.0, 2.0, 5.0]); // line breaks are removed
+        assert_eq!(documents.get(), 5);
+        // truncated to --dim and renormalized
+        let embeddings = batch_embeddings(&embedder, &texts[..1], EmbedMode::Query, 2, Some(1)).unwrap();
+        assert_eq!(embeddings, vec![vec![1.0]]);
+        assert_eq!(documents.get(), 5);
+    }
+
+    #[test]
+    fn search_with_custom_embedder() {
+        let dir = temp_dir("embedder");
+        let file = dir.join("a.snippets");
+        write(&file, "[\n    SingleSnippet(id: \"one\", tags: [], discription: \"one\", content: \"\"),\n    SingleSnippet(id: \"two\", tags: [], discription: \"two words\", content: \"\"),\n    SingleSnippet(id: \"three\", tags: [], discription: \"three words here\", content: \"\"),\n]\n").unwrap();
+        let documents = Rc::new(Cell::new(0));
+        let mut snippets = Snippets::new(&[SnippetSource::Project((file, Vec::new()))], None).unwrap();
+        assert!(snippets.semantic_model().is_none()); // keyword search without embedder
+        snippets.set_embedder(Box::new(CountingEmbedder{documents: documents.clone()}));
+        assert!(snippets.semantic_model() == Some((Model::Custom("counting".to_string()), String::new())));
+        // the query has 2 words, the same direction as "two words"
+        let found = snippets.search("x y").unwrap();
+        let ids: Vec<&str> = found.iter().map(|(i, _)| snippets.data[*i].id.as_str()).collect();
+        assert_eq!(ids, vec!["two", "three", "one"]);
+        assert!((found[0].1.unwrap() - 1.0).abs() < 1e-6);
+        assert_eq!(documents.get(), 3);
+        // embed is one sentence of embed_batch
+        assert_eq!(snippets.embedder().unwrap().unwrap().embed("a b c", EmbedMode::Query).unwrap(), vec![3.0, 1.0]);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn related_by_text() {
+        let dir = temp_dir("related");
+        let file = dir.join("a.snippets");
+        write(&file, "[\n    SingleSnippet(id: \"ls\", tags: [], discription: \"list files in a folder\", content: \"ls -la\"),\n    SingleSnippet(id: \"du\", tags: [], discription: \"disk usage of a folder\", content: \"du -sh\"),\n    SingleSnippet(id: \"tree\", tags: [], discription: \"list files in a folder as a tree\", content: \"tree -a\"),\n]\n").unwrap();
+        let snippets = Snippets::new(&[SnippetSource::Project((file, Vec::new()))], None).unwrap();
+        let id = |name: &str| snippets.data.iter().position(|s| s.id == name).unwrap();
+        // lexical similarity without a model, the snippet itself is excluded
+        let related = snippets.related_similarity(id("ls")).unwrap();
+        assert_eq!(related.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![id("tree"), id("du")]);
+        assert!(related[0].1 > related[1].1);
+        assert!(snippets.related_similarity(3).is_err());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn override_and_hide_by_precedence() {
+        let dir = temp_dir("layers");
+        let layer = |name: &str, entries: &[&str]| {
+            let file = dir.join(name);
+            write(&file, format!("[\n{}\n]\n", entries.join(",\n"))).unwrap();
+            (file, Vec::new())
+        };
+        let team = layer("team.snippets", &[
+            r#"SingleSnippet(id: "ls", tags: [], discription: "list files", content: "ls")"#,
+            r#"SingleSnippet(id: "du", tags: [], discription: "disk usage", content: "du -sh")"#,
+            r#"SingleSnippet(id: "df", tags: [], discription: "free disk space", content: "df -h")"#,
+            r#"SingleSnippet(tags: [], discription: "", content: "", hide: ["user:ls"])"#, // lower sources cannot hide higher ones
+        ]);
+        let user = layer("user.snippets", &[
+            r#"SingleSnippet(id: "ls", tags: [], discription: "list all files", content: "ls -a", overrides: "team:ls")"#,
+            r#"SingleSnippet(tags: [], discription: "", content: "", hide: ["df"])"#, // any namespace
+        ]);
+        let project = layer("project.snippets", &[
+            r#"SingleSnippet(id: "ls", tags: [], discription: "list files in long format", content: "ls -la", overrides: "ls")"#,
+        ]);
+        // sources are given in precedence order
+        let snippets = Snippets::new(&[SnippetSource::Team(team), SnippetSource::User(user), SnippetSource::Project(project)], None).unwrap();
+        assert_eq!(snippets.data.iter().map(|s| s.qualified_id()).collect::<Vec<_>>(), vec!["team:du", "project:ls"]);
+        assert_eq!(snippets.sources.iter().map(|(_, n, hidden)| (*n, *hidden)).collect::<Vec<_>>(), vec![(3, 2), (1, 1), (1, 0)]);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn min_score_and_margin() {
+        let sorted = vec![(3, 0.8), (1, 0.75), (2, 0.5)];
+        let cut = |min_score, score_margin| {
+            let mut similarity = sorted.clone();
+            let fallback = score_cutoff(&mut similarity, min_score, score_margin);
+            (similarity.iter().map(|(i, _)| *i).collect::<Vec<_>>(), fallback)
+        };
+        assert_eq!(cut(None, None), (vec![3, 1, 2], None));
+        assert_eq!(cut(Some(0.6), None), (vec![3, 1], None));
+        assert_eq!(cut(None, Some(0.1)), (vec![3, 1], None));
+        assert_eq!(cut(None, Some(0.0)), (vec![3], None)); // the best result is always kept
+        assert_eq!(cut(Some(0.7), Some(0.01)), (vec![3], None)); // the stricter cutoff
+        assert_eq!(cut(Some(0.9), Some(0.5)), (vec![], Some(0.8))); // fall back to keyword search
+        assert_eq!(score_cutoff(&mut Vec::new(), Some(0.9), None), None); // no candidate
+    }
+
+    /// empty temporary folder of the test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("snippets-snip-{}-{}", name, std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// snippets of the file, searched by the counting embedder with --content
+    fn content_search(file: &Path, documents: &Rc<Cell<usize>>) -> Snippets {
+        let mut snippets = Snippets::new(&[SnippetSource::Project((file.to_path_buf(), Vec::new()))], None).unwrap();
+        snippets.set_embedder(Box::new(CountingEmbedder{documents: documents.clone()}));
+        snippets.set_search_options(SearchOptions{content: true, ..Default::default()});
+        snippets
+    }
+
+    #[test]
+    fn content_chunks_cached() {
+        let dir = temp_dir("content");
+        let file = dir.join("a.snippets");
+        write(&file, "[\n    SingleSnippet(id: \"ls\", tags: [], discription: \"list files\", content: \"ls -la\"),\n    SingleSnippet(id: \"du\", tags: [], discription: \"disk usage\", content: \"\"),\n]\n").unwrap();
+        let documents = Rc::new(Cell::new(0));
+        let snippets = content_search(&file, &documents);
+        assert_eq!(snippets.search("files").unwrap().len(), 2);
+        assert_eq!(documents.get(), 3); // 2 discriptions, 1 chunk, empty content has none
+        snippets.search("usage").unwrap();
+        assert_eq!(documents.get(), 5); // only discriptions, chunks are cached
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn content_chunks_saved() {
+        let dir = temp_dir("content-db");
+        let file = dir.join("a.snippets");
+        write(&file, "[\n    SingleSnippet(id: \"ls\", tags: [], discription: \"list files\", content: \"ls -la\"),\n]\n").unwrap();
+        let db = dir.join("s.db");
+        let documents = Rc::new(Cell::new(0));
+        content_search(&file, &documents).migrate(&db, std::slice::from_ref(&file)).unwrap();
+        content_search(&db, &documents).search("files").unwrap();
+        assert_eq!(documents.get(), 2); // discription and chunk, both saved
+        content_search(&db, &documents).search("files").unwrap();
+        assert_eq!(documents.get(), 2); // loaded from the database
+        // edited content, its chunks are calculated again
+        write(&file, "[\n    SingleSnippet(id: \"ls\", tags: [], discription: \"list files\", content: \"ls -l\"),\n]\n").unwrap();
+        content_search(&file, &documents).migrate(&db, std::slice::from_ref(&file)).unwrap();
+        content_search(&db, &documents).search("files").unwrap();
+        assert_eq!(documents.get(), 3);
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,396 @@
+use std::fs::{read_to_string, write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::{
+    snip::{
+        FileSnippet,
+        parse_snippets_text,
+    },
+    error::MyError,
+};
+
+/// text of *.snippets file, edited snippet by snippet
+/// comments, formatting and fields which are not edited (e.g. embedding) are kept
+pub(crate) struct SnippetsText {
+    text:    String,
+    entries: Vec<Range<usize>>, // byte range of each snippet in the list, from `SingleSnippet(` or `(` to `)`
+    chunks:  Vec<Range<usize>>, // byte range of each snippet with its leading comments, until the comma after it
+}
+
+impl SnippetsText {
+    /// scan the text, find each snippet of the list
+    pub(crate) fn new(text: String) -> Result<Self, MyError> {
+        let (entries, chunks) = scan_list(text.as_bytes()).map_err(|pos| scan_error(&text, pos))?;
+        Ok(Self { text, entries, chunks })
+    }
+
+    /// read *.snippets file
+    pub(crate) fn read(file: &Path) -> Result<Self, MyError> {
+        let text = read_to_string(file).map_err(|e| MyError::ReadFileError{file: file.display().to_string(), error: e})?;
+        Self::new(text).map_err(|e| MyError::ParaError{para: format!("{}: {}", file.display(), e)})
+    }
+
+    /// write *.snippets file, the edited text is checked before writing
+    pub(crate) fn write(&self, file: &Path) -> Result<(), MyError> {
+        parse_snippets_text(&self.text).map_err(|e| MyError::ParaError{para: format!("edited {} is invalid, it is not written: {}", file.display(), e)})?;
+        write(file, &self.text).map_err(|e| MyError::WriteFileError{file: file.display().to_string(), error: e})
+    }
+
+    /// the snippet parsed, fields which FileSnippet does not have (e.g. embedding) are ignored
+    pub(crate) fn snippet(&self, idx: usize) -> Result<FileSnippet, MyError> {
+        ron::de::from_str::<FileSnippet>(&self.text[self.entries[idx].clone()]).map_err(|e| MyError::ParaError{para: format!("snippet {}: {}", idx, e)})
+    }
+
+    /// text before the first snippet (until `[`), text of each snippet with its leading comments, and text after the last snippet
+    /// a comma is added after the last snippet if missing, so the snippets can be put together in any order
+    pub(crate) fn parts(&self) -> (&str, Vec<String>, &str) {
+        let start = self.chunks.first().map(|c| c.start).unwrap_or_else(|| skip_trivia(self.text.as_bytes(), 0).unwrap_or(0) + 1);
+        let end = self.chunks.last().map(|c| c.end).unwrap_or(start);
+        let chunks = self.chunks.iter().zip(&self.entries).map(|(chunk, entry)| {
+            let text = &self.text[chunk.clone()];
+            if chunk.end == entry.end { format!("{},", text) } else { text.to_string() }
+        }).collect();
+        (&self.text[..start], chunks, &self.text[end..])
+    }
+
+    /// replace the tags of the snippet, add the tags field if missing
+    pub(crate) fn set_tags(&mut self, idx: usize, tags: &[String]) -> Result<(), MyError> {
+        let value = format!("[{}]", tags.iter().map(|t| format!("{:?}", t)).collect::<Vec<_>>().join(", "));
+        let Some(entry) = self.entries.get(idx).cloned() else {
+            return Err(MyError::ParaError{para: format!("no snippet {} in the file", idx)})
+        };
+        let fields = scan_fields(self.text.as_bytes(), entry.start).map_err(|pos| scan_error(&self.text, pos))?;
+        match fields.iter().find(|(name, _)| &self.text[name.clone()] == "tags") {
+            Some((_, range)) => self.text.replace_range(range.clone(), &value),
+            None => {
+                // insert before the first field, in the same line style
+                let (at, after) = match fields.first() {
+                    Some((name, _)) => {
+                        let line_start = self.text[..name.start].rfind('\n').map(|p| p + 1).unwrap_or(0);
+                        let indent = &self.text[line_start..name.start];
+                        let sep = if indent.trim().is_empty() { format!("\n{}", indent) } else { " ".to_string() };
+                        (name.start, sep)
+                    },
+                    None => (entry.end - 1, String::new()),
+                };
+                self.text.insert_str(at, &format!("tags: {},{}", value, after.replace('\n', self.newline())));
+            },
+        }
+        self.rescan()
+    }
+
+    /// remove the snippets with their leading comments
+    pub(crate) fn remove(&mut self, indexes: &[usize]) -> Result<(), MyError> {
+        let mut indexes: Vec<usize> = indexes.iter().copied().filter(|i| *i < self.chunks.len()).collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        for idx in indexes.into_iter().rev() {
+            self.text.replace_range(self.chunks[idx].clone(), "");
+        }
+        self.rescan()
+    }
+
+    /// line break used by the file
+    fn newline(&self) -> &'static str {
+        if self.text.contains("\r\n") { "\r\n" } else { "\n" }
+    }
+
+    /// find the snippets again after editing
+    fn rescan(&mut self) -> Result<(), MyError> {
+        let (entries, chunks) = scan_list(self.text.as_bytes()).map_err(|pos| scan_error(&self.text, pos))?;
+        self.entries = entries;
+        self.chunks = chunks;
+        Ok(())
+    }
+}
+
+/// error at the byte position of the text
+fn scan_error(text: &str, pos: usize) -> MyError {
+    let before = &text[..pos.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map(|p| p + 1).unwrap_or(0) + 1;
+    MyError::ParaError{para: format!("invalid snippets list position: line={}, column={}", line, col)}
+}
+
+/// skip whitespace and comments (// and nested /* */), return the next position
+fn skip_trivia(b: &[u8], mut pos: usize) -> Result<usize, usize> {
+    loop {
+        match (b.get(pos), b.get(pos + 1)) {
+            (Some(c), _) if c.is_ascii_whitespace() => pos += 1,
+            (Some(b'/'), Some(b'/')) => {
+                while pos < b.len() && b[pos] != b'\n' {
+                    pos += 1;
+                }
+            },
+            (Some(b'/'), Some(b'*')) => {
+                let start = pos;
+                let mut depth = 0;
+                loop {
+                    match (b.get(pos), b.get(pos + 1)) {
+                        (Some(b'/'), Some(b'*')) => { depth += 1; pos += 2; },
+                        (Some(b'*'), Some(b'/')) => {
+                            depth -= 1;
+                            pos += 2;
+                            if depth == 0 {
+                                break
+                            }
+                        },
+                        (Some(_), _) => pos += 1,
+                        (None, _) => return Err(start),
+                    }
+                }
+            },
+            _ => return Ok(pos),
+        }
+    }
+}
+
+/// skip one value: string, raw string, char, (), [] or {} group, or identifier/number optionally followed by a () group
+fn skip_value(b: &[u8], pos: usize) -> Result<usize, usize> {
+    match b.get(pos) {
+        Some(b'"') | Some(b'\'') => skip_string(b, pos),
+        Some(b'r') if matches!(b.get(pos + 1), Some(b'"') | Some(b'#')) => skip_string(b, pos),
+        Some(b'(') | Some(b'[') | Some(b'{') => skip_group(b, pos),
+        Some(c) if is_token(*c) => {
+            let mut end = pos;
+            while end < b.len() && is_token(b[end]) {
+                end += 1;
+            }
+            let next = skip_trivia(b, end)?;
+            if b.get(next) == Some(&b'(') {
+                skip_group(b, next) // struct name, e.g. SingleSnippet(...) or Some(...)
+            } else {
+                Ok(end)
+            }
+        },
+        _ => Err(pos),
+    }
+}
+
+/// character of identifier or number
+fn is_token(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, b'_' | b'.' | b'+' | b'-')
+}
+
+/// skip "..." with escapes, r#"..."# or '...', return the position after it
+fn skip_string(b: &[u8], pos: usize) -> Result<usize, usize> {
+    if b[pos] == b'r' {
+        let hashes = b[pos + 1..].iter().take_while(|c| **c == b'#').count();
+        let open = pos + 1 + hashes;
+        if b.get(open) != Some(&b'"') {
+            return Err(pos)
+        }
+        let mut end = open + 1;
+        while end < b.len() {
+            if b[end] == b'"' && b[end + 1..].iter().take(hashes).filter(|c| **c == b'#').count() == hashes {
+                return Ok(end + 1 + hashes)
+            }
+            end += 1;
+        }
+        return Err(pos)
+    }
+    let quote = b[pos];
+    let mut end = pos + 1;
+    while end < b.len() {
+        match b[end] {
+            b'\\' => end += 2,
+            c if c == quote => return Ok(end + 1),
+            _ => end += 1,
+        }
+    }
+    Err(pos)
+}
+
+/// skip a (), [] or {} group with everything nested in it
+fn skip_group(b: &[u8], pos: usize) -> Result<usize, usize> {
+    let close = match b[pos] {
+        b'(' => b')',
+        b'[' => b']',
+        _    => b'}',
+    };
+    let mut p = pos + 1;
+    loop {
+        p = skip_trivia(b, p)?;
+        match b.get(p) {
+            None => return Err(pos),
+            Some(c) if *c == close => return Ok(p + 1),
+            Some(b')') | Some(b']') | Some(b'}') => return Err(p),
+            Some(b',') | Some(b':') => p += 1,
+            Some(_) => p = skip_value(b, p)?,
+        }
+    }
+}
+
+/// entries and chunks of SnippetsText
+type ListRanges = (Vec<Range<usize>>, Vec<Range<usize>>);
+
+/// find the snippets of the top-level list
+fn scan_list(b: &[u8]) -> Result<ListRanges, usize> {
+    let open = skip_trivia(b, 0)?;
+    if b.get(open) != Some(&b'[') {
+        return Err(open)
+    }
+    let mut entries = Vec::new();
+    let mut chunks = Vec::new();
+    let mut chunk_start = open + 1;
+    loop {
+        let start = skip_trivia(b, chunk_start)?;
+        match b.get(start) {
+            Some(b']') => break,
+            None => return Err(open),
+            _ => (),
+        }
+        let end = skip_value(b, start)?;
+        let next = skip_trivia(b, end)?;
+        let chunk_end = match b.get(next) {
+            Some(b',') => trailing_comment_end(b, next + 1),
+            Some(b']') => end,
+            _ => return Err(next),
+        };
+        entries.push(start..end);
+        chunks.push(chunk_start..chunk_end);
+        chunk_start = chunk_end;
+    }
+    Ok((entries, chunks))
+}
+
+/// after the comma of a snippet, spaces and a // comment in the same line still belong to this snippet
+fn trailing_comment_end(b: &[u8], pos: usize) -> usize {
+    let mut p = pos;
+    while p < b.len() && (b[p] == b' ' || b[p] == b'\t') {
+        p += 1;
+    }
+    if b.get(p) == Some(&b'/') && b.get(p + 1) == Some(&b'/') {
+        while p < b.len() && b[p] != b'\n' && b[p] != b'\r' {
+            p += 1;
+        }
+        p
+    } else {
+        pos
+    }
+}
+
+/// name and value range of a field
+type Field = (Range<usize>, Range<usize>);
+
+/// fields of the snippet starting at pos
+fn scan_fields(b: &[u8], pos: usize) -> Result<Vec<Field>, usize> {
+    let mut p = pos;
+    while p < b.len() && b[p] != b'(' {
+        p += 1; // struct name
+    }
+    let mut fields = Vec::new();
+    p += 1;
+    loop {
+        p = skip_trivia(b, p)?;
+        match b.get(p) {
+            Some(b')') => return Ok(fields),
+            Some(c) if is_token(*c) => (),
+            _ => return Err(p),
+        }
+        let name_start = p;
+        while p < b.len() && is_token(b[p]) {
+            p += 1;
+        }
+        let name = name_start..p;
+        p = skip_trivia(b, p)?;
+        if b.get(p) != Some(&b':') {
+            return Err(p)
+        }
+        let value_start = skip_trivia(b, p + 1)?;
+        let value_end = skip_value(b, value_start)?;
+        p = skip_trivia(b, value_end)?;
+        if b.get(p) == Some(&b',') {
+            p += 1;
+        }
+        fields.push((name, value_start..value_end));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the snippet parsed
+    fn snippet(text: &SnippetsText, idx: usize) -> FileSnippet {
+        text.snippet(idx).unwrap()
+    }
+
+    const TEXT: &str = r####"// my snippets
+[
+    // first
+    SingleSnippet(
+        tags:        ["Code", "Python"],
+        discription: "python hello world",
+        content:     r##"
+print("Hello, World")  // not a comment ) ]
+"##,
+    ), // same line
+    SingleSnippet(
+        discription: "no tags",
+        content:     "a \" quote",
+        embedding:   Some({MultilingualE5Small: [0.1, -0.2e-3]}),
+    ),
+    /* block /* nested */ comment */
+    (discription: "last", content: "x")
+]
+"####;
+
+    #[test]
+    fn scan_entries() {
+        let text = SnippetsText::new(TEXT.to_string()).unwrap();
+        assert_eq!(text.entries.len(), 3);
+        let entry = &text.text[text.entries[0].clone()];
+        let chunk = |i: usize| &text.text[text.chunks[i].clone()];
+        assert!(entry.starts_with("SingleSnippet(") && entry.ends_with(')'));
+        assert!(chunk(0).contains("// first") && chunk(0).ends_with("// same line"));
+        assert!(chunk(2).contains("/* block"));
+        assert_eq!(snippet(&text, 1).discription, "no tags");
+        assert_eq!(snippet(&text, 2).content, "x");
+    }
+
+    #[test]
+    fn set_tags_keeps_other_fields() {
+        let mut text = SnippetsText::new(TEXT.to_string()).unwrap();
+        text.set_tags(0, &["Code".to_string(), "Shell".to_string()]).unwrap();
+        text.set_tags(1, &["Note".to_string()]).unwrap();
+        assert_eq!(snippet(&text, 0).tags, vec!["Code", "Shell"]);
+        assert_eq!(snippet(&text, 1).tags, vec!["Note"]);
+        assert!(text.text.contains("// my snippets") && text.text.contains("0.1, -0.2e-3"));
+        assert!(parse_snippets_text(&text.text).is_ok());
+    }
+
+    #[test]
+    fn remove_entries() {
+        let mut text = SnippetsText::new(TEXT.to_string()).unwrap();
+        text.remove(&[0, 2]).unwrap();
+        assert_eq!(text.entries.len(), 1);
+        assert_eq!(snippet(&text, 0).discription, "no tags");
+        assert!(!text.text.contains("// first") && !text.text.contains("nested"));
+        assert!(text.text.starts_with("// my snippets") && text.text.contains("embedding"));
+        assert!(parse_snippets_text(&text.text).is_ok());
+    }
+
+    #[test]
+    fn parts_in_any_order() {
+        let text = SnippetsText::new(TEXT.to_string()).unwrap();
+        let (head, chunks, tail) = text.parts();
+        assert_eq!(head, "// my snippets\n[");
+        assert_eq!(tail, "\n]\n");
+        assert!(chunks[2].ends_with("x\"),")); // comma added after the last snippet
+        assert_eq!(format!("{}{}{}", head, chunks.concat(), tail).replace("x\"),", "x\")"), TEXT);
+        let reordered = SnippetsText::new(format!("{}{}{}{}", head, chunks[2], chunks[0], tail)).unwrap();
+        assert_eq!(reordered.entries.len(), 2);
+        assert_eq!(snippet(&reordered, 0).discription, "last");
+        assert!(parse_snippets_text(&reordered.text).is_ok());
+        let empty = SnippetsText::new("// none\n[\n]\n".to_string()).unwrap();
+        assert_eq!(empty.parts(), ("// none\n[", Vec::new(), "\n]\n"));
+    }
+
+    #[test]
+    fn invalid_list() {
+        assert!(SnippetsText::new("[ (discription: \"a\"".to_string()).is_err());
+        assert!(SnippetsText::new("(discription: \"a\")".to_string()).is_err());
+    }
+}
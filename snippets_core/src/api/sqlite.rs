@@ -0,0 +1,500 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ron::ser::to_string;
+use rusqlite::{Connection, OpenFlags, OptionalExtension, params};
+
+use crate::{
+    SnipTag,
+    snip::{
+        SingleSnippet,
+        Snippets,
+        batch_embeddings,
+        read_source_file,
+    },
+    utils::is_sqlite,
+    error::MyError,
+};
+
+use embedding_lib::{EmbedMode, Model};
+
+/// tables of the SQLite database, created by migrate if missing
+/// a snippet is identified by the migrated file (source) and its id, snippets of different files may have the same id
+/// snippets_fts indexes discription and content with the trigram tokenizer, so MATCH finds substrings like keyword search
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS snippets (
+    rowid       INTEGER PRIMARY KEY,
+    source      TEXT NOT NULL DEFAULT '',
+    id          TEXT NOT NULL,
+    discription TEXT NOT NULL,
+    content     TEXT NOT NULL DEFAULT '',
+    overrides   TEXT NOT NULL DEFAULT '',
+    hide        TEXT NOT NULL DEFAULT '[]',
+    UNIQUE (source, id)
+);
+CREATE TABLE IF NOT EXISTS tags (
+    snippet INTEGER NOT NULL REFERENCES snippets(rowid) ON DELETE CASCADE,
+    tag     TEXT NOT NULL,
+    PRIMARY KEY (snippet, tag)
+);
+CREATE TABLE IF NOT EXISTS embeddings (
+    snippet INTEGER NOT NULL REFERENCES snippets(rowid) ON DELETE CASCADE,
+    model   TEXT NOT NULL,
+    prompt  TEXT NOT NULL DEFAULT '',
+    vector  BLOB NOT NULL,
+    PRIMARY KEY (snippet, model)
+);
+CREATE TABLE IF NOT EXISTS content_embeddings (
+    snippet INTEGER NOT NULL REFERENCES snippets(rowid) ON DELETE CASCADE,
+    model   TEXT NOT NULL,
+    prompt  TEXT NOT NULL DEFAULT '',
+    chunk   INTEGER NOT NULL,
+    vector  BLOB NOT NULL,
+    PRIMARY KEY (snippet, model, chunk)
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS snippets_fts USING fts5(discription, content, content='snippets', content_rowid='rowid', tokenize='trigram');
+CREATE TRIGGER IF NOT EXISTS snippets_ai AFTER INSERT ON snippets BEGIN
+    INSERT INTO snippets_fts(rowid, discription, content) VALUES (new.rowid, new.discription, new.content);
+END;
+CREATE TRIGGER IF NOT EXISTS snippets_ad AFTER DELETE ON snippets BEGIN
+    INSERT INTO snippets_fts(snippets_fts, rowid, discription, content) VALUES ('delete', old.rowid, old.discription, old.content);
+END;
+CREATE TRIGGER IF NOT EXISTS snippets_au AFTER UPDATE ON snippets BEGIN
+    INSERT INTO snippets_fts(snippets_fts, rowid, discription, content) VALUES ('delete', old.rowid, old.discription, old.content);
+    INSERT INTO snippets_fts(rowid, discription, content) VALUES (new.rowid, new.discription, new.content);
+END;
+"#;
+
+/// wait for other writers of a shared database before failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// snippets, tags, per-model discription embeddings and content chunk embeddings stored in a SQLite database
+/// all writes are transactions, so the database can be shared and edited concurrently
+/// databases loaded with -f or --team are opened read-only once, only saving embeddings and dedupe -M open them for writing
+pub(crate) struct SnippetDb {
+    conn: Connection,
+    file: PathBuf,
+}
+
+impl SnippetDb {
+    /// open the existing database read-only
+    pub(crate) fn open(file: &Path) -> Result<Self, MyError> {
+        Self::connect(file, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+    }
+
+    /// open the existing database for writing, the tables are not created
+    pub(crate) fn open_write(file: &Path) -> Result<Self, MyError> {
+        Self::connect(file, OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+    }
+
+    /// open the database for writing, create the file and the tables if missing, used by migrate
+    fn create(file: &Path) -> Result<Self, MyError> {
+        let db = Self::connect(file, OpenFlags::default())?;
+        db.conn.execute_batch(SCHEMA).map_err(|e| MyError::SqliteError{file: file.display().to_string(), error: e})?;
+        Ok(db)
+    }
+
+    fn connect(file: &Path, flags: OpenFlags) -> Result<Self, MyError> {
+        let error = |e: rusqlite::Error| MyError::SqliteError{file: file.display().to_string(), error: e};
+        let conn = Connection::open_with_flags(file, flags).map_err(error)?;
+        conn.busy_timeout(BUSY_TIMEOUT).map_err(error)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;").map_err(error)?;
+        Ok(Self { conn, file: file.to_path_buf() })
+    }
+
+    /// all snippets with their tags, source records the rowid, embeddings are loaded by load_embeddings when needed
+    pub(crate) fn snippets(&self) -> Result<Vec<SingleSnippet>, MyError> {
+        let error = |e| MyError::SqliteError{file: self.file.display().to_string(), error: e};
+        let mut tags: HashMap<usize, HashSet<SnipTag>> = HashMap::new(); // key: rowid
+        let mut stmt = self.conn.prepare("SELECT snippet, tag FROM tags").map_err(error)?;
+        let rows = stmt.query_map([], |r| Ok((r.get::<_, usize>(0)?, r.get::<_, String>(1)?))).map_err(error)?;
+        for row in rows {
+            let (rowid, tag) = row.map_err(error)?;
+            let tag = SnipTag::string_to_tag(&tag.to_lowercase()).ok_or(MyError::ParaError{para: format!("unknown tag {} in {}", tag, self.file.display())})?;
+            tags.entry(rowid).or_default().insert(tag);
+        }
+        let mut stmt = self.conn.prepare("SELECT rowid, id, discription, content, overrides, hide FROM snippets ORDER BY rowid").map_err(error)?;
+        let rows = stmt.query_map([], |r| Ok((r.get::<_, usize>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?, r.get::<_, String>(3)?, r.get::<_, String>(4)?, r.get::<_, String>(5)?))).map_err(error)?;
+        let mut snippets: Vec<SingleSnippet> = Vec::new();
+        for row in rows {
+            let (rowid, id, discription, content, overrides, hide) = row.map_err(error)?;
+            snippets.push(SingleSnippet {
+                id,
+                tags:              tags.remove(&rowid).unwrap_or_default(),
+                discription,
+                embedding:         None,
+                embedding_prompt:  None,
+                content_embedding: None,
+                content,
+                overrides,
+                hide:              serde_json::from_str(&hide).map_err(|e| MyError::JsonFromStrError{error: e})?,
+                source:            Some((self.file.clone(), rowid)),
+                namespace:         "",
+            });
+        }
+        Ok(snippets)
+    }
+
+    /// stored discription embeddings of the model, key: rowid, value: (document prompt, embedding)
+    fn embeddings(&self, model: &Model) -> Result<HashMap<usize, (String, Vec<f32>)>, MyError> {
+        let error = |e| MyError::SqliteError{file: self.file.display().to_string(), error: e};
+        let mut stmt = self.conn.prepare("SELECT snippet, prompt, vector FROM embeddings WHERE model = ?1").map_err(error)?;
+        let rows = stmt.query_map([model_key(model)], |r| Ok((r.get::<_, usize>(0)?, r.get::<_, String>(1)?, r.get::<_, Vec<u8>>(2)?))).map_err(error)?;
+        let mut embeddings = HashMap::new();
+        for row in rows {
+            let (rowid, prompt, vector) = row.map_err(error)?;
+            embeddings.insert(rowid, (prompt, from_blob(&vector)));
+        }
+        Ok(embeddings)
+    }
+
+    /// put the stored embeddings of the model into the snippets loaded from this database
+    pub(crate) fn load_embeddings(&self, model: &Model, snippets: &mut [SingleSnippet]) -> Result<(), MyError> {
+        let mut embeddings = self.embeddings(model)?;
+        for s in snippets.iter_mut() {
+            let rowid = match &s.source {
+                Some((f, rowid)) if *f == self.file => *rowid,
+                _ => continue,
+            };
+            if let Some((prompt, e)) = embeddings.remove(&rowid) {
+                s.embedding.get_or_insert_with(HashMap::new).insert(model.clone(), e);
+                if !prompt.is_empty() {
+                    s.embedding_prompt.get_or_insert_with(HashMap::new).insert(model.clone(), prompt);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// store discription embeddings of the model in one transaction, (rowid, embedding)
+    fn save_embeddings(&mut self, model: &Model, prompt: &str, embeddings: &[(usize, &Vec<f32>)]) -> Result<(), MyError> {
+        let error = |e| MyError::SqliteError{file: self.file.display().to_string(), error: e};
+        let tx = self.conn.transaction().map_err(error)?;
+        {
+            let mut stmt = tx.prepare("INSERT OR REPLACE INTO embeddings (snippet, model, prompt, vector) VALUES (?1, ?2, ?3, ?4)").map_err(error)?;
+            for (rowid, e) in embeddings {
+                stmt.execute(params![rowid, model_key(model), prompt, to_blob(e)]).map_err(error)?;
+            }
+        }
+        tx.commit().map_err(error)
+    }
+
+    /// stored content chunk embeddings of the model calculated with the document prompt, key: rowid, value: embedding of each chunk
+    fn content_embeddings(&self, model: &Model, prompt: &str, rowids: &[usize]) -> Result<HashMap<usize, Vec<Vec<f32>>>, MyError> {
+        let error = |e| MyError::SqliteError{file: self.file.display().to_string(), error: e};
+        let mut stmt = self.conn.prepare("SELECT vector FROM content_embeddings WHERE snippet = ?1 AND model = ?2 AND prompt = ?3 ORDER BY chunk").map_err(error)?;
+        let mut chunks = HashMap::new();
+        for rowid in rowids {
+            let rows = stmt.query_map(params![rowid, model_key(model), prompt], |r| r.get::<_, Vec<u8>>(0)).map_err(error)?;
+            let vectors: Vec<Vec<f32>> = rows.map(|v| v.map(|v| from_blob(&v))).collect::<Result<_, _>>().map_err(error)?;
+            if !vectors.is_empty() {
+                chunks.insert(*rowid, vectors);
+            }
+        }
+        Ok(chunks)
+    }
+
+    /// store content chunk embeddings of the model in one transaction, the chunks stored before are replaced, (rowid, chunk embeddings)
+    fn save_content_embeddings(&mut self, model: &Model, prompt: &str, embeddings: &[(usize, &Vec<Vec<f32>>)]) -> Result<(), MyError> {
+        let error = |e| MyError::SqliteError{file: self.file.display().to_string(), error: e};
+        let tx = self.conn.transaction().map_err(error)?;
+        for (rowid, chunks) in embeddings {
+            tx.execute("DELETE FROM content_embeddings WHERE snippet = ?1 AND model = ?2", params![rowid, model_key(model)]).map_err(error)?;
+            for (c, e) in chunks.iter().enumerate() {
+                tx.execute("INSERT INTO content_embeddings (snippet, model, prompt, chunk, vector) VALUES (?1, ?2, ?3, ?4, ?5)", params![rowid, model_key(model), prompt, c, to_blob(e)]).map_err(error)?;
+            }
+        }
+        tx.commit().map_err(error)
+    }
+
+    /// rowids of snippets whose discription or content contains the keyword, FTS5 needs at least 3 characters
+    fn keyword(&self, kw: &str) -> Result<HashSet<usize>, MyError> {
+        let error = |e| MyError::SqliteError{file: self.file.display().to_string(), error: e};
+        let mut stmt = self.conn.prepare("SELECT rowid FROM snippets_fts WHERE snippets_fts MATCH ?1").map_err(error)?;
+        let phrase = format!("\"{}\"", kw.replace('"', "\"\""));
+        let rows = stmt.query_map([phrase], |r| r.get::<_, usize>(0)).map_err(error)?;
+        rows.collect::<Result<HashSet<usize>, _>>().map_err(error)
+    }
+
+    /// insert the snippets of the source file or update its snippets with the same id in one transaction, embeddings in the snippets are stored too
+    /// stored embeddings of an edited discription or content are deleted, snippets no longer in the source file are deleted
+    /// return the number of inserted, updated and deleted snippets
+    fn upsert(&mut self, source: &str, snippets: &[SingleSnippet]) -> Result<(usize, usize, usize), MyError> {
+        let error = |e| MyError::SqliteError{file: self.file.display().to_string(), error: e};
+        let tx = self.conn.transaction().map_err(error)?;
+        let (mut inserted, mut updated, mut deleted) = (0, 0, 0);
+        for s in snippets {
+            let hide = serde_json::to_string(&s.hide).map_err(|e| MyError::ToJsonStirngError{uuid: s.id.clone(), error: e})?;
+            let stored: Option<(usize, String, String)> = tx.query_row("SELECT rowid, discription, content FROM snippets WHERE source = ?1 AND id = ?2", [source, &s.id], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?))).optional().map_err(error)?;
+            let rowid = match stored {
+                Some((rowid, discription, content)) => {
+                    tx.execute("UPDATE snippets SET discription = ?1, content = ?2, overrides = ?3, hide = ?4 WHERE rowid = ?5", params![s.discription, s.content, s.overrides, hide, rowid]).map_err(error)?;
+                    tx.execute("DELETE FROM tags WHERE snippet = ?1", [rowid]).map_err(error)?;
+                    if discription != s.discription {
+                        tx.execute("DELETE FROM embeddings WHERE snippet = ?1", [rowid]).map_err(error)?; // calculated again for the new discription
+                    }
+                    if content != s.content {
+                        tx.execute("DELETE FROM content_embeddings WHERE snippet = ?1", [rowid]).map_err(error)?;
+                    }
+                    updated += 1;
+                    rowid
+                },
+                None => {
+                    tx.execute("INSERT INTO snippets (source, id, discription, content, overrides, hide) VALUES (?1, ?2, ?3, ?4, ?5, ?6)", params![source, s.id, s.discription, s.content, s.overrides, hide]).map_err(error)?;
+                    inserted += 1;
+                    tx.last_insert_rowid() as usize
+                },
+            };
+            for tag in s.sorted_tags() {
+                tx.execute("INSERT INTO tags (snippet, tag) VALUES (?1, ?2)", params![rowid, tag.to_string()]).map_err(error)?;
+            }
+            for (model, e) in s.embedding.iter().flatten() {
+                let prompt = s.embedding_prompt.as_ref().and_then(|p| p.get(model)).map(|p| p.as_str()).unwrap_or("");
+                tx.execute("INSERT OR REPLACE INTO embeddings (snippet, model, prompt, vector) VALUES (?1, ?2, ?3, ?4)", params![rowid, model_key(model), prompt, to_blob(e)]).map_err(error)?;
+            }
+        }
+        // snippets removed from the source file, their tags and embeddings are deleted by the foreign keys
+        let ids: HashSet<&str> = snippets.iter().map(|s| s.id.as_str()).collect();
+        let removed: Vec<usize> = {
+            let mut stmt = tx.prepare("SELECT rowid, id FROM snippets WHERE source = ?1").map_err(error)?;
+            let rows = stmt.query_map([source], |r| Ok((r.get::<_, usize>(0)?, r.get::<_, String>(1)?))).map_err(error)?;
+            rows.collect::<Result<Vec<_>, _>>().map_err(error)?.into_iter().filter(|(_, id)| !ids.contains(id.as_str())).map(|(rowid, _)| rowid).collect()
+        };
+        for rowid in removed {
+            tx.execute("DELETE FROM snippets WHERE rowid = ?1", [rowid]).map_err(error)?;
+            deleted += 1;
+        }
+        tx.commit().map_err(error)?;
+        Ok((inserted, updated, deleted))
+    }
+
+    /// ids used by snippets of several source files, and these files
+    fn shared_ids(&self) -> Result<Vec<(String, String)>, MyError> {
+        let error = |e| MyError::SqliteError{file: self.file.display().to_string(), error: e};
+        let mut stmt = self.conn.prepare("SELECT id, group_concat(source, ', ') FROM snippets GROUP BY id HAVING count(*) > 1 ORDER BY id").map_err(error)?;
+        let rows = stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?))).map_err(error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(error)
+    }
+
+    /// set the tags of kept snippets and delete the merged snippets in one transaction, used by dedupe -M
+    pub(crate) fn apply_merge(&mut self, update: &[(usize, Vec<String>)], remove: &[usize]) -> Result<(), MyError> {
+        let error = |e| MyError::SqliteError{file: self.file.display().to_string(), error: e};
+        let tx = self.conn.transaction().map_err(error)?;
+        for (rowid, tags) in update {
+            tx.execute("DELETE FROM tags WHERE snippet = ?1", [rowid]).map_err(error)?;
+            for tag in tags {
+                tx.execute("INSERT INTO tags (snippet, tag) VALUES (?1, ?2)", params![rowid, tag]).map_err(error)?;
+            }
+        }
+        for rowid in remove {
+            tx.execute("DELETE FROM snippets WHERE rowid = ?1", [rowid]).map_err(error)?;
+        }
+        tx.commit().map_err(error)
+    }
+}
+
+/// embedding stored as little-endian f32 bytes
+fn to_blob(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// embedding of the stored bytes
+fn from_blob(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+}
+
+/// model name stored in the embeddings table, same as the built-in database
+fn model_key(model: &Model) -> String {
+    to_string(model).unwrap_or_else(|_| model.name().to_string())
+}
+
+/// sqlite databases among the snippets' sources
+fn databases(snippets: &[SingleSnippet]) -> Vec<&PathBuf> {
+    let mut files: Vec<&PathBuf> = snippets.iter().filter_map(|s| s.source.as_ref().map(|(f, _)| f)).filter(|f| is_sqlite(f)).collect();
+    files.sort();
+    files.dedup();
+    files
+}
+
+/// rowids matching the keyword with FTS5 in each opened database, databases that cannot be searched (e.g. keyword shorter than 3 characters) are missing
+pub(crate) fn keyword_matches(databases: &HashMap<PathBuf, SnippetDb>, kw: &str) -> HashMap<PathBuf, HashSet<usize>> {
+    if kw.chars().count() < 3 {
+        return HashMap::new()
+    }
+    databases.iter().filter_map(|(f, db)| Some((f.clone(), db.keyword(kw).ok()?))).collect()
+}
+
+/// store the calculated discription embeddings of snippets loaded from databases, (index in snippets, embedding)
+pub(crate) fn save_embeddings(snippets: &[SingleSnippet], embeddings: &[(usize, &Vec<f32>)], model: &Model, prompt: &str) -> Result<(), MyError> {
+    for f in databases(snippets) {
+        let rows: Vec<(usize, &Vec<f32>)> = embeddings.iter().filter_map(|(i, e)| match &snippets[*i].source {
+            Some((s, rowid)) if s == f => Some((*rowid, *e)),
+            _ => None,
+        }).collect();
+        if !rows.is_empty() {
+            SnippetDb::open_write(f)?.save_embeddings(model, prompt, &rows)?;
+        }
+    }
+    Ok(())
+}
+
+/// stored content chunk embeddings of the snippets loaded from the opened databases, key: index in snippets
+/// databases that cannot be read are skipped, the chunks are calculated again
+pub(crate) fn stored_content_embeddings(databases: &HashMap<PathBuf, SnippetDb>, snippets: &[SingleSnippet], indexes: &[usize], model: &Model, prompt: &str) -> HashMap<usize, Vec<Vec<f32>>> {
+    let mut chunks = HashMap::new();
+    for (f, db) in databases {
+        let rows: Vec<(usize, usize)> = indexes.iter().filter_map(|i| match &snippets[*i].source {
+            Some((s, rowid)) if s == f => Some((*i, *rowid)),
+            _ => None,
+        }).collect();
+        if rows.is_empty() {
+            continue
+        }
+        if let Ok(mut stored) = db.content_embeddings(model, prompt, &rows.iter().map(|(_, r)| *r).collect::<Vec<_>>()) {
+            chunks.extend(rows.into_iter().filter_map(|(i, rowid)| Some((i, stored.remove(&rowid)?))));
+        }
+    }
+    chunks
+}
+
+/// store the calculated content chunk embeddings of snippets loaded from databases, key: index in snippets
+pub(crate) fn save_content_embeddings(snippets: &[SingleSnippet], chunks: &HashMap<usize, Vec<Vec<f32>>>, model: &Model, prompt: &str) -> Result<(), MyError> {
+    for f in databases(snippets) {
+        let rows: Vec<(usize, &Vec<Vec<f32>>)> = chunks.iter().filter_map(|(i, c)| match &snippets[*i].source {
+            Some((s, rowid)) if s == f && !c.is_empty() => Some((*rowid, c)),
+            _ => None,
+        }).collect();
+        if !rows.is_empty() {
+            SnippetDb::open_write(f)?.save_content_embeddings(model, prompt, &rows)?;
+        }
+    }
+    Ok(())
+}
+
+impl Snippets {
+    /// convert *.snippets and markdown files to the SQLite database, snippets migrated from the same file with the same id are updated
+    /// stored embeddings are kept, the missing discription embeddings of -m are calculated
+    pub fn migrate(&self, db: &Path, files: &[PathBuf]) -> Result<(), MyError> {
+        let mut database = SnippetDb::create(db)?;
+        let (mut total, mut inserted, mut updated, mut deleted) = (0, 0, 0, 0);
+        for f in files {
+            // an id repeated in one file would update the first snippet, the later ones get a -n suffix
+            let mut snippets = read_source_file(f)?;
+            let mut ids: HashSet<String> = HashSet::new();
+            for s in snippets.iter_mut() {
+                if !ids.contains(&s.id) {
+                    ids.insert(s.id.clone());
+                    continue
+                }
+                let id = (2..).map(|n| format!("{}-{}", s.id, n)).find(|id| !ids.contains(id)).unwrap_or_default();
+                println!("Warning - id {} repeats in {}, migrated as {}", s.id, f.display(), id);
+                ids.insert(id.clone());
+                s.id = id;
+            }
+            let (i, u, d) = database.upsert(&f.to_string_lossy(), &snippets)?;
+            (total, inserted, updated, deleted) = (total + snippets.len(), inserted + i, updated + u, deleted + d);
+        }
+        println!("migrated {} snippets from {} files to {}: {} inserted, {} updated, {} deleted", total, files.len(), db.display(), inserted, updated, deleted);
+        for (id, sources) in database.shared_ids()? {
+            println!("Warning - id {} is used by snippets of {}, overrides and hide of this id apply to all of them", id, sources);
+        }
+
+        if let Some((model, prompt)) = self.semantic_model() {
+            let stored = database.embeddings(&model)?;
+            let missing: Vec<(usize, String)> = database.snippets()?.into_iter()
+                .filter_map(|s| s.source.map(|(_, rowid)| (rowid, s.discription)))
+                .filter(|(rowid, _)| stored.get(rowid).is_none_or(|(p, _)| *p != prompt))
+                .collect();
+            if !missing.is_empty() {
+                let embedder = self.embedder()?.ok_or(MyError::ParaError{para: "no embedding model".to_string()})?;
+                let texts: Vec<String> = missing.iter().map(|(_, d)| d.clone()).collect();
+                let embeddings = batch_embeddings(embedder, &texts, EmbedMode::Document, self.options.batch_size, None)?;
+                let rows: Vec<(usize, &Vec<f32>)> = missing.iter().map(|(rowid, _)| *rowid).zip(embeddings.iter()).collect();
+                database.save_embeddings(&model, &prompt, &rows)?;
+                println!("calculated {} {} embeddings", rows.len(), model.name());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    use embedding_lib::snippet_id;
+
+    /// empty temporary folder of the test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("snippets-sqlite-{}-{}", name, std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// write a *.snippets file with one snippet per (id, discription)
+    fn snippets_file(dir: &Path, name: &str, snippets: &[(&str, &str)]) -> PathBuf {
+        let file = dir.join(name);
+        let entries: Vec<String> = snippets.iter().map(|(id, d)| format!("SingleSnippet(id: \"{}\", tags: [\"shell\"], discription: \"{}\", content: \"echo {}\")", id, d, id)).collect();
+        write(&file, format!("[\n{}\n]\n", entries.join(",\n"))).unwrap();
+        file
+    }
+
+    fn upsert_file(db: &mut SnippetDb, file: &Path) -> (usize, usize, usize) {
+        db.upsert(&file.to_string_lossy(), &read_source_file(file).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn same_id_in_different_files() {
+        let dir = temp_dir("ids");
+        let a = snippets_file(&dir, "a.snippets", &[("", "list files"), ("st", "git status")]);
+        let b = snippets_file(&dir, "b.snippets", &[("", "list files")]);
+        let mut db = SnippetDb::create(&dir.join("s.db")).unwrap();
+        assert_eq!(upsert_file(&mut db, &a), (2, 0, 0));
+        assert_eq!(upsert_file(&mut db, &b), (1, 0, 0)); // same default id, another file
+        assert_eq!(upsert_file(&mut db, &a), (0, 2, 0)); // migrated again
+        assert_eq!(db.snippets().unwrap().len(), 3);
+        assert_eq!(db.shared_ids().unwrap(), vec![(snippet_id("list files"), format!("{}, {}", a.display(), b.display()))]);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn migrate_edited_file() {
+        let dir = temp_dir("edited");
+        let a = snippets_file(&dir, "a.snippets", &[("ls", "list files"), ("du", "disk usage"), ("df", "free disk space")]);
+        let mut db = SnippetDb::create(&dir.join("s.db")).unwrap();
+        upsert_file(&mut db, &a);
+        let model = Model::MultilingualE5Small;
+        let rowids: HashMap<String, usize> = db.snippets().unwrap().into_iter().map(|s| (s.id, s.source.unwrap().1)).collect();
+        let vector = vec![0.5f32];
+        db.save_embeddings(&model, "", &rowids.values().map(|r| (*r, &vector)).collect::<Vec<_>>()).unwrap();
+        // ls: discription edited, du: unchanged, df: removed
+        snippets_file(&dir, "a.snippets", &[("ls", "list all files"), ("du", "disk usage")]);
+        assert_eq!(upsert_file(&mut db, &a), (0, 2, 1));
+        let stored = db.embeddings(&model).unwrap();
+        assert_eq!(stored.keys().copied().collect::<Vec<_>>(), vec![rowids["du"]]);
+        let snippets = db.snippets().unwrap();
+        assert_eq!(snippets.iter().map(|s| (s.id.as_str(), s.discription.as_str())).collect::<Vec<_>>(), vec![("ls", "list all files"), ("du", "disk usage")]);
+        assert!(db.keyword("free disk").unwrap().is_empty());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_only_open() {
+        let dir = temp_dir("read-only");
+        let file = dir.join("s.db");
+        assert!(SnippetDb::open(&file).is_err()); // not created
+        let a = snippets_file(&dir, "a.snippets", &[("ls", "list files"), ("du", "disk usage")]);
+        upsert_file(&mut SnippetDb::create(&file).unwrap(), &a);
+        let mut db = SnippetDb::open(&file).unwrap();
+        let snippets = db.snippets().unwrap();
+        assert_eq!(snippets.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(), vec!["ls", "du"]);
+        assert!(db.upsert("a.snippets", &snippets).is_err());
+        let rowid = snippets[1].source.as_ref().unwrap().1;
+        assert_eq!(db.keyword("usage").unwrap(), HashSet::from([rowid]));
+        assert!(db.keyword("nothing").unwrap().is_empty());
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use tabled::{
+    builder::Builder,
+    settings::Style,
+};
+
+use crate::{
+    SnipTag,
+    snip::{
+        SingleSnippet,
+        Snippets,
+        read_source_file,
+    },
+    utils::{
+        cosine_similarity,
+        text_trigrams,
+        trigram_similarity,
+    },
+    error::MyError,
+};
+
+/// only print suggestions above this confidence
+const MIN_CONFIDENCE: f32 = 0.1;
+
+/// in check mode, a missing tag above this confidence is reported
+const MISSING_CONFIDENCE: f32 = 0.6;
+
+/// in check mode, an existing tag below this confidence is reported
+const UNSUPPORTED_CONFIDENCE: f32 = 0.2;
+
+/// weight of neighbours vote when use keyword heuristic, the rest is keyword match
+const NEIGHBOUR_WEIGHT: f32 = 0.7;
+
+/// snippet to suggest tags for
+pub enum SuggestTarget {
+    Id(usize),     // snippet id
+    File(PathBuf), // *.snippets file, may contain untagged snippets
+    All,           // check all snippets
+}
+
+/// snippet representation used to find neighbours
+enum Vector {
+    Embedding(Vec<f32>),
+    Text(HashSet<String>),
+}
+
+impl Snippets {
+    /// suggest tags by nearest neighbours of tagged snippets
+    /// use -m embedding model if specified, otherwise use text similarity and keyword heuristic
+    pub fn suggest_tags(&self, target: &SuggestTarget, neighbours: usize) -> Result<(), MyError> {
+        let vectors: Vec<Vector> = match self.discription_embeddings()? {
+            Some(e) => e.into_iter().map(Vector::Embedding).collect(),
+            None => self.data.iter().map(|s| Vector::Text(text_trigrams(&s.full_text()))).collect(),
+        };
+        let use_keyword = matches!(vectors.first(), Some(Vector::Text(_)));
+        match target {
+            SuggestTarget::Id(id) => {
+                if *id >= self.data.len() {
+                    return Err(MyError::ParaError{para: format!("no such snippet id: {}", id)})
+                }
+                let s = &self.data[*id];
+                let mut suggestion = self.vote(&vectors[*id], Some(*id), &vectors, neighbours)?;
+                if use_keyword {
+                    add_keyword_score(&mut suggestion, &s.full_text());
+                }
+                print_suggestion(&id.to_string(), &s.discription, &s.tags, &suggestion);
+            },
+            SuggestTarget::File(file) => {
+                let file_snippets: Vec<(usize, SingleSnippet)> = read_source_file(file)?.into_iter().enumerate()
+                    .map(|(i, s)| (s.source.as_ref().map(|(_, rowid)| *rowid).unwrap_or(i), s)) // index in this file, rowid in SQLite database
+                    .filter(|(_, s)| !s.discription.is_empty()) // entries that only hide snippets
+                    .collect();
+                // the loaded snippets of this file are excluded from their own neighbours
+                let path = file.canonicalize().unwrap_or(file.clone());
+                let loaded: HashMap<usize, usize> = self.data.iter().enumerate().filter_map(|(j, d)| match &d.source {
+                    Some((f, k)) if f.canonicalize().unwrap_or(f.clone()) == path => Some((*k, j)),
+                    _ => None,
+                }).collect();
+                let texts: Vec<String> = file_snippets.iter().map(|(_, s)| format!("{} {}", s.discription.trim(), s.content.trim())).collect();
+                let query_vectors: Vec<Vector> = match self.text_embeddings(&file_snippets.iter().map(|(_, s)| s.discription.trim().to_string()).collect::<Vec<_>>())? {
+                    Some(e) => e.into_iter().map(Vector::Embedding).collect(),
+                    None => texts.iter().map(|t| Vector::Text(text_trigrams(t))).collect(),
+                };
+                for (i, ((index, s), q)) in file_snippets.iter().zip(query_vectors.iter()).enumerate() {
+                    let mut suggestion = self.vote(q, loaded.get(index).copied(), &vectors, neighbours)?;
+                    if use_keyword {
+                        add_keyword_score(&mut suggestion, &texts[i]);
+                    }
+                    print_suggestion(&format!("{}:{}", file.display(), index), &s.discription, &s.tags, &suggestion);
+                }
+            },
+            SuggestTarget::All => {
+                let mut builder = Builder::default();
+                builder.push_record(vec!["id", "discription", "categories", "suggested", "unsupported"]);
+                let mut num = 0;
+                for (i, s) in self.data.iter().enumerate() {
+                    let mut suggestion = self.vote(&vectors[i], Some(i), &vectors, neighbours)?;
+                    if use_keyword {
+                        add_keyword_score(&mut suggestion, &s.full_text());
+                    }
+                    let confidence: HashMap<&SnipTag, f32> = suggestion.iter().map(|(t, c)| (t, *c)).collect();
+                    let suggested: Vec<String> = suggestion.iter()
+                        .filter(|(t, c)| *c >= MISSING_CONFIDENCE && !s.tags.contains(t))
+                        .map(|(t, c)| format!("{} ({:.2})", t.to_string(), c))
+                        .collect();
+                    let unsupported: Vec<String> = s.sorted_tags().iter()
+                        .map(|t| (t, confidence.get(t).copied().unwrap_or(0.0)))
+                        .filter(|(_, c)| *c < UNSUPPORTED_CONFIDENCE)
+                        .map(|(t, c)| format!("{} ({:.2})", t.to_string(), c))
+                        .collect();
+                    if !suggested.is_empty() || !unsupported.is_empty() {
+                        builder.push_record(vec![&i.to_string(), &s.discription, &s.format_tags(), &suggested.join("\n"), &unsupported.join("\n")]);
+                        num += 1;
+                    }
+                }
+                if num == 0 {
+                    println!("all snippets' tags agree with their {} nearest neighbours", neighbours);
+                } else {
+                    let mut table = builder.build();
+                    table.with(Style::modern()); // table style: ascii, extended, markdown, re_structured_text, dots, psql, ascii_rounded, blank, empty, rounded, modern, sharp
+                    println!("{}", table);
+                    println!("{} snippets' tags disagree with their {} nearest neighbours", num, neighbours);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// find the k most similar tagged snippets, each neighbour votes its tags with its similarity
+    /// return tags and confidence sorted by confidence
+    fn vote(&self, query: &Vector, exclude: Option<usize>, vectors: &[Vector], k: usize) -> Result<Vec<(SnipTag, f32)>, MyError> {
+        let mut similarity: Vec<(usize, f32)> = Vec::new();
+        for (i, v) in vectors.iter().enumerate() {
+            if Some(i) == exclude || self.data[i].tags.is_empty() {
+                continue
+            }
+            let simi = match (query, v) {
+                (Vector::Embedding(q), Vector::Embedding(e)) => cosine_similarity(q, e)?,
+                (Vector::Text(q), Vector::Text(t)) => trigram_similarity(q, t),
+                _ => unreachable!(),
+            };
+            similarity.push((i, simi.max(0.0)));
+        }
+        similarity.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        similarity.truncate(k);
+        let total: f32 = similarity.iter().map(|(_, s)| s).sum();
+        let mut votes: HashMap<SnipTag, f32> = HashMap::new();
+        if total > 0.0 {
+            for (i, s) in &similarity {
+                for t in &self.data[*i].tags {
+                    *votes.entry(t.clone()).or_insert(0.0) += s / total;
+                }
+            }
+        }
+        let mut votes: Vec<(SnipTag, f32)> = votes.into_iter().collect();
+        votes.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+        Ok(votes)
+    }
+}
+
+/// keyword heuristic, tag name appears as a word in discription or content
+/// confidence = NEIGHBOUR_WEIGHT * neighbours vote + (1 - NEIGHBOUR_WEIGHT) * keyword match
+fn add_keyword_score(suggestion: &mut Vec<(SnipTag, f32)>, text: &str) {
+    let words: HashSet<String> = text.to_lowercase().split(|c: char| !c.is_alphanumeric()).map(|w| w.to_string()).collect();
+    let mut score: HashMap<SnipTag, f32> = suggestion.iter().map(|(t, c)| (t.clone(), c * NEIGHBOUR_WEIGHT)).collect();
+    for tag in SnipTag::all_tags() {
+        let name = tag.to_string().to_lowercase();
+        if name.chars().count() > 1 && words.contains(&name) {
+            *score.entry(tag).or_insert(0.0) += 1.0 - NEIGHBOUR_WEIGHT;
+        }
+    }
+    *suggestion = score.into_iter().collect();
+    suggestion.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+}
+
+/// print one snippet and its suggested tags
+fn print_suggestion(id: &str, discription: &str, tags: &HashSet<SnipTag>, suggestion: &[(SnipTag, f32)]) {
+    let mut sorted_tags: Vec<&SnipTag> = tags.iter().collect();
+    sorted_tags.sort();
+    let mut builder = Builder::default();
+    builder.push_record(vec!["id", "discription", "categories"]);
+    builder.push_record(vec![id, discription, &sorted_tags.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", ")]);
+    let mut table = builder.build();
+    table.with(Style::modern()); // table style: ascii, extended, markdown, re_structured_text, dots, psql, ascii_rounded, blank, empty, rounded, modern, sharp
+    println!("{}", table);
+    let mut builder = Builder::default();
+    builder.push_record(vec!["suggested tag", "confidence", "existing"]);
+    for (t, c) in suggestion.iter().filter(|(_, c)| *c >= MIN_CONFIDENCE) {
+        builder.push_record(vec![t.to_string(), format!("{:.4}", c), if tags.contains(t) { "yes".to_string() } else { "".to_string() }]);
+    }
+    let mut table = builder.build();
+    table.with(Style::ascii()); // table style: ascii, extended, markdown, re_structured_text, dots, psql, ascii_rounded, blank, empty, rounded, modern, sharp
+    println!("{}", table);
+}
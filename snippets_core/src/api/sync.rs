@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use std::fs::{create_dir_all, write};
+use std::path::Path;
+use std::process::Command;
+
+use tabled::{
+    builder::Builder,
+    settings::Style,
+};
+
+use crate::{
+    snip::{
+        FileSnippet,
+        parse_snippets_text,
+    },
+    snippets_text::SnippetsText,
+    markdown::{
+        has_snippet_front_matter,
+        markdown_sections,
+        read_markdown_snippets,
+    },
+    utils::{
+        is_markdown,
+        is_marked_markdown,
+    },
+    error::MyError,
+};
+
+use embedding_lib::snippet_id;
+
+/// which version of a snippet changed on both sides is kept by sync --prefer
+#[derive(Clone, Copy)]
+pub enum Prefer {
+    Local,
+    Remote,
+}
+
+impl Prefer {
+    /// parse --prefer value
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "local"  => Some(Self::Local),
+            "remote" => Some(Self::Remote),
+            _        => None,
+        }
+    }
+}
+
+/// snippet changed differently on both sides since the common version
+#[derive(Debug)]
+struct SnippetConflict {
+    file:   String,
+    id:     String,
+    local:  Option<String>, // text of the snippet, None if deleted locally
+    remote: Option<String>, // text of the snippet, None if deleted remotely
+}
+
+/// one version of a conflicting snippet file, snippets are merged by their text, so comments, formatting and other fields (e.g. embedding) are kept
+struct FileVersion {
+    head:     String,                 // text before the first snippet
+    snippets: Vec<(String, String)>,  // key (see snippet_key) and text of each snippet with its leading comments
+    tail:     String,                 // text after the last snippet
+}
+
+impl FileVersion {
+    /// split the text of *.snippets file by snippets, or markdown snippet file by headings
+    fn parse(file: &str, text: &str) -> Result<Self, MyError> {
+        if is_markdown(Path::new(file)) {
+            let (head, snippets) = markdown_sections(text).map_err(|e| MyError::ParaError{para: e})?;
+            return Ok(Self { head: head.to_string(), snippets, tail: String::new() })
+        }
+        let text = SnippetsText::new(text.to_string())?;
+        let (head, chunks, tail) = text.parts();
+        let mut snippets = Vec::new();
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            snippets.push((snippet_key(&text.snippet(i)?), chunk));
+        }
+        Ok(Self { head: head.to_string(), snippets, tail: tail.to_string() })
+    }
+}
+
+/// run git in the repository, return stdout
+/// GIT_EDITOR is disabled, git never waits for a commit message
+fn git(repo: &Path, args: &[&str]) -> Result<String, MyError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .env("GIT_EDITOR", "true")
+        .output()
+        .map_err(|e| MyError::GitError{args: args.join(" "), info: e.to_string()})?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(MyError::GitError{args: args.join(" "), info: String::from_utf8_lossy(&output.stderr).trim().to_string()})
+    }
+}
+
+/// commit local changes of the snippets repository, rebase them on the remote branch (origin/HEAD) and push
+/// conflicts are resolved per snippet (by id) in *.snippets and markdown snippet files, snippets changed on both sides are kept by --prefer or reported
+pub fn sync(repo: &Path, remote: Option<&str>, message: &str, prefer: Option<Prefer>) -> Result<(), MyError> {
+    // clone the remote for the first sync
+    if !repo.join(".git").exists() {
+        if let (Some(r), false) = (remote, repo.read_dir().map(|mut d| d.next().is_some()).unwrap_or(false)) {
+            git(Path::new("."), &["clone", "-q", r, &repo.to_string_lossy()])?;
+            println!("cloned {} to {}", r, repo.display());
+            return Ok(())
+        }
+        create_dir_all(repo).map_err(|e| MyError::CreateDirAllError{dir_name: repo.display().to_string(), error: e})?;
+        git(repo, &["init"])?;
+        println!("initialized git repository {}", repo.display());
+    }
+    if let Some(r) = remote {
+        match git(repo, &["remote", "get-url", "origin"]) {
+            Ok(url) if url.trim() == r => (),
+            Ok(_) => { git(repo, &["remote", "set-url", "origin", r])?; },
+            Err(_) => { git(repo, &["remote", "add", "origin", r])?; },
+        }
+    }
+
+    // commit local changes
+    git(repo, &["add", "-A"])?;
+    if !git(repo, &["status", "--porcelain"])?.trim().is_empty() {
+        git(repo, &["commit", "-q", "-m", message])?;
+        println!("committed local changes: {}", message);
+    }
+    if git(repo, &["remote", "get-url", "origin"]).is_err() {
+        println!("no remote, use sync -r to set it, only local changes are committed");
+        return Ok(())
+    }
+
+    // rebase on the default branch of the remote, or the local branch if the remote is empty
+    git(repo, &["fetch", "-q", "origin"])?;
+    let _ = git(repo, &["remote", "set-head", "origin", "--auto"]); // origin/HEAD is only set by clone
+    let branch = match git(repo, &["symbolic-ref", "--short", "refs/remotes/origin/HEAD"]) {
+        Ok(head) => head.trim().trim_start_matches("origin/").to_string(),
+        Err(_) => git(repo, &["symbolic-ref", "--short", "HEAD"])?.trim().to_string(),
+    };
+    let upstream = format!("origin/{}", branch);
+    if git(repo, &["rev-parse", "--verify", "-q", &upstream]).is_ok() {
+        if git(repo, &["rev-parse", "--verify", "-q", "HEAD"]).is_err() {
+            // nothing committed locally yet
+            git(repo, &["reset", "-q", "--hard", &upstream])?;
+        } else if git(repo, &["rebase", &upstream]).is_err() {
+            resolve_rebase(repo, prefer)?;
+        }
+    }
+    if git(repo, &["rev-parse", "--verify", "-q", "HEAD"]).is_ok() {
+        git(repo, &["push", "-q", "origin", &format!("HEAD:refs/heads/{}", branch)])?;
+    }
+    println!("synced {} with {}", repo.display(), git(repo, &["remote", "get-url", "origin"])?.trim());
+    Ok(())
+}
+
+/// merge the conflicting *.snippets and markdown snippet files of each replayed commit snippet by snippet, until the rebase is finished
+/// the rebase is aborted if any snippet or other file conflicts, the local commits are kept
+fn resolve_rebase(repo: &Path, prefer: Option<Prefer>) -> Result<(), MyError> {
+    loop {
+        let files: Vec<String> = git(repo, &["diff", "--name-only", "--diff-filter=U"])?.lines().map(|l| l.to_string()).collect();
+        if files.is_empty() {
+            git(repo, &["rebase", "--abort"])?;
+            return Err(MyError::ParaError{para: "rebase stopped without conflicting files, it is aborted".to_string()})
+        }
+        let mut conflicts: Vec<SnippetConflict> = Vec::new();
+        let mut others: Vec<String> = Vec::new(); // conflicting files which are not snippet files
+        let mut merged: Vec<(String, Option<String>)> = Vec::new();
+        for f in files {
+            // stage 1: common version, 2: remote (the branch rebased onto), 3: local commit being replayed, None: added or deleted on one side
+            let texts: Vec<Option<String>> = (1..=3).map(|n| git(repo, &["show", &format!(":{}:{}", n, f)]).ok()).collect();
+            let path = Path::new(&f);
+            let snippet_file = f.ends_with(".snippets") || (is_markdown(path) && (is_marked_markdown(path) || texts.iter().flatten().any(|t| has_snippet_front_matter(t))));
+            if !snippet_file {
+                others.push(f);
+                continue
+            }
+            let result = merge_file(&f, [texts[0].as_deref(), texts[1].as_deref(), texts[2].as_deref()], prefer);
+            let (text, mut c) = match result {
+                Ok(r) => r,
+                Err(e) => {
+                    git(repo, &["rebase", "--abort"])?;
+                    return Err(e)
+                },
+            };
+            conflicts.append(&mut c);
+            merged.push((f, text));
+        }
+        if !conflicts.is_empty() || !others.is_empty() {
+            git(repo, &["rebase", "--abort"])?;
+            print_conflicts(&conflicts, &others);
+            return Err(MyError::ParaError{para: format!("{} snippets and {} other files conflict, sync is aborted and local changes are kept, edit them or use sync --prefer local|remote", conflicts.len(), others.len())})
+        }
+        for (f, text) in &merged {
+            match text {
+                Some(text) => {
+                    write(repo.join(f), text).map_err(|e| MyError::WriteFileError{file: f.clone(), error: e})?;
+                    git(repo, &["add", f])?;
+                },
+                None => { git(repo, &["rm", "-q", f])?; },
+            }
+            println!("merged {} snippet by snippet", f);
+        }
+        // the merged commit may be empty, e.g. the same change was made on both sides
+        let result = if git(repo, &["diff", "--cached", "--quiet"]).is_ok() {
+            git(repo, &["rebase", "--skip"])
+        } else {
+            git(repo, &["rebase", "--continue"])
+        };
+        if result.is_ok() {
+            return Ok(())
+        }
+    }
+}
+
+/// three-way merge of the common, remote and local text of a snippet file (None: missing in this version)
+/// return the merged text (None: the file is deleted) and the snippets changed on both sides, the merged text is checked
+fn merge_file(file: &str, texts: [Option<&str>; 3], prefer: Option<Prefer>) -> Result<(Option<String>, Vec<SnippetConflict>), MyError> {
+    let mut versions: Vec<Option<FileVersion>> = Vec::new();
+    for (text, name) in texts.iter().zip(["common", "remote", "local"]) {
+        versions.push(text.map(|t| FileVersion::parse(file, t)).transpose().map_err(|e| MyError::ParaError{para: format!("{} ({} version): {}", file, name, e)})?);
+    }
+    let snippets = |n: usize| versions[n].as_ref().map(|v| v.snippets.clone()).unwrap_or_default();
+    let (snippets, conflicts) = merge_snippets(file, snippets(0), snippets(1), snippets(2), prefer);
+    if snippets.is_empty() && (versions[1].is_none() || versions[2].is_none()) {
+        return Ok((None, conflicts)) // deleted on one side, and no snippet is kept
+    }
+    // the text around the snippets is taken from the remote version
+    let Some(frame) = versions[1].as_ref().or(versions[2].as_ref()).or(versions[0].as_ref()) else {
+        return Ok((None, conflicts))
+    };
+    let text = format!("{}{}{}", frame.head, snippets.concat(), frame.tail);
+    let checked = if is_markdown(Path::new(file)) {
+        read_markdown_snippets(Path::new(file), &text).map(|_| ())
+    } else {
+        parse_snippets_text(&text).map(|_| ())
+    };
+    checked.map_err(|e| MyError::ParaError{para: format!("merged {} is invalid: {}", file, e)})?;
+    Ok((Some(text), conflicts))
+}
+
+/// key of a snippet in the three versions: id, or the default id of the discription
+fn snippet_key(s: &FileSnippet) -> String {
+    if !s.id.is_empty() {
+        s.id.clone()
+    } else if !s.discription.trim().is_empty() {
+        snippet_id(s.discription.trim())
+    } else {
+        format!("hide:{}", s.hide.join(","))
+    }
+}
+
+/// repeated keys get a #n suffix
+fn keyed(snippets: Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    snippets.into_iter().map(|(key, text)| {
+        let n = seen.entry(key.clone()).or_default();
+        *n += 1;
+        (if *n > 1 { format!("{}#{}", key, n) } else { key }, text)
+    }).collect()
+}
+
+/// three-way merge of the (key, text) snippets of one file, snippets are compared by text
+/// a snippet changed only on one side takes this change, snippets changed differently on both sides conflict unless prefer is set
+/// return the merged snippet texts and the conflicts
+fn merge_snippets(file: &str, base: Vec<(String, String)>, remote: Vec<(String, String)>, local: Vec<(String, String)>, prefer: Option<Prefer>) -> (Vec<String>, Vec<SnippetConflict>) {
+    let base: HashMap<String, String> = keyed(base).into_iter().collect();
+    let remote = keyed(remote);
+    let local = keyed(local);
+    // remote order, snippets added locally or deleted remotely follow the snippet before them in the local version
+    let mut keys: Vec<String> = remote.iter().map(|(k, _)| k.clone()).collect();
+    let mut previous: Option<&String> = None;
+    for (k, _) in &local {
+        if !keys.contains(k) {
+            let at = previous.and_then(|p| keys.iter().position(|x| x == p)).map(|p| p + 1).unwrap_or(0);
+            keys.insert(at, k.clone());
+        }
+        previous = Some(k);
+    }
+    let remote: HashMap<String, String> = remote.into_iter().collect();
+    let local: HashMap<String, String> = local.into_iter().collect();
+
+    let same = |a: Option<&String>, b: Option<&String>| a.map(|t| t.trim()) == b.map(|t| t.trim());
+    let mut merged: Vec<String> = Vec::new();
+    let mut conflicts: Vec<SnippetConflict> = Vec::new();
+    for key in keys {
+        let (b, r, l) = (base.get(&key), remote.get(&key), local.get(&key));
+        let keep = if same(r, l) || same(l, b) {
+            r
+        } else if same(r, b) {
+            l
+        } else {
+            match prefer {
+                Some(Prefer::Local) => l,
+                Some(Prefer::Remote) => r,
+                None => {
+                    let trimmed = |s: Option<&String>| s.map(|t| t.trim().to_string());
+                    conflicts.push(SnippetConflict{file: file.to_string(), id: key.clone(), local: trimmed(l), remote: trimmed(r)});
+                    r
+                },
+            }
+        };
+        if let Some(s) = keep {
+            merged.push(s.clone());
+        }
+    }
+    (merged, conflicts)
+}
+
+/// print the snippets changed on both sides, and the other conflicting files
+fn print_conflicts(conflicts: &[SnippetConflict], others: &[String]) {
+    let describe = |s: &Option<String>| s.clone().unwrap_or("(deleted)".to_string());
+    if !conflicts.is_empty() {
+        let mut builder = Builder::default();
+        builder.push_record(vec!["file", "id", "local", "remote"]);
+        for c in conflicts {
+            builder.push_record(vec![c.file.clone(), c.id.clone(), describe(&c.local), describe(&c.remote)]);
+        }
+        let mut table = builder.build();
+        table.with(Style::modern()); // table style: ascii, extended, markdown, re_structured_text, dots, psql, ascii_rounded, blank, empty, rounded, modern, sharp
+        println!("{}", table);
+    }
+    for f in others {
+        println!("Warning - {} conflicts, only *.snippets and markdown snippet files (*.snippets.md or starting with tags front matter) are merged snippet by snippet", f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippets(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items.iter().map(|(k, t)| (k.to_string(), t.to_string())).collect()
+    }
+
+    #[test]
+    fn changes_of_both_sides() {
+        let base = snippets(&[("a", "A"), ("b", "B"), ("d", "D")]);
+        let remote = snippets(&[("a", "A remote"), ("b", "B")]);                 // a changed, d deleted
+        let local = snippets(&[("a", "A"), ("c", "C"), ("b", "B local"), ("d", "D")]); // c added after a, b changed
+        let (merged, conflicts) = merge_snippets("f", base, remote, local, None);
+        assert_eq!(merged, vec!["A remote", "C", "B local"]);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn changed_on_both_sides() {
+        let base = snippets(&[("a", "A"), ("b", "B")]);
+        let remote = snippets(&[("a", "A remote")]);
+        let local = snippets(&[("a", "A local"), ("b", "B local")]); // b deleted remotely, changed locally
+        let (merged, conflicts) = merge_snippets("f", base.clone(), remote.clone(), local.clone(), None);
+        assert_eq!(merged, vec!["A remote"]);
+        assert_eq!(conflicts.iter().map(|c| (c.id.as_str(), c.local.as_deref(), c.remote.as_deref())).collect::<Vec<_>>(), vec![("a", Some("A local"), Some("A remote")), ("b", Some("B local"), None)]);
+        let (merged, conflicts) = merge_snippets("f", base.clone(), remote.clone(), local.clone(), Some(Prefer::Local));
+        assert_eq!((merged, conflicts.len()), (vec!["A local".to_string(), "B local".to_string()], 0));
+        let (merged, _) = merge_snippets("f", base, remote, local, Some(Prefer::Remote));
+        assert_eq!(merged, vec!["A remote"]);
+    }
+
+    #[test]
+    fn same_change_and_repeated_keys() {
+        let base = snippets(&[("a", "A"), ("a", "A2")]);
+        let remote = snippets(&[("a", "A"), ("a", "  A2 new\n")]);
+        let local = snippets(&[("a", "A"), ("a", "A2 new")]); // the same change, only whitespace differs
+        let (merged, conflicts) = merge_snippets("f", base, remote, local, None);
+        assert_eq!(merged, vec!["A", "  A2 new\n"]);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_snippets_file() {
+        let base = "// shared\n[\n    // ls\n    SingleSnippet(id: \"ls\", discription: \"list files\", content: \"ls\"),\n    (discription: \"disk usage\", content: \"du\")\n]\n";
+        let remote = "// shared\n[\n    // ls\n    SingleSnippet(id: \"ls\", discription: \"list files\", content: \"ls -l\"),\n    (discription: \"disk usage\", content: \"du\")\n]\n";
+        let local = "// shared\n[\n    // ls\n    SingleSnippet(id: \"ls\", discription: \"list files\", content: \"ls\"),\n    (discription: \"disk usage\", content: \"du -sh\", embedding: Some({MultilingualE5Small: [0.5]})), // kept\n]\n";
+        let (text, conflicts) = merge_file("a.snippets", [Some(base), Some(remote), Some(local)], None).unwrap();
+        assert!(conflicts.is_empty());
+        assert_eq!(text.unwrap(), "// shared\n[\n    // ls\n    SingleSnippet(id: \"ls\", discription: \"list files\", content: \"ls -l\"),\n    (discription: \"disk usage\", content: \"du -sh\", embedding: Some({MultilingualE5Small: [0.5]})), // kept\n]\n");
+        // deleted remotely and not changed locally
+        assert!(merge_file("a.snippets", [Some(base), None, Some(base)], None).unwrap().0.is_none());
+        assert!(merge_file("a.snippets", [Some(base), Some("[ (discription: "), Some(local)], None).is_err());
+    }
+
+    #[test]
+    fn merge_markdown_file() {
+        let base = "---\ntags: [shell]\n---\n# list files\n```\nls\n```\n# disk usage\n```\ndu\n```\n";
+        let remote = "---\ntags: [shell]\n---\n# list files\n```\nls -l\n```\n# disk usage\n```\ndu\n```\n";
+        let local = "---\ntags: [shell]\n---\n# list files\n```\nls\n```\n# free memory\n```\nfree -h\n```\n# disk usage\n```\ndu\n```";
+        let (text, conflicts) = merge_file("a.md", [Some(base), Some(remote), Some(local)], None).unwrap();
+        assert!(conflicts.is_empty());
+        assert_eq!(text.unwrap(), "---\ntags: [shell]\n---\n# list files\n```\nls -l\n```\n# free memory\n```\nfree -h\n```\n# disk usage\n```\ndu\n```\n");
+    }
+}
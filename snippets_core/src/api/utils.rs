@@ -1,9 +1,16 @@
-use std::env::current_exe;
-use std::fs::File;
+use std::collections::HashSet;
+use std::env::{self, current_exe};
+use std::fs::{File, read_to_string};
 use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 
-use crate::error::MyError;
+use glob::{MatchOptions, Pattern};
+use ignore::WalkBuilder;
+
+use crate::{
+    error::MyError,
+    markdown::has_snippet_front_matter,
+};
 
 /// save file
 /// https://users.rust-lang.org/t/write-to-normal-or-gzip-file-transparently/35561/2
@@ -13,47 +20,250 @@ pub fn my_writer(file: &Path) -> Result<Box<dyn Write>, MyError> {
     Ok(Box::new(BufWriter::new(created_file)))
 }
 
-/// get all *.snippets files from current path or binary file path
-pub fn get_snippet_files() -> Result<Vec<PathBuf>, MyError> {
-    let mut files: Vec<PathBuf> = Vec::new();
-    // search from current path
-    let current_path = Path::new("./");
-    files.extend(get_snippets(current_path));
+/// *.snippets file and the folders between the searched path and the file, folders become tags with --folder-tags
+pub type SnippetFile = (PathBuf, Vec<String>);
+
+/// where snippets are loaded from, in precedence order
+/// snippets of a source can override or hide snippets of lower sources by namespace:id
+#[derive(Clone, PartialEq, Eq)]
+pub enum SnippetSource {
+    Builtin,              // snippets in the binary file
+    Binary(SnippetFile),  // *.snippets in the binary file path
+    Team(SnippetFile),    // specified by --team or SNIPPETS_TEAM
+    User(SnippetFile),    // ~/.snippets file or ~/.snippets/**/*.snippets
+    Project(SnippetFile), // specified by -f or SNIPPETS_FILE, or *.snippets in the current path, .snippets file or .snippets/**/*.snippets in the current path and its parents
+}
+
+impl SnippetSource {
+    /// namespace of the snippets of this source, snippet id is namespace:id
+    pub fn namespace(&self) -> &'static str {
+        match self {
+            Self::Builtin    => "builtin",
+            Self::Binary(_)  => "binary",
+            Self::Team(_)    => "team",
+            Self::User(_)    => "user",
+            Self::Project(_) => "project",
+        }
+    }
+
+    /// higher precedence overrides lower
+    pub fn precedence(&self) -> usize {
+        match self {
+            Self::Builtin    => 0,
+            Self::Binary(_)  => 1,
+            Self::Team(_)    => 2,
+            Self::User(_)    => 3,
+            Self::Project(_) => 4,
+        }
+    }
+
+    /// snippets file and its folders, None for built-in snippets
+    fn file(&self) -> Option<&SnippetFile> {
+        match self {
+            Self::Binary(f) | Self::Team(f) | Self::User(f) | Self::Project(f) => Some(f),
+            Self::Builtin => None,
+        }
+    }
+
+    /// snippets file, None for built-in snippets
+    pub fn path(&self) -> Option<&Path> {
+        self.file().map(|(p, _)| p.as_path())
+    }
+
+    /// folders between the searched path and the file
+    pub fn folders(&self) -> &[String] {
+        self.file().map(|(_, f)| f.as_slice()).unwrap_or_default()
+    }
+
+    /// same source without folder tags, used without --folder-tags
+    pub fn without_folders(self) -> Self {
+        match self {
+            Self::Builtin         => Self::Builtin,
+            Self::Binary((p, _))  => Self::Binary((p, Vec::new())),
+            Self::Team((p, _))    => Self::Team((p, Vec::new())),
+            Self::User((p, _))    => Self::User((p, Vec::new())),
+            Self::Project((p, _)) => Self::Project((p, Vec::new())),
+        }
+    }
+}
+
+/// home directory of the current user
+pub fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).map(PathBuf::from)
+}
+
+/// cache path of the ANN index files, priority order: SNIPPETS_CACHE > XDG_CACHE_HOME/snippets > ~/.cache/snippets
+pub fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("SNIPPETS_CACHE").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir))
+    }
+    match env::var_os("XDG_CACHE_HOME").filter(|d| !d.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir).join("snippets")),
+        None => Some(home_dir()?.join(".cache").join("snippets")),
+    }
+}
+
+/// replace the leading ~ with the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~"), home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with(['/', '\\']) => home.join(rest.trim_start_matches(['/', '\\'])),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// all snippet sources in precedence order: the snippets in the binary file, *.snippets in the binary file path, team files, ~/.snippets,
+/// then the files of -f, or the project files found like git finds its repository:
+/// *.snippets in the current path, .snippets file or .snippets/**/*.snippets in the current path and each parent path, nearest first
+pub fn find_snippet_sources(files: &[SnippetFile], team: &[SnippetFile]) -> Result<Vec<SnippetSource>, MyError> {
+    let mut sources: Vec<SnippetSource> = vec![SnippetSource::Builtin];
     // search from binary file path
+    if let Ok(mut binary_path) = current_exe() {
+        if binary_path.pop() { // Truncates binary_path to parent
+            sources.extend(get_snippets(&binary_path).into_iter().map(SnippetSource::Binary));
+        }
+    }
+    sources.extend(team.iter().cloned().map(SnippetSource::Team));
+    // search from home path
+    let home = home_dir();
+    if let Some(h) = &home {
+        sources.extend(get_dot_snippets(h).into_iter().map(SnippetSource::User));
+    }
     if files.is_empty() {
-        if let Ok(mut binary_path) = current_exe() {
-            if binary_path.pop() { // Truncates binary_path to parent
-                files.extend(get_snippets(&binary_path));
-            }
+        // search from current path and its parents, the home path is the user source
+        let current_path = env::current_dir().map_err(|e| MyError::ReadDirError{dir: ".".to_string(), error: e})?;
+        sources.extend(project_snippets(&current_path, home.as_deref()).into_iter().map(SnippetSource::Project));
+    } else {
+        sources.extend(files.iter().cloned().map(SnippetSource::Project));
+    }
+    // the same file may be found twice, e.g. the binary file is in the current path, keep the higher source
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    sources.reverse();
+    sources.retain(|s| s.path().map(|p| seen.insert(p.canonicalize().unwrap_or(p.to_path_buf()))).unwrap_or(true));
+    sources.reverse();
+    Ok(sources)
+}
+
+/// project files found like git finds its repository, nearest first
+/// *.snippets in the current path, then .snippets file or .snippets/**/*.snippets in the current path and each parent path except the home path
+fn project_snippets(current_path: &Path, home: Option<&Path>) -> Vec<SnippetFile> {
+    let mut project = get_snippets(current_path);
+    for dir in current_path.ancestors() {
+        if home != Some(dir) {
+            project.extend(get_dot_snippets(dir));
         }
     }
-    Ok(files)
+    project
 }
 
-/// get all *.snippets from path
-fn get_snippets(inpath: &Path) -> Vec<PathBuf> {
-    let mut files: Vec<PathBuf> = Vec::new();
+/// .snippets file or all *.snippets under .snippets directory of path
+fn get_dot_snippets(inpath: &Path) -> Vec<SnippetFile> {
+    let dot = inpath.join(".snippets");
+    if dot.is_file() {
+        vec![(dot, Vec::new())]
+    } else if dot.is_dir() {
+        walk_snippets(&dot)
+    } else {
+        Vec::new()
+    }
+}
+
+/// get all *.snippets from path, subdirectories are not searched
+fn get_snippets(inpath: &Path) -> Vec<SnippetFile> {
+    let mut files: Vec<SnippetFile> = Vec::new();
     if let Ok(dirs) = inpath.read_dir() {
-        for i in dirs {
-            if let Ok(entry) = i {
-                let tmp_path = entry.path();
-                if tmp_path.is_file() {
-                    if let Some(ext) = tmp_path.extension() {
-                        if ext == "snippets" {
-                            files.push(tmp_path);
-                        }
+        for entry in dirs.flatten() {
+            let tmp_path = entry.path();
+            if tmp_path.is_file() {
+                if let Some(ext) = tmp_path.extension() {
+                    if ext == "snippets" {
+                        files.push((tmp_path, Vec::new()));
                     }
                 }
             }
         }
     }
+    files.sort();
     files
 }
 
+/// all *.snippets and markdown snippet files under dir and its subdirectories
+/// other markdown files (e.g. README.md or notes) are skipped, see is_snippet_markdown
+/// files and folders matched by .snippetsignore (gitignore syntax) in dir, its subdirectories or its parents are skipped
+pub fn walk_snippets(dir: &Path) -> Vec<SnippetFile> {
+    let mut files: Vec<SnippetFile> = Vec::new();
+    let walker = WalkBuilder::new(dir)
+        .standard_filters(false) // hidden folders, .gitignore and .ignore are not skipped, only .snippetsignore
+        .add_custom_ignore_filename(".snippetsignore")
+        .filter_entry(|e| e.file_name() != ".git") // e.g. the repository of sync
+        .build();
+    for entry in walker.flatten() {
+        let path = entry.path();
+        if path.is_file() && (path.extension().is_some_and(|e| e == "snippets") || is_snippet_markdown(path)) {
+            files.push((path.to_path_buf(), folders_between(dir, path)));
+        }
+    }
+    files.sort();
+    files
+}
+
+/// files matching the glob pattern, e.g. ~/notes/**/*.snippets, the path before the first wildcard is searched like walk_snippets
+/// folders are counted from this path
+pub fn glob_snippets(pattern: &str) -> Result<Vec<SnippetFile>, MyError> {
+    let pattern = expand_home(pattern);
+    let base: PathBuf = pattern.components().take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '['])).collect();
+    let matcher = Pattern::new(&pattern.to_string_lossy()).map_err(|e| MyError::ParaError{para: format!("invalid glob pattern {}: {}", pattern.display(), e)})?;
+    let options = MatchOptions { require_literal_separator: true, ..Default::default() }; // * does not match /, ** matches any folders
+    let walker = WalkBuilder::new(&base)
+        .standard_filters(false)
+        .add_custom_ignore_filename(".snippetsignore")
+        .build();
+    let mut files: Vec<SnippetFile> = Vec::new();
+    for entry in walker.flatten() {
+        let path = entry.path();
+        if path.is_file() && matcher.matches_path_with(path, options) {
+            files.push((path.to_path_buf(), folders_between(&base, path)));
+        }
+    }
+    if files.is_empty() {
+        return Err(MyError::ParaError{para: format!("no file matches {}", pattern.display())})
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// markdown snippet file (*.md or *.markdown), other files are RON
+pub fn is_markdown(file: &Path) -> bool {
+    file.extension().is_some_and(|e| e == "md" || e == "markdown")
+}
+
+/// markdown file found in a path is loaded only if it is named *.snippets.md (or *.snippets.markdown), or starts with snippet front matter
+/// markdown files given explicitly with -f are always loaded
+pub fn is_snippet_markdown(file: &Path) -> bool {
+    is_markdown(file) && (is_marked_markdown(file) || read_to_string(file).is_ok_and(|text| has_snippet_front_matter(&text)))
+}
+
+/// markdown file named *.snippets.md or *.snippets.markdown
+pub fn is_marked_markdown(file: &Path) -> bool {
+    is_markdown(file) && file.file_stem().is_some_and(|s| Path::new(s).extension().is_some_and(|e| e == "snippets"))
+}
+
+/// SQLite snippet database (*.db, *.sqlite or *.sqlite3), only specified files are loaded, paths are not searched for them
+pub fn is_sqlite(file: &Path) -> bool {
+    file.extension().is_some_and(|e| e == "db" || e == "sqlite" || e == "sqlite3")
+}
+
+/// names of the folders between base and the file
+fn folders_between(base: &Path, file: &Path) -> Vec<String> {
+    file.parent()
+        .and_then(|p| p.strip_prefix(base).ok())
+        .map(|rel| rel.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect())
+        .unwrap_or_default()
+}
+
 /// Computes the cosine similarity between two tone Tensor
 /// https://en.wikipedia.org/wiki/Cosine_similarity
 /// https://github.com/gaspiman/cosine_similarity/blob/master/cosine.go
-pub fn cosine_similarity(vec_a: &Vec<f32>, vec_b: &Vec<f32>) -> Result<f32, MyError> {
+pub fn cosine_similarity(vec_a: &[f32], vec_b: &[f32]) -> Result<f32, MyError> {
     let mut ab: f32 = 0.0;
     let mut sum_a: f32 = 0.0;
     let mut sum_b: f32 = 0.0;
@@ -63,6 +273,115 @@ pub fn cosine_similarity(vec_a: &Vec<f32>, vec_b: &Vec<f32>) -> Result<f32, MyEr
         sum_b += j.powf(2.0);
     }
 
+    // zero vector (e.g. text with only unknown tokens for static models) is not similar to anything
+    if sum_a == 0.0 || sum_b == 0.0 {
+        return Ok(0.0)
+    }
     Ok(ab / (sum_a.sqrt() * sum_b.sqrt()))
 }
 
+
+/// split text to character trigrams, ignore case and whitespace differences
+/// used as text similarity when no embedding model is selected
+pub fn text_trigrams(text: &str) -> HashSet<String> {
+    let chars: Vec<char> = text.to_lowercase().split_whitespace().collect::<Vec<_>>().join(" ").chars().collect();
+    if chars.len() < 3 {
+        return HashSet::from([chars.iter().collect::<String>()])
+    }
+    chars.windows(3).map(|w| w.iter().collect::<String>()).collect()
+}
+
+/// Computes the jaccard similarity between two trigram sets
+/// https://en.wikipedia.org/wiki/Jaccard_index
+pub fn trigram_similarity(set_a: &HashSet<String>, set_b: &HashSet<String>) -> f32 {
+    let intersection = set_a.intersection(set_b).count();
+    let union = set_a.len() + set_b.len() - intersection;
+    if union == 0 {
+        return 1.0
+    }
+    intersection as f32 / union as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// empty temporary folder of the test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("snippets-utils-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// create the files under dir, parent folders included
+    fn touch(dir: &Path, files: &[&str]) {
+        for f in files {
+            let file = dir.join(f);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, "[]").unwrap();
+        }
+    }
+
+    #[test]
+    fn project_snippets_in_parents() {
+        let root = temp_dir("project");
+        touch(&root, &["home/.snippets", "home/repo/.snippets", "home/repo/src/.snippets/b/y.snippets", "home/repo/src/.snippets/x.snippets", "home/repo/src/z.snippets", "home/repo/src/.snippets/notes.md", "home/repo/src/lib/w.snippets"]);
+        let current = root.join("home/repo/src");
+        let found = project_snippets(&current, Some(&root.join("home")));
+        assert!(found == vec![
+            (current.join("z.snippets"), vec![]),                            // current path, subfolders are not searched
+            (current.join(".snippets/b/y.snippets"), vec!["b".to_string()]), // nearest .snippets folder first
+            (current.join(".snippets/x.snippets"), vec![]),
+            (root.join("home/repo/.snippets"), vec![]),                      // parent .snippets file, home is the user source
+        ]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn walk_with_snippetsignore() {
+        let dir = temp_dir("walk");
+        touch(&dir, &["a.snippets", "docker/b.snippets", "docker/compose/c.snippets", "git/d.snippets.md", "git/README.md", "drafts/e.snippets", "docker/old.snippets", ".git/f.snippets", ".hidden/g.snippets"]);
+        std::fs::write(dir.join(".snippetsignore"), "drafts/\n").unwrap();
+        std::fs::write(dir.join("docker/.snippetsignore"), "old.snippets\n").unwrap();
+        let folders = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert!(walk_snippets(&dir) == vec![
+            (dir.join(".hidden/g.snippets"), folders(&[".hidden"])),                // hidden folders are searched
+            (dir.join("a.snippets"), vec![]),
+            (dir.join("docker/b.snippets"), folders(&["docker"])),                  // folder names become tags
+            (dir.join("docker/compose/c.snippets"), folders(&["docker", "compose"])),
+            (dir.join("git/d.snippets.md"), folders(&["git"])),                     // README.md is not a snippet file
+        ]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn glob_from_base() {
+        let dir = temp_dir("glob");
+        touch(&dir, &["a.snippets", "docker/b.snippets", "docker/compose/c.snippets", "docker/notes.txt"]);
+        let glob = |pattern: &str| glob_snippets(&dir.join(pattern).to_string_lossy());
+        assert!(glob("*.snippets").unwrap() == vec![(dir.join("a.snippets"), vec![])]); // * does not match /
+        assert!(glob("docker/*").unwrap() == vec![                                       // folders are counted from the base
+            (dir.join("docker/b.snippets"), vec![]),
+            (dir.join("docker/notes.txt"), vec![]),
+        ]);
+        assert!(glob("**/*.snippets").unwrap() == vec![
+            (dir.join("a.snippets"), vec![]),
+            (dir.join("docker/b.snippets"), vec!["docker".to_string()]),
+            (dir.join("docker/compose/c.snippets"), vec!["docker".to_string(), "compose".to_string()]),
+        ]);
+        assert!(glob("*.md").unwrap_err().to_string().contains("no file matches"));
+        assert!(glob("[*.snippets").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cosine_of_zero_vectors() {
+        // unknown tokens of static models embed to the zero vector, it is not similar to anything
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[0.6, 0.8]).unwrap(), 0.0);
+        assert_eq!(cosine_similarity(&[0.6, 0.8], &[0.0, 0.0]).unwrap(), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[0.0, 0.0]).unwrap(), 0.0);
+        assert!((cosine_similarity(&[3.0, 4.0], &[0.6, 0.8]).unwrap() - 1.0).abs() < 1e-6);
+        assert!((cosine_similarity(&[1.0, 0.0], &[-2.0, 0.0]).unwrap() + 1.0).abs() < 1e-6);
+    }
+}
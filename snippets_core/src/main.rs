@@ -1,6 +1,10 @@
 use snippets::{
-    parse_paras::parse_para,
+    parse_paras::{
+        parse_para,
+        Command,
+    },
     snip::Snippets,
+    sync::sync,
     SnipTag,
     error::MyError,
 };
@@ -18,12 +22,36 @@ fn run() -> Result<(), MyError> {
     if paras.show_tags {
         println!("supported tags: {}", SnipTag::supported_tags());
         Ok(())
+    } else if let Some(Command::Sync{repo, remote, message, prefer}) = &paras.command {
+        // sync before loading, the repository may not exist yet
+        sync(repo, remote.as_deref(), message, *prefer)
     } else {
         // init SnipType
-        let my_snippets = Snippets::new(&paras.files, paras.model.clone())?;
+        let mut my_snippets = Snippets::new(&paras.sources, paras.model.clone())?;
+        if paras.verbose {
+            my_snippets.print_sources();
+        }
+        my_snippets.set_search_options(paras.options.clone());
+        if let Some(rerank) = &paras.rerank {
+            my_snippets.set_reranker(rerank.clone());
+        }
 
         // run
-        if paras.summary.is_empty() {
+        if let Some(command) = &paras.command {
+            match command {
+                Command::Dedupe{threshold, merge} => my_snippets.dedupe(*threshold, *merge),
+                Command::SuggestTags{target, neighbours} => my_snippets.suggest_tags(target, *neighbours),
+                Command::Related{id, num} => my_snippets.related(*id, *num, &paras),
+                Command::QuantizeReport{neighbours, queries} => my_snippets.quantize_report(*neighbours, *queries),
+                Command::Eval{queries, k, models} => my_snippets.eval(queries, *k, models),
+                Command::Shell{model_path, use_cpu, dtype, options} => my_snippets.shell(model_path, *use_cpu, *dtype, options, &paras.outpath),
+                #[cfg(feature = "sqlite")]
+                Command::Migrate{db, files} => my_snippets.migrate(db, files),
+                #[cfg(not(feature = "sqlite"))]
+                Command::Migrate{..} => Err(MyError::ParaError{para: "migrate is only valid for sqlite feature".to_string()}),
+                Command::Sync{..} => unreachable!(), // synced before loading
+            }
+        } else if paras.summary.is_empty() {
             my_snippets.get(paras)
         } else {
             my_snippets.print_summary(&paras.summary)